@@ -1,53 +1,1038 @@
-use crate::core::types::AccountId;
-use crate::server::ServerResponse;
-use bincode::{deserialize, serialize};
-use clap::Subcommand;
-use log::{debug, error, info};
+use crate::core::address::to_address;
+use crate::core::admin::{admin_message, AccountStatus, AdminAction};
+use crate::core::constants::{DEFAULT_PROPOSAL_TTL, NATIVE_ASSET, NATIVE_SYMBOL};
+use crate::core::dump::{write_blocks, DumpFormat};
+use crate::core::escrow::{refund_message, release_message, EscrowState};
+use crate::core::keys::{generate_secret_key, parse_key, public_key, sign, PublicKey, SecretKey};
+use crate::core::multisig::sign_approval;
+use crate::core::names::{validate_name, AccountRef};
+use crate::core::types::{
+    AccountId, AssetId, Block, BlockId, Blocks, Hash, Id, Transaction, TransactionId,
+    TransactionKind,
+};
+use crate::core::utilities::{asset_id, asset_symbol, parse_hash, to_hex};
+use crate::core::validation::{parse_amount, parse_balance};
+use crate::core::vm::{assemble, Program, Word, DEFAULT_GAS_LIMIT};
+use crate::server::{Answer, NodeEvent, ServerResponse};
+use bincode::{deserialize_from, serialize_into};
+use clap::{Args, Subcommand};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
+use std::fs;
+use std::io::BufWriter;
 use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Signature of an admin command by the node operator key.
+#[derive(Debug, Clone, Default, Args, Serialize, Deserialize)]
+pub struct AdminAuth {
+    /// File holding the node operator's secret key, as written by `b keygen`
+    #[clap(long)]
+    #[serde(skip)]
+    pub key: PathBuf,
+    /// Time of signing (ms since the UNIX epoch)
+    #[clap(skip)]
+    pub timestamp: u64,
+    #[clap(skip)]
+    pub signature: Vec<u8>,
+}
+
+impl AdminAuth {
+    fn sign(self, action: AdminAction, account: AccountId) -> Result<AdminAuth, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let signature = sign(
+            &read_key(&self.key)?,
+            &admin_message(action, account, timestamp),
+        );
+        Ok(AdminAuth {
+            timestamp,
+            signature,
+            ..self
+        })
+    }
+}
 
 #[derive(Debug, Clone, Subcommand, Serialize, Deserialize)]
 pub enum ClientCommands {
+    /// Creates an account under a new address chosen by the node
     CreateAccount {
-        account: AccountId,
+        #[clap(value_parser = parse_balance)]
         starting_balance: f64,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
     },
     Transfer {
-        from_account: AccountId,
-        to_account: AccountId,
+        from_account: AccountRef,
+        to_account: AccountRef,
+        #[clap(value_parser = parse_amount)]
+        amount: f64,
+        /// Symbol of the asset to transfer
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+        /// Lock the amount for the recipient until the chain reaches this block id
+        #[clap(long, conflicts_with = "unlock_time")]
+        unlock_height: Option<BlockId>,
+        /// Lock the amount for the recipient until this time (seconds since the UNIX epoch)
+        #[clap(long)]
+        unlock_time: Option<u64>,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Makes the node submit a transfer every few blocks, starting with the next block
+    ScheduleTransfer {
+        from_account: AccountRef,
+        to_account: AccountRef,
+        #[clap(value_parser = parse_amount)]
         amount: f64,
+        /// Symbol of the asset to transfer
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+        /// Number of blocks between transfers
+        #[clap(long)]
+        every: u64,
+        /// Number of transfers to make (runs until cancelled if omitted)
+        #[clap(long)]
+        count: Option<u64>,
+    },
+    CancelSchedule {
+        schedule_id: Id,
+    },
+    /// Creates an account whose transfers need M-of-N approvals
+    CreateMultisig {
+        /// Number of approvals a transfer needs
+        #[clap(long)]
+        threshold: usize,
+        /// Public key (hex) of a signer, as printed by `b keygen` (repeatable)
+        #[clap(long = "signer", value_parser = parse_key, required = true)]
+        signers: Vec<PublicKey>,
+    },
+    /// Proposes a transfer out of a multisig account
+    ProposeTransfer {
+        from_account: AccountRef,
+        to_account: AccountRef,
+        #[clap(value_parser = parse_amount)]
+        amount: f64,
+        /// Symbol of the asset to transfer
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+        /// Number of blocks the proposal can be approved for
+        #[clap(long, default_value_t = DEFAULT_PROPOSAL_TTL)]
+        expires_in: u64,
+    },
+    /// Signs a proposal; the transfer is made once enough signers approved it
+    Approve {
+        proposal_id: Id,
+        /// Recipient of the proposed transfer, which the approval covers
+        to_account: AccountRef,
+        /// Amount of the proposed transfer, which the approval covers
+        #[clap(value_parser = parse_amount)]
+        amount: f64,
+        /// Symbol of the asset of the proposed transfer
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+        /// File holding the signer's secret key, as written by `b keygen`
+        #[clap(long)]
+        #[serde(skip)]
+        key: PathBuf,
+        #[clap(skip)]
+        signer: PublicKey,
+        #[clap(skip)]
+        signature: Vec<u8>,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Mints the whole supply of a new asset to an existing account
+    IssueAsset {
+        #[clap(value_name = "SYMBOL", value_parser = asset_id)]
+        asset: AssetId,
+        #[clap(value_parser = parse_amount)]
+        supply: f64,
+        /// Account receiving the issued supply
+        #[clap(long)]
+        issuer: AccountRef,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
     },
     Balance {
-        account: AccountId,
+        account: AccountRef,
+        /// Symbol of the asset to report
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+    },
+    TransactionStatus {
+        transaction_id: TransactionId,
+    },
+    /// Locks funds in escrow until they are released to the payee or refunded to the payer
+    OpenEscrow {
+        payer: AccountRef,
+        payee: AccountRef,
+        #[clap(value_parser = parse_amount)]
+        amount: f64,
+        /// Symbol of the asset to escrow
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+        /// Public key (hex) of an arbiter who can release or refund the escrow at any time
+        #[clap(long, value_parser = parse_key)]
+        arbiter: Option<PublicKey>,
+        /// SHA-256 (hex) of a secret whose reveal releases the escrow before the timeout
+        #[clap(long, value_parser = parse_hash)]
+        hashlock: Option<Hash>,
+        /// Number of blocks after which the payer can take the funds back
+        #[clap(long)]
+        timeout: u64,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Pays an escrow out to the payee
+    ReleaseEscrow {
+        escrow_id: TransactionId,
+        /// Secret matching the hashlock of the escrow
+        #[clap(long)]
+        preimage: Option<String>,
+        /// File holding the arbiter's secret key, as written by `b keygen`
+        #[clap(long)]
+        #[serde(skip)]
+        key: Option<PathBuf>,
+        #[clap(skip)]
+        signature: Option<Vec<u8>>,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Returns an escrow to the payer (after the timeout, or any time by the arbiter)
+    RefundEscrow {
+        escrow_id: TransactionId,
+        /// File holding the arbiter's secret key, as written by `b keygen`
+        #[clap(long)]
+        #[serde(skip)]
+        key: Option<PathBuf>,
+        #[clap(skip)]
+        signature: Option<Vec<u8>>,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    EscrowStatus {
+        escrow_id: TransactionId,
+    },
+    /// Stops an account from sending funds (admin only)
+    FreezeAccount {
+        account: AccountRef,
+        #[clap(flatten)]
+        auth: AdminAuth,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Lets a frozen account send funds again (admin only)
+    UnfreezeAccount {
+        account: AccountRef,
+        #[clap(flatten)]
+        auth: AdminAuth,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Sweeps every balance of an account to another account and closes it for good (admin only)
+    CloseAccount {
+        account: AccountRef,
+        /// Account receiving the remaining balances
+        #[clap(long)]
+        sweep_to: AccountRef,
+        #[clap(flatten)]
+        auth: AdminAuth,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Registers a name that other commands accept in place of the account id
+    RegisterName {
+        account: AccountRef,
+        #[clap(value_parser = parse_name)]
+        name: String,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Hands a registered name over to another account
+    TransferName {
+        #[clap(value_parser = parse_name)]
+        name: String,
+        to_account: AccountRef,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Shows whether an account exists, when it was created and its pending state
+    AccountInfo {
+        account: AccountRef,
+    },
+    /// Lists the transactions to or from an account in the canonical chain
+    History {
+        account: AccountRef,
+        /// First block to list transactions from
+        #[clap(long, default_value_t = 0)]
+        from: BlockId,
     },
+    /// Shows a transaction of the canonical chain or the mempool
+    GetTx {
+        transaction_id: TransactionId,
+    },
+    /// Shows the account a name belongs to, or the names of an account
+    Resolve {
+        account: AccountRef,
+    },
+    /// Deploys a contract written in the VM's assembly language
+    DeployContract {
+        from_account: AccountRef,
+        /// File holding the contract source
+        #[serde(skip)]
+        path: PathBuf,
+        #[clap(skip)]
+        code: Program,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Calls a contract; the call runs when the next block is sealed
+    CallContract {
+        from_account: AccountRef,
+        contract: AccountRef,
+        /// Integer arguments passed to the contract
+        #[clap(allow_negative_numbers = true)]
+        args: Vec<Word>,
+        /// Most gas the call may use
+        #[clap(long = "gas", default_value_t = DEFAULT_GAS_LIMIT)]
+        gas_limit: u64,
+        /// Wait until the transaction has this many confirmations
+        #[clap(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Shows the stored key/value pairs of a contract
+    ContractStorage {
+        contract: AccountRef,
+    },
+    ChainInfo,
+    /// Prints new blocks, pending transactions and reorganizations as they happen
+    #[clap(name = "watch")]
+    Subscribe {
+        /// Only print the transactions of this account, pending and sealed
+        #[clap(long)]
+        account: Option<AccountRef>,
+    },
+    /// Reports minted, circulating and burned amounts
+    Supply {
+        /// Symbol of the asset to report
+        #[clap(long, value_parser = asset_id, default_value = NATIVE_SYMBOL)]
+        asset: AssetId,
+    },
+    /// Writes blocks of the canonical chain to a file that `b import` can load
+    Export {
+        /// File to write the dump to
+        #[serde(skip)]
+        path: PathBuf,
+        /// Encoding of the dump
+        #[clap(long, value_enum, default_value = "jsonl")]
+        #[serde(skip)]
+        format: DumpFormat,
+        /// First block to export
+        #[clap(long, default_value_t = 0)]
+        from: BlockId,
+        /// Last block to export (the head if omitted)
+        #[clap(long)]
+        to: Option<BlockId>,
+    },
+    /// Sent by a peer node when it seals or relays a block
+    #[clap(skip)]
+    SubmitBlock {
+        block: Block,
+        origin: u16,
+        genesis: Hash,
+    },
+    /// Sent by a peer node to fetch the canonical chain starting at a block id
+    #[clap(skip)]
+    GetBlocks {
+        from: BlockId,
+        genesis: Hash,
+    },
+    /// Sent by a new peer node to bootstrap from the latest snapshot instead
+    /// of replaying the whole chain
+    #[clap(skip)]
+    GetSnapshot {
+        genesis: Hash,
+    },
+}
+
+/// Connection to a node started with `b start-node`. Commands go out and
+/// answers come back bincode-encoded; one connection carries any number of
+/// commands, one at a time.
+pub struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn connect(port: u16) -> Result<Client, String> {
+        TcpStream::connect(format!("127.0.0.1:{}", port))
+            .map(|stream| Client { stream })
+            .map_err(|e| format!("could not connect to the node: {}", e))
+    }
+
+    /// Sends a command and returns the node's answer. Reorganizations the node
+    /// reports while the command waits for confirmations go to `on_reorg` with
+    /// the number of orphaned blocks and the new head block id.
+    pub fn request_with(
+        &mut self,
+        command: &ClientCommands,
+        on_reorg: impl FnMut(u64, BlockId),
+    ) -> Result<ServerResponse, String> {
+        self.exchange(command, on_reorg)?
+    }
+
+    /// Like `request_with`, but an outer error means the connection failed
+    /// and an inner one that the node refused the command.
+    pub(crate) fn exchange(
+        &mut self,
+        command: &ClientCommands,
+        mut on_reorg: impl FnMut(u64, BlockId),
+    ) -> Answer {
+        serialize_into(&mut self.stream, command).map_err(|e| e.to_string())?;
+        loop {
+            let response: Result<ServerResponse, String> =
+                deserialize_from(&mut self.stream).map_err(|e| e.to_string())?;
+            match response {
+                Ok(ServerResponse::Reorganized {
+                    orphaned_blocks,
+                    head_block_id,
+                }) => on_reorg(orphaned_blocks, head_block_id),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    pub fn request(&mut self, command: &ClientCommands) -> Result<ServerResponse, String> {
+        self.request_with(command, |_, _| {})
+    }
+
+    /// Turns the connection into a stream of the node's events, only those of
+    /// `account` if given.
+    pub fn subscribe(mut self, account: Option<AccountRef>) -> Result<Events, String> {
+        serialize_into(&mut self.stream, &ClientCommands::Subscribe { account })
+            .map_err(|e| e.to_string())?;
+        Ok(Events {
+            stream: Some(self.stream),
+        })
+    }
+
+    /// Creates an account and returns its id once the mint is sealed.
+    pub fn create_account(&mut self, starting_balance: f64) -> Result<AccountId, String> {
+        match self.request(&ClientCommands::CreateAccount {
+            starting_balance,
+            confirmations: 1,
+        })? {
+            ServerResponse::AccountCreated { account, .. } => Ok(account),
+            response => Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+
+    /// Transfers native funds and returns the transaction id once it is sealed.
+    pub fn transfer(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        amount: f64,
+    ) -> Result<TransactionId, String> {
+        match self.request(&ClientCommands::Transfer {
+            from_account: AccountRef::Id(from),
+            to_account: AccountRef::Id(to),
+            amount,
+            asset: NATIVE_ASSET,
+            unlock_height: None,
+            unlock_time: None,
+            confirmations: 1,
+        })? {
+            ServerResponse::Transferred { transaction_id, .. } => Ok(transaction_id),
+            response => Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+
+    /// Native balance of an account over the sealed blocks; pending
+    /// transactions are not counted until they are sealed.
+    pub fn balance(&mut self, account: AccountId) -> Result<f64, String> {
+        match self.request(&ClientCommands::Balance {
+            account: AccountRef::Id(account),
+            asset: NATIVE_ASSET,
+        })? {
+            ServerResponse::Balance { balance, .. } => Ok(balance),
+            response => Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+}
+
+/// Events streamed by a node after `Client::subscribe`; ends after the first error.
+pub struct Events {
+    stream: Option<TcpStream>,
+}
+
+impl Iterator for Events {
+    type Item = Result<NodeEvent, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let response: bincode::Result<Result<ServerResponse, String>> =
+            deserialize_from(self.stream.as_mut()?);
+        let error = match response {
+            Ok(Ok(ServerResponse::Event(event))) => return Some(Ok(event)),
+            Ok(Ok(response)) => format!("unexpected response: {:?}", response),
+            Ok(Err(e)) => e,
+            Err(e) => format!("lost the connection to the node: {}", e),
+        };
+        self.stream = None;
+        Some(Err(error))
+    }
+}
+
+/// Writes a new secret key to `path` and prints its public key.
+pub fn generate_key(path: &Path) {
+    if path.exists() {
+        error!("Refusing to overwrite existing file {}", path.display());
+        return;
+    }
+    let secret = generate_secret_key();
+    match fs::write(path, to_hex(&secret)) {
+        Ok(()) => info!(
+            "secret key written to {}\n\tpublic key: {}",
+            path.display(),
+            to_hex(&public_key(&secret))
+        ),
+        Err(e) => error!("could not write key file {}: {}", path.display(), e),
+    }
+}
+
+fn write_dump(path: &Path, format: DumpFormat, blocks: &Blocks) -> Result<(), String> {
+    let mut file = BufWriter::new(
+        fs::File::create(path)
+            .map_err(|e| format!("could not create {}: {}", path.display(), e))?,
+    );
+    write_blocks(&mut file, format, blocks)
+}
+
+fn describe_transaction(transaction: &Transaction) -> String {
+    let kind = match transaction.kind() {
+        TransactionKind::Transfer => "transfer".to_string(),
+        TransactionKind::Fee { transaction } => format!("fee for {}", transaction),
+        TransactionKind::EscrowOpen(_) => "open-escrow".to_string(),
+        TransactionKind::EscrowRelease { escrow, .. } => format!("release-escrow {}", escrow),
+        TransactionKind::EscrowRefund { escrow, .. } => format!("refund-escrow {}", escrow),
+        TransactionKind::Deploy { .. } => "deploy-contract".to_string(),
+        TransactionKind::Call { args, .. } => format!("call-contract {:?}", args),
+        TransactionKind::RegisterName { name } => format!("register-name {}", name),
+        TransactionKind::TransferName { name } => format!("transfer-name {}", name),
+        TransactionKind::Admin { action, .. } => format!("admin {:?}", action),
+    };
+    format!(
+        "{}: {} -> {}, {} {} ({})",
+        transaction.id,
+        to_address(transaction.from()),
+        to_address(transaction.to()),
+        transaction.amount(),
+        asset_symbol(transaction.asset()),
+        kind
+    )
+}
+
+fn read_key(path: &Path) -> Result<SecretKey, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("could not read key file {}: {}", path.display(), e))?;
+    parse_key(&contents)
+}
+
+fn parse_name(name: &str) -> Result<String, String> {
+    validate_name(name).map(|()| name.to_string())
+}
+
+/// Looks up the id behind a name on the node over `client`; admin and
+/// approval signatures cover ids, not names.
+fn resolve_account(account: AccountRef, client: &mut Client) -> Result<AccountId, String> {
+    let name = match account {
+        AccountRef::Id(id) => return Ok(id),
+        AccountRef::Name(name) => name,
+    };
+    match client.request(&ClientCommands::Resolve {
+        account: AccountRef::Name(name),
+    })? {
+        ServerResponse::Resolved { account, .. } => Ok(account),
+        response => Err(format!("unexpected response: {:?}", response)),
+    }
+}
+
+/// Signs commands that need a signature with the key file given on the
+/// command line and assembles contract source files. Names that a signature
+/// covers are resolved over `client`.
+pub(crate) fn prepare_command(
+    command: ClientCommands,
+    client: &mut Client,
+) -> Result<ClientCommands, String> {
+    match command {
+        ClientCommands::FreezeAccount {
+            account,
+            auth,
+            confirmations,
+        } => {
+            let account = resolve_account(account, client)?;
+            Ok(ClientCommands::FreezeAccount {
+                account: AccountRef::Id(account),
+                auth: auth.sign(AdminAction::Freeze, account)?,
+                confirmations,
+            })
+        }
+        ClientCommands::UnfreezeAccount {
+            account,
+            auth,
+            confirmations,
+        } => {
+            let account = resolve_account(account, client)?;
+            Ok(ClientCommands::UnfreezeAccount {
+                account: AccountRef::Id(account),
+                auth: auth.sign(AdminAction::Unfreeze, account)?,
+                confirmations,
+            })
+        }
+        ClientCommands::CloseAccount {
+            account,
+            sweep_to,
+            auth,
+            confirmations,
+        } => {
+            let (account, sweep_to) = (
+                resolve_account(account, client)?,
+                resolve_account(sweep_to, client)?,
+            );
+            Ok(ClientCommands::CloseAccount {
+                account: AccountRef::Id(account),
+                sweep_to: AccountRef::Id(sweep_to),
+                auth: auth.sign(AdminAction::Close { sweep_to }, account)?,
+                confirmations,
+            })
+        }
+        ClientCommands::DeployContract {
+            from_account,
+            path,
+            confirmations,
+            ..
+        } => {
+            let source = fs::read_to_string(&path)
+                .map_err(|e| format!("could not read contract {}: {}", path.display(), e))?;
+            let code = assemble(&source)
+                .map_err(|e| format!("could not assemble {}: {}", path.display(), e))?;
+            Ok(ClientCommands::DeployContract {
+                from_account,
+                path,
+                code,
+                confirmations,
+            })
+        }
+        ClientCommands::ReleaseEscrow {
+            escrow_id,
+            preimage,
+            key: Some(key),
+            confirmations,
+            ..
+        } => Ok(ClientCommands::ReleaseEscrow {
+            escrow_id,
+            preimage,
+            signature: Some(sign(&read_key(&key)?, &release_message(escrow_id))),
+            key: Some(key),
+            confirmations,
+        }),
+        ClientCommands::RefundEscrow {
+            escrow_id,
+            key: Some(key),
+            confirmations,
+            ..
+        } => Ok(ClientCommands::RefundEscrow {
+            escrow_id,
+            signature: Some(sign(&read_key(&key)?, &refund_message(escrow_id))),
+            key: Some(key),
+            confirmations,
+        }),
+        ClientCommands::Approve {
+            proposal_id,
+            to_account,
+            amount,
+            asset,
+            key,
+            confirmations,
+            ..
+        } => {
+            let to_account = resolve_account(to_account, client)?;
+            let (signer, signature) =
+                sign_approval(&read_key(&key)?, proposal_id, to_account, amount, asset);
+            Ok(ClientCommands::Approve {
+                proposal_id,
+                to_account: AccountRef::Id(to_account),
+                amount,
+                asset,
+                key,
+                signer,
+                signature,
+                confirmations,
+            })
+        }
+        command => Ok(command),
+    }
 }
 
 pub fn run_client(command: ClientCommands, port: u16) {
-    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).expect("fix me");
+    let mut client = match Client::connect(port) {
+        Ok(client) => client,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
     debug!("Client connected to the server");
 
-    let serialized_command = serialize(&command).expect("Failed to serialize command");
-    stream
-        .write(&serialized_command)
-        .expect("failed to send serialized command");
-    info!("Sent command: {:?}", command);
+    let command = match prepare_command(command, &mut client) {
+        Ok(command) => command,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
 
-    let mut buffer = [0; 512];
-    let bytes_read = stream.read(&mut buffer).expect("no - fix me");
-    let return_value: Result<ServerResponse, String> = deserialize(&buffer[..bytes_read]).unwrap();
+    if let ClientCommands::Subscribe { account } = command {
+        match client.subscribe(account) {
+            Ok(events) => {
+                for event in events {
+                    match event {
+                        Ok(event) => print_event(&event),
+                        Err(e) => error!("{}", e),
+                    }
+                }
+            }
+            Err(e) => error!("{}", e),
+        }
+        return;
+    }
 
-    match return_value {
+    info!("Sent command: {:?}", command);
+    match client.request_with(&command, print_reorg) {
         Err(e) => error!("recieved error: {}", e),
-        Ok(val) => match val {
-            ServerResponse::Transferred {
+        Ok(response) => print_response(response, &command),
+    }
+}
+
+/// Prints the node's answer to `command`; for `export`, writes the blocks to its file.
+pub(crate) fn print_response(response: ServerResponse, command: &ClientCommands) {
+    let export = match command {
+        ClientCommands::Export { path, format, .. } => Some((path.clone(), *format)),
+        _ => None,
+    };
+
+    match response {
+        ServerResponse::Transferred {
+            block_id,
+            transaction_id,
+            confirmations,
+            is_final,
+            ..
+        } => info!(
+            "transfer success. \n\tblock_id: {}\n\ttransaction_id: {}\n\tconfirmations: {}\n\tfinal: {}",
+            block_id,
+            transaction_id,
+            confirmations,
+            is_final
+        ),
+        ServerResponse::TransactionStatus {
+            transaction_id,
+            block_id,
+            confirmations,
+            finality,
+            is_final,
+        } => match block_id {
+            Some(block_id) => info!(
+                "transaction_id: {}\n\tblock_id: {}\n\tconfirmations: {}/{}\n\tfinal: {}",
+                transaction_id,
+                block_id,
+                confirmations,
+                finality,
+                is_final
+            ),
+            None => info!("transaction_id: {}\n\tpending", transaction_id),
+        },
+        ServerResponse::Balance {
+            balance,
+            locked,
+            status,
+        } => match status {
+            AccountStatus::Frozen => info!(
+                "balance: {}\n\tlocked: {}\n\tspendable: 0 (account is frozen)",
+                balance, locked
+            ),
+            _ => info!(
+                "balance: {}\n\tlocked: {}\n\tspendable: {}",
+                balance,
+                locked,
+                balance - locked
+            ),
+        },
+        ServerResponse::AccountCreated {
+            account,
+            block_id,
+            transaction_id,
+            confirmations,
+            is_final,
+            ..
+        } => info!(
+            "account created.\n\taddress: {}\n\tblock_id: {}\n\ttransaction_id: {}\n\tconfirmations: {}\n\tfinal: {}",
+            to_address(account),
+            block_id,
+            transaction_id,
+            confirmations,
+            is_final
+        ),
+        ServerResponse::AccountInfo {
+            account,
+            exists,
+            created_block_id,
+            created_pruned,
+            pending_transactions,
+            status,
+            names,
+            multisig_threshold,
+        } => info!(
+            "account: {}\n\texists: {}\n\tcreated_block_id: {}\n\tpending_transactions: {}\n\tstatus: {:?}\n\tnames: {}\n\tmultisig_threshold: {}",
+            to_address(account),
+            exists,
+            match (exists, created_block_id) {
+                _ if created_pruned => "unknown (pruned)".to_string(),
+                (_, Some(block_id)) => block_id.to_string(),
+                (true, None) => "pending".to_string(),
+                (false, None) => "none".to_string(),
+            },
+            pending_transactions,
+            status,
+            match names.is_empty() {
+                true => "none".to_string(),
+                false => names.join(", "),
+            },
+            multisig_threshold.map_or("none".to_string(), |threshold| threshold.to_string())
+        ),
+        ServerResponse::Resolved { account, names } => match names.is_empty() {
+            true => info!("account: {}\n\tnames: none", to_address(account)),
+            false => info!(
+                "account: {}\n\tnames: {}",
+                to_address(account),
+                names.join(", ")
+            ),
+        },
+        ServerResponse::AccountUpdated {
+            account,
+            status,
+            block_id,
+            transaction_id,
+        } => info!(
+            "account: {}\n\tstatus: {:?}\n\tblock_id: {}\n\ttransaction_id: {}",
+            to_address(account),
+            status,
+            block_id,
+            transaction_id
+        ),
+        ServerResponse::Scheduled {
+            schedule_id,
+            first_block_id,
+        } => info!(
+            "schedule_id: {}\n\tfirst_block_id: {}",
+            schedule_id, first_block_id
+        ),
+        ServerResponse::ScheduleCancelled { schedule_id } => {
+            info!("schedule cancelled: {}", schedule_id)
+        }
+        ServerResponse::MultisigCreated { account, threshold } => {
+            info!(
+                "multisig account: {}\n\tthreshold: {}",
+                to_address(account),
+                threshold
+            )
+        }
+        ServerResponse::Proposed {
+            proposal_id,
+            expires_at,
+        } => info!(
+            "proposal_id: {}\n\texpires_at_block: {}",
+            proposal_id, expires_at
+        ),
+        ServerResponse::Approval {
+            proposal_id,
+            approvals,
+            threshold,
+        } => info!(
+            "proposal_id: {}\n\tapprovals: {}/{}",
+            proposal_id, approvals, threshold
+        ),
+        ServerResponse::Reorganized {
+            orphaned_blocks,
+            head_block_id,
+        } => print_reorg(orphaned_blocks, head_block_id),
+        ServerResponse::Event(event) => print_event(&event),
+        ServerResponse::BlockReceived { block_id } => {
+            info!("block received: {}", block_id)
+        }
+        ServerResponse::Blocks { blocks } => match &export {
+            Some((path, format)) => match write_dump(path, *format, &blocks) {
+                Ok(()) => info!(
+                    "exported {} block(s) to {}",
+                    blocks.len(),
+                    path.display()
+                ),
+                Err(e) => error!("{}", e),
+            },
+            None => info!("blocks: {:?}", blocks),
+        },
+        ServerResponse::Snapshot { snapshot, blocks } => info!(
+            "snapshot: {:?}\n\tblocks: {}",
+            snapshot.map(|snapshot| snapshot.block_id),
+            blocks.len()
+        ),
+        ServerResponse::Supply { supply, supply_cap } => info!(
+            "minted: {}\n\tcirculating: {}\n\tburned: {}\n\tsupply_cap: {}",
+            supply.minted,
+            supply.circulating,
+            supply.burned,
+            supply_cap.map_or("none".to_string(), |cap| cap.to_string())
+        ),
+        ServerResponse::Escrow {
+            escrow,
+            head_block_id,
+        } => info!(
+            "escrow_id: {}\n\tpayer: {}\n\tpayee: {}\n\tamount: {} {}\n\tarbiter: {}\n\thashlock: {}\n\ttimeout_block_id: {} (head {})\n\tstate: {}",
+            escrow.id,
+            to_address(escrow.payer),
+            to_address(escrow.terms.payee),
+            escrow.amount,
+            asset_symbol(escrow.asset),
+            escrow.terms.arbiter.map_or("none".to_string(), |key| to_hex(&key)),
+            escrow.terms.hashlock.map_or("none".to_string(), |hash| to_hex(&hash)),
+            escrow.terms.timeout,
+            head_block_id,
+            match escrow.state {
+                EscrowState::Open => "open".to_string(),
+                EscrowState::Released { preimage: Some(preimage) } => {
+                    format!("released (preimage: {})", preimage)
+                }
+                EscrowState::Released { preimage: None } => "released".to_string(),
+                EscrowState::Refunded => "refunded".to_string(),
+            }
+        ),
+        ServerResponse::ContractDeployed { contract, block_id } => {
+            info!(
+                "contract: {}\n\tblock_id: {}",
+                to_address(contract),
+                block_id
+            )
+        }
+        ServerResponse::ContractCalled {
+            block_id,
+            transaction_id,
+            receipt,
+        } => match receipt.result {
+            Ok(result) => info!(
+                "call success.\n\tblock_id: {}\n\ttransaction_id: {}\n\tgas_used: {}\n\tresult: {}\n\twrites: {:?}",
                 block_id,
                 transaction_id,
-            } => info!(
-                "transfer success. \n\tblock_id: {}\n\ttransaction_id: {}",
-                block_id, transaction_id
+                receipt.gas_used,
+                result.map_or("none".to_string(), |result| result.to_string()),
+                receipt.writes
+            ),
+            Err(e) => error!(
+                "call failed: {}\n\tblock_id: {}\n\ttransaction_id: {}\n\tgas_used: {}",
+                e, block_id, transaction_id, receipt.gas_used
             ),
-            ServerResponse::Balance { balance } => info!("balance: {}", balance),
         },
+        ServerResponse::ContractStorage { contract, storage } => {
+            info!(
+                "contract: {}\n\tstorage: {:?}",
+                to_address(contract),
+                storage
+            )
+        }
+        ServerResponse::History {
+            account,
+            transactions,
+        } => info!(
+            "account: {}\n\ttransactions: {}{}",
+            to_address(account),
+            transactions.len(),
+            transactions
+                .iter()
+                .map(|(block_id, transaction)| format!(
+                    "\n\tblock {}: {}",
+                    block_id,
+                    describe_transaction(transaction)
+                ))
+                .collect::<String>()
+        ),
+        ServerResponse::Transaction {
+            block_id,
+            transaction,
+        } => info!(
+            "transaction {}\n\tblock_id: {}\n\tlock: {:?}",
+            describe_transaction(&transaction),
+            block_id.map_or("pending".to_string(), |block_id| block_id.to_string()),
+            transaction.lock()
+        ),
+        ServerResponse::ChainInfo {
+            chain_id,
+            genesis_hash,
+            head_block_id,
+            parameters,
+        } => info!(
+            "chain_id: {}\n\tgenesis_hash: {}\n\thead_block_id: {}\n\tparameters: {:?}",
+            chain_id,
+            to_hex(&genesis_hash),
+            head_block_id,
+            parameters
+        ),
+    }
+}
+
+fn print_event(event: &NodeEvent) {
+    match event {
+        NodeEvent::Block(block) => info!(
+            "block {} sealed with {} transaction(s)\n\thash: {}",
+            block.id,
+            block.all_transactions().count(),
+            to_hex(&block.hash())
+        ),
+        NodeEvent::Pending(transaction) => {
+            info!("pending transaction {}", describe_transaction(transaction))
+        }
+        NodeEvent::Sealed {
+            block_id,
+            transaction,
+        } => info!(
+            "transaction {}\n\tblock_id: {}",
+            describe_transaction(transaction),
+            block_id
+        ),
+        NodeEvent::Reorganized {
+            orphaned_blocks,
+            head_block_id,
+        } => print_reorg(*orphaned_blocks, *head_block_id),
     }
 }
+
+pub(crate) fn print_reorg(orphaned_blocks: u64, head_block_id: BlockId) {
+    warn!(
+        "chain reorganized: {} block(s) orphaned, new head block_id: {}",
+        orphaned_blocks, head_block_id
+    );
+}
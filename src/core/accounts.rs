@@ -0,0 +1,41 @@
+use super::address::to_address;
+use super::types::AccountId;
+use std::fmt;
+
+/// Reasons a transaction is refused because of the accounts it moves funds between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountError {
+    SenderNotFound(AccountId),
+    /// Only raised when the node requires recipients to exist
+    RecipientNotFound(AccountId),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountError::SenderNotFound(account) => {
+                write!(f, "Sender account not found: {}", to_address(*account))
+            }
+            AccountError::RecipientNotFound(account) => write!(
+                f,
+                "Recipient account not found: {} (this node does not create accounts implicitly)",
+                to_address(*account)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_name_the_side_and_address() {
+        let sender = AccountError::SenderNotFound(1).to_string();
+        let recipient = AccountError::RecipientNotFound(1).to_string();
+
+        assert!(sender.starts_with("Sender"));
+        assert!(recipient.starts_with("Recipient"));
+        assert!(sender.contains(&to_address(1)));
+    }
+}
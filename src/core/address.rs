@@ -0,0 +1,161 @@
+use super::constants::ESCROW_ID;
+use super::env::Random;
+use super::types::AccountId;
+use sha2::{Digest, Sha256};
+
+/// Addresses are `B` followed by the base58 encoding of the big-endian
+/// account id and the first 4 bytes of its double SHA-256.
+pub const ADDRESS_PREFIX: char = 'B';
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn encode_base58(bytes: &[u8]) -> String {
+    // Little-endian base58 digits of the big-endian number in `bytes`
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in bytes {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat_n(ALPHABET[0], zeros)
+        .chain(digits.iter().rev().map(|digit| ALPHABET[*digit as usize]))
+        .map(char::from)
+        .collect()
+}
+
+fn decode_base58(text: &str) -> Result<Vec<u8>, String> {
+    // Little-endian bytes of the number
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in text.bytes() {
+        let mut carry = ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .ok_or_else(|| format!("invalid base58 character '{}'", c as char))?
+            as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let zeros = text.bytes().take_while(|c| *c == ALPHABET[0]).count();
+    Ok(std::iter::repeat_n(0, zeros)
+        .chain(bytes.into_iter().rev())
+        .collect())
+}
+
+pub fn to_address(account: AccountId) -> String {
+    let payload = account.to_be_bytes();
+    let encoded = encode_base58(&[payload.as_slice(), &checksum(&payload)].concat());
+    format!("{}{}", ADDRESS_PREFIX, encoded)
+}
+
+pub fn parse_address(address: &str) -> Result<AccountId, String> {
+    let encoded = address
+        .strip_prefix(ADDRESS_PREFIX)
+        .ok_or_else(|| format!("addresses start with '{}'", ADDRESS_PREFIX))?;
+    let bytes =
+        decode_base58(encoded).map_err(|e| format!("invalid address {}: {}", address, e))?;
+    if bytes.len() != 12 {
+        return Err(format!("invalid address {}: wrong length", address));
+    }
+
+    let (payload, check) = bytes.split_at(8);
+    if checksum(payload) != check {
+        return Err(format!(
+            "invalid address {}: checksum mismatch (mistyped?)",
+            address
+        ));
+    }
+    Ok(AccountId::from_be_bytes(payload.try_into().unwrap()))
+}
+
+/// Parses an address. Plain numeric ids are refused: without a checksum a
+/// mistyped id would silently name another account.
+pub fn parse_account_id(value: &str) -> Result<AccountId, String> {
+    match value.parse::<AccountId>() {
+        Ok(_) => Err(format!(
+            "{} is a plain account id; give the account's address ({}...) instead",
+            value, ADDRESS_PREFIX
+        )),
+        Err(_) => parse_address(value),
+    }
+}
+
+/// Random account id below the ids reserved for the node's own bookkeeping.
+pub fn generate_account_id(random: &dyn Random) -> AccountId {
+    random.below(ESCROW_ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::env::SystemRandom;
+
+    #[test]
+    fn test_address_round_trip() {
+        for account in [
+            0,
+            1,
+            12345,
+            ESCROW_ID,
+            AccountId::MAX,
+            generate_account_id(&SystemRandom),
+        ] {
+            let address = to_address(account);
+            assert!(address.starts_with(ADDRESS_PREFIX));
+            assert_eq!(parse_address(&address), Ok(account));
+        }
+    }
+
+    #[test]
+    fn test_base58_matches_reference_encoding() {
+        assert_eq!(encode_base58(b"hello world"), "StV1DL6CwTryKyV");
+        assert_eq!(encode_base58(&[0, 0, 1]), "112");
+        assert_eq!(decode_base58("112"), Ok(vec![0, 0, 1]));
+    }
+
+    #[test]
+    fn test_mistyped_addresses_are_rejected() {
+        let address = to_address(987_654_321);
+        for (index, original) in address.char_indices().skip(1) {
+            for replacement in ALPHABET.iter().map(|c| *c as char) {
+                if replacement == original {
+                    continue;
+                }
+                let mut typo = address.clone();
+                typo.replace_range(index..index + 1, &replacement.to_string());
+                assert!(parse_address(&typo).is_err(), "{}", typo);
+            }
+        }
+        assert!(parse_address(&address[..address.len() - 1]).is_err());
+        assert!(parse_address(&address[1..]).is_err());
+        assert!(parse_address("B0OIl").is_err());
+    }
+
+    #[test]
+    fn test_parse_account_id() {
+        assert!(parse_account_id("12345").is_err());
+        assert_eq!(parse_account_id(&to_address(12345)), Ok(12345));
+        assert!(parse_account_id("alice").is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use super::address::to_address;
+use super::constants::ADMIN_SIGNATURE_WINDOW;
+use super::keys::{verify, PublicKey};
+use super::snapshot::Snapshot;
+use super::types::{AccountId, AssetId, Transaction, TransactionKind};
+use serde::{Deserialize, Serialize};
+
+/// Change to an account made by the node operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminAction {
+    Freeze,
+    Unfreeze,
+    /// Sweeps the remaining balances to `sweep_to`; closed accounts stay closed
+    Close {
+        sweep_to: AccountId,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountStatus {
+    Active,
+    /// Can receive but not send
+    Frozen,
+    /// Can neither receive nor send
+    Closed,
+}
+
+/// Message signed by the operator key; `timestamp` (ms since the UNIX epoch)
+/// keeps old signatures from being replayed.
+pub fn admin_message(action: AdminAction, account: AccountId, timestamp: u64) -> Vec<u8> {
+    let (tag, sweep_to) = match action {
+        AdminAction::Freeze => (0u8, 0),
+        AdminAction::Unfreeze => (1, 0),
+        AdminAction::Close { sweep_to } => (2, sweep_to),
+    };
+    [
+        b"b-admin:".as_slice(),
+        &[tag],
+        &account.to_be_bytes(),
+        &sweep_to.to_be_bytes(),
+        &timestamp.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Checks that an admin action was signed by `admin_key` within
+/// `ADMIN_SIGNATURE_WINDOW` of `now` (ms since the UNIX epoch).
+pub fn verify_admin(
+    admin_key: &PublicKey,
+    action: AdminAction,
+    account: AccountId,
+    timestamp: u64,
+    signature: &[u8],
+    now: u64,
+) -> Result<(), String> {
+    if now.abs_diff(timestamp) > ADMIN_SIGNATURE_WINDOW {
+        return Err("Admin signature has expired; sign the command again".to_string());
+    }
+    match verify(
+        admin_key,
+        &admin_message(action, account, timestamp),
+        signature,
+    ) {
+        true => Ok(()),
+        false => Err("Invalid admin signature".to_string()),
+    }
+}
+
+/// Whether an admin transaction among `transactions` already carries
+/// `signature`, which would make it a replay.
+pub fn is_signature_used<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    signature: &[u8],
+) -> bool {
+    transactions.into_iter().any(|transaction| {
+        matches!(transaction.kind(), TransactionKind::Admin { signature: used, .. } if used == signature)
+    })
+}
+
+/// Status of an account after the admin transactions among `transactions`.
+pub fn account_status<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    account: AccountId,
+) -> AccountStatus {
+    transactions
+        .into_iter()
+        .filter(|transaction| transaction.from() == account)
+        .fold(AccountStatus::Active, |status, transaction| {
+            match (status, transaction.kind()) {
+                (AccountStatus::Closed, _) => AccountStatus::Closed,
+                (_, TransactionKind::Admin { action, .. }) => match action {
+                    AdminAction::Freeze => AccountStatus::Frozen,
+                    AdminAction::Unfreeze => AccountStatus::Active,
+                    AdminAction::Close { .. } => AccountStatus::Closed,
+                },
+                (status, _) => status,
+            }
+        })
+}
+
+/// Assets an account holds in `snapshot` or has sent or received among
+/// `transactions`, which follow the snapshot's block.
+pub fn held_assets<'a>(
+    snapshot: Option<&Snapshot>,
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    account: AccountId,
+) -> Vec<AssetId> {
+    let mut assets: Vec<AssetId> = snapshot
+        .into_iter()
+        .flat_map(|snapshot| &snapshot.balances)
+        .filter(|(_, balances)| balances.contains_key(&account))
+        .map(|(asset, _)| *asset)
+        .chain(
+            transactions
+                .into_iter()
+                .filter(|transaction| transaction.to() == account || transaction.from() == account)
+                .map(|transaction| transaction.asset()),
+        )
+        .collect();
+    assets.sort();
+    assets.dedup();
+    assets
+}
+
+impl AccountStatus {
+    pub fn check_can_send(self, account: AccountId) -> Result<(), String> {
+        match self {
+            AccountStatus::Active => Ok(()),
+            AccountStatus::Frozen => Err(format!("Account {} is frozen", to_address(account))),
+            AccountStatus::Closed => Err(format!("Account {} is closed", to_address(account))),
+        }
+    }
+
+    pub fn check_can_receive(self, account: AccountId) -> Result<(), String> {
+        match self {
+            AccountStatus::Closed => Err(format!("Account {} is closed", to_address(account))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::{generate_secret_key, public_key, sign};
+    use crate::core::types::Block;
+
+    fn admin(account: AccountId, action: AdminAction) -> Transaction {
+        Transaction::new(account, account, 0.0).with_kind(TransactionKind::Admin {
+            action,
+            timestamp: 0,
+            signature: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_verify_admin() {
+        let key = generate_secret_key();
+        let admin_key = public_key(&key);
+        let signature = sign(&key, &admin_message(AdminAction::Freeze, 1, 1_000));
+
+        assert!(verify_admin(&admin_key, AdminAction::Freeze, 1, 1_000, &signature, 2_000).is_ok());
+        assert!(verify_admin(
+            &admin_key,
+            AdminAction::Unfreeze,
+            1,
+            1_000,
+            &signature,
+            2_000
+        )
+        .is_err());
+        assert!(
+            verify_admin(&admin_key, AdminAction::Freeze, 2, 1_000, &signature, 2_000).is_err()
+        );
+        assert!(verify_admin(
+            &admin_key,
+            AdminAction::Freeze,
+            1,
+            1_000,
+            &signature,
+            1_000 + ADMIN_SIGNATURE_WINDOW + 1
+        )
+        .is_err());
+        assert!(verify_admin(
+            &public_key(&generate_secret_key()),
+            AdminAction::Freeze,
+            1,
+            1_000,
+            &signature,
+            2_000
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_account_status_follows_admin_transactions() {
+        let freeze = admin(1, AdminAction::Freeze);
+        let unfreeze = admin(1, AdminAction::Unfreeze);
+        let close = Transaction::new(2, 1, 5.0).with_kind(TransactionKind::Admin {
+            action: AdminAction::Close { sweep_to: 2 },
+            timestamp: 0,
+            signature: Vec::new(),
+        });
+
+        assert_eq!(account_status([&freeze], 1), AccountStatus::Frozen);
+        assert_eq!(account_status([&freeze], 2), AccountStatus::Active);
+        assert_eq!(
+            account_status([&freeze, &unfreeze], 1),
+            AccountStatus::Active
+        );
+        assert_eq!(
+            account_status([&close, &unfreeze], 1),
+            AccountStatus::Closed
+        );
+        assert_eq!(account_status([&close], 2), AccountStatus::Active);
+    }
+
+    #[test]
+    fn test_is_signature_used() {
+        let kind = |signature: Vec<u8>| TransactionKind::Admin {
+            action: AdminAction::Freeze,
+            timestamp: 1_000,
+            signature,
+        };
+        let transactions = [
+            Transaction::new(1, 1, 0.0).with_kind(kind(vec![1, 2])),
+            Transaction::new(2, 1, 0.0),
+        ];
+
+        assert!(is_signature_used(&transactions, &[1, 2]));
+        assert!(!is_signature_used(&transactions, &[1, 3]));
+    }
+
+    #[test]
+    fn test_held_assets() {
+        let transactions = [
+            Transaction::with_asset(1, 2, 1.0, 7),
+            Transaction::new(3, 1, 1.0),
+            Transaction::with_asset(1, 2, 1.0, 7),
+            Transaction::with_asset(4, 5, 1.0, 9),
+        ];
+
+        assert_eq!(held_assets(None, &transactions, 1), vec![0, 7]);
+
+        let snapshot = Snapshot::of(&[Block::new(
+            0,
+            [0; 32],
+            vec![Transaction::with_asset(1, 2, 1.0, 9)],
+        )]);
+        assert_eq!(
+            held_assets(Some(&snapshot), &transactions, 1),
+            vec![0, 7, 9]
+        );
+        assert_eq!(held_assets(Some(&snapshot), [], 1), vec![9]);
+    }
+}
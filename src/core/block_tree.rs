@@ -0,0 +1,558 @@
+use super::monetary::Supply;
+use super::pow::meets_difficulty;
+use super::snapshot::Snapshot;
+use super::traits::TransactionInfo;
+use super::types::{AccountId, AssetId, Block, BlockId, Blocks, Hash, TransactionId};
+use std::collections::HashMap;
+
+/// Blocks removed from and added to the canonical chain by a reorganization.
+#[derive(Debug)]
+pub struct Reorg {
+    pub orphaned: Blocks,
+    pub adopted: Blocks,
+}
+
+#[derive(Debug)]
+pub enum InsertOutcome {
+    /// The block was appended to the canonical chain
+    Extended,
+    /// The block was stored on a chain that is not (yet) canonical
+    SideChain,
+    /// The block made a competing chain canonical
+    Reorganized(Reorg),
+    /// The block was already known
+    Duplicate,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InsertError {
+    UnknownParent,
+    InvalidHeight,
+    InvalidProofOfWork,
+    /// The block would orphan blocks that are already final
+    FinalityViolation,
+    /// The block claims to start a chain other than the genesis block of the tree
+    InvalidGenesis,
+}
+
+/// All known blocks, linked by `previous_hash` down to a single genesis
+/// block, with the canonical chain
+/// selected by the heaviest-chain rule. A block weighs `2^difficulty`, so
+/// chains of unmined blocks fall back to longest-chain. Ties keep the chain
+/// that was seen first.
+///
+/// With a finality depth, canonical blocks with at least that many
+/// confirmations can no longer be orphaned.
+///
+/// Balances are derived from the latest snapshot of a canonical block, if
+/// any, and the blocks after it.
+#[derive(Debug)]
+pub struct BlockTree {
+    blocks: HashMap<Hash, Block>,
+    work: HashMap<Hash, u128>,
+    chain: Blocks,
+    finality: u64,
+    snapshot: Option<Snapshot>,
+}
+
+pub fn block_work(block: &Block) -> u128 {
+    1u128 << block.difficulty.min(126)
+}
+
+impl BlockTree {
+    pub fn new(genesis: Block, finality: u64) -> BlockTree {
+        let hash = genesis.hash();
+        BlockTree {
+            work: HashMap::from([(hash, block_work(&genesis))]),
+            chain: vec![genesis.clone()],
+            blocks: HashMap::from([(hash, genesis)]),
+            finality,
+            snapshot: None,
+        }
+    }
+
+    /// Canonical chain, indexed by block id.
+    pub fn chain(&self) -> &Blocks {
+        &self.chain
+    }
+
+    pub fn head(&self) -> &Block {
+        self.chain
+            .last()
+            .expect("chain always holds the genesis block")
+    }
+
+    /// Number of canonical blocks from `block_id` up to and including the head.
+    pub fn confirmations(&self, block_id: BlockId) -> u64 {
+        let head = self.head();
+        match block_id <= head.id {
+            true => head.id - block_id + 1,
+            false => 0,
+        }
+    }
+
+    pub fn is_final(&self, block_id: BlockId) -> bool {
+        self.finality > 0 && self.confirmations(block_id) >= self.finality
+    }
+
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    /// Adopts a snapshot of a canonical block.
+    pub fn set_snapshot(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        snapshot.verify(&self.chain)?;
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    /// Discards the transaction bodies of canonical blocks the snapshot covers
+    /// that are more than `keep` blocks below the head, returning how many
+    /// blocks were pruned. The genesis block is kept whole.
+    pub fn prune(&mut self, keep: u64) -> usize {
+        let Some(snapshot) = &self.snapshot else {
+            return 0;
+        };
+        let last = snapshot.block_id.min(self.head().id.saturating_sub(keep));
+
+        let mut pruned = 0;
+        for block in self.chain.iter_mut().take(last as usize + 1).skip(1) {
+            if !block.is_pruned() {
+                block.prune();
+                if let Some(stored) = self.blocks.get_mut(&block.hash()) {
+                    stored.prune();
+                }
+                pruned += 1;
+            }
+        }
+        pruned
+    }
+
+    /// Id of the newest canonical block that was pruned, if any.
+    pub fn pruned_through(&self) -> Option<BlockId> {
+        self.chain
+            .iter()
+            .skip(1)
+            .take_while(|block| block.is_pruned())
+            .last()
+            .map(|block| block.id)
+    }
+
+    /// Canonical block a pruned transaction was sealed in.
+    pub fn find_pruned_transaction(&self, transaction_id: TransactionId) -> Option<BlockId> {
+        self.chain
+            .iter()
+            .filter_map(|block| block.pruned.as_ref().map(|pruned| (block.id, pruned)))
+            .find(|(_, pruned)| pruned.transaction_ids.contains(&transaction_id))
+            .map(|(block_id, _)| block_id)
+    }
+
+    /// Whether an account appears in the canonical chain, pruned blocks included.
+    pub fn contains_account(&self, account: AccountId) -> bool {
+        self.snapshot
+            .as_ref()
+            .is_some_and(|snapshot| snapshot.contains_account(account))
+            || self.chain.contains_account(account)
+    }
+
+    pub fn supply(&self, asset: AssetId) -> Supply {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.supply(&self.chain, asset),
+            None => Supply::of_chain(&self.chain, asset),
+        }
+    }
+
+    /// Balance of an account in an asset over the canonical chain, `None` if
+    /// it never held any.
+    pub fn balance(&self, account: AccountId, asset: AssetId) -> Option<f64> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.balance(&self.chain, account, asset),
+            None => self.chain.calculate_total(account, asset),
+        }
+    }
+
+    /// Blocks from the genesis block up to the block with hash `hash`, which
+    /// may be on a side chain; `None` if the block is unknown.
+    pub fn branch(&self, hash: &Hash) -> Option<Blocks> {
+        let mut side = Vec::new();
+        let mut cursor = self.blocks.get(hash)?;
+        while !self.is_canonical(&cursor.hash()) {
+            side.push(cursor.clone());
+            cursor = self.blocks.get(&cursor.previous_hash)?;
+        }
+        let mut branch = self.chain[..=cursor.id as usize].to_vec();
+        branch.extend(side.into_iter().rev());
+        Some(branch)
+    }
+
+    /// Balances after the last block of `branch`, replayed from the snapshot
+    /// when the branch contains its block.
+    pub fn balances_at(&self, branch: &Blocks) -> Snapshot {
+        match &self.snapshot {
+            Some(snapshot) if snapshot.verify(branch).is_ok() => {
+                snapshot.extend(&branch[snapshot.block_id as usize + 1..])
+            }
+            _ => Snapshot::of(branch),
+        }
+    }
+
+    pub fn insert(&mut self, block: Block) -> Result<InsertOutcome, InsertError> {
+        let hash = block.hash();
+        if self.blocks.contains_key(&hash) {
+            return Ok(InsertOutcome::Duplicate);
+        }
+
+        if block.difficulty > 0 && !meets_difficulty(&hash, block.difficulty) {
+            return Err(InsertError::InvalidProofOfWork);
+        }
+
+        if block.id == 0 {
+            return Err(InsertError::InvalidGenesis);
+        }
+
+        let parent = self
+            .blocks
+            .get(&block.previous_hash)
+            .ok_or(InsertError::UnknownParent)?;
+        if block.id != parent.id + 1 {
+            return Err(InsertError::InvalidHeight);
+        }
+
+        let work = self.work[&block.previous_hash] + block_work(&block);
+        let head_hash = self.head().hash();
+        let extends_head = block.previous_hash == head_hash;
+        let is_heavier = work > self.work[&head_hash];
+
+        if is_heavier && !extends_head {
+            let fork_height = self.fork_height(&block);
+            if fork_height < self.chain.len() as BlockId && self.is_final(fork_height) {
+                return Err(InsertError::FinalityViolation);
+            }
+        }
+
+        self.work.insert(hash, work);
+        self.blocks.insert(hash, block);
+
+        if !is_heavier {
+            return Ok(InsertOutcome::SideChain);
+        }
+
+        if extends_head {
+            self.chain.push(self.blocks[&hash].clone());
+            return Ok(InsertOutcome::Extended);
+        }
+
+        Ok(InsertOutcome::Reorganized(self.reorganize(hash)))
+    }
+
+    fn is_canonical(&self, hash: &Hash) -> bool {
+        let block = &self.blocks[hash];
+        self.chain
+            .get(block.id as usize)
+            .is_some_and(|canonical| canonical.hash() == *hash)
+    }
+
+    /// Height of the first block of `block`'s chain that is not canonical.
+    fn fork_height(&self, block: &Block) -> BlockId {
+        let mut cursor = block;
+        loop {
+            let parent = &self.blocks[&cursor.previous_hash];
+            if self.is_canonical(&cursor.previous_hash) {
+                return parent.id + 1;
+            }
+            cursor = parent;
+        }
+    }
+
+    fn reorganize(&mut self, new_head: Hash) -> Reorg {
+        let mut adopted: Blocks = Vec::new();
+        let mut cursor = new_head;
+        loop {
+            if self.is_canonical(&cursor) {
+                break;
+            }
+            let block = &self.blocks[&cursor];
+            adopted.push(block.clone());
+            cursor = block.previous_hash;
+        }
+        adopted.reverse();
+
+        let fork_height = adopted[0].id as usize;
+        let orphaned = self.chain.split_off(fork_height);
+        self.chain.extend(adopted.iter().cloned());
+        if self
+            .snapshot
+            .as_ref()
+            .is_some_and(|snapshot| snapshot.verify(&self.chain).is_err())
+        {
+            self.snapshot = None;
+        }
+
+        Reorg { orphaned, adopted }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::Transaction;
+
+    fn create_genesis() -> Block {
+        Block::new(0, [0; 32], Vec::new())
+    }
+
+    fn child_of(parent: &Block, marker: u64) -> Block {
+        let mut block = Block::new(
+            parent.id + 1,
+            parent.hash(),
+            vec![Transaction::new(marker, 1, 1.0)],
+        );
+        block.nonce = marker;
+        block
+    }
+
+    #[test]
+    fn test_new_tree_starts_at_genesis() {
+        let genesis = create_genesis();
+        let tree = BlockTree::new(genesis.clone(), 0);
+
+        assert_eq!(tree.chain().len(), 1);
+        assert_eq!(tree.head().hash(), genesis.hash());
+        assert_eq!(tree.chain()[0].hash(), genesis.hash());
+    }
+
+    #[test]
+    fn test_insert_extends_chain() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+        let next = child_of(&first, 2);
+
+        assert!(matches!(tree.insert(first), Ok(InsertOutcome::Extended)));
+        assert!(matches!(tree.insert(next), Ok(InsertOutcome::Extended)));
+        assert_eq!(tree.chain().len(), 3);
+    }
+
+    #[test]
+    fn test_branch_of_side_chain() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+        let next = child_of(&first, 2);
+        let side = child_of(&first, 3);
+        tree.insert(first.clone()).unwrap();
+        tree.insert(next).unwrap();
+        tree.insert(side.clone()).unwrap();
+
+        let hashes = |branch: Blocks| branch.iter().map(Block::hash).collect::<Vec<_>>();
+        assert_eq!(
+            tree.branch(&side.hash()).map(hashes),
+            Some(vec![genesis.hash(), first.hash(), side.hash()])
+        );
+        assert!(tree.branch(&[7; 32]).is_none());
+    }
+
+    #[test]
+    fn test_insert_duplicate() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+
+        tree.insert(first.clone()).unwrap();
+        assert!(matches!(tree.insert(first), Ok(InsertOutcome::Duplicate)));
+        assert!(matches!(tree.insert(genesis), Ok(InsertOutcome::Duplicate)));
+    }
+
+    #[test]
+    fn test_insert_rejects_other_genesis() {
+        let mut tree = BlockTree::new(create_genesis(), 0);
+        let other_genesis = Block::new(0, [1; 32], Vec::new());
+
+        assert_eq!(
+            tree.insert(other_genesis).unwrap_err(),
+            InsertError::InvalidGenesis
+        );
+    }
+
+    #[test]
+    fn test_insert_rejects_unknown_parent_and_bad_height() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+        let next = child_of(&first, 2);
+
+        let mut bad_height = child_of(&genesis, 3);
+        bad_height.id = 5;
+
+        assert_eq!(tree.insert(next).unwrap_err(), InsertError::UnknownParent);
+        assert_eq!(
+            tree.insert(bad_height).unwrap_err(),
+            InsertError::InvalidHeight
+        );
+    }
+
+    #[test]
+    fn test_insert_rejects_invalid_proof_of_work() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let mut block = child_of(&genesis, 1);
+        block.difficulty = 64;
+
+        assert_eq!(
+            tree.insert(block).unwrap_err(),
+            InsertError::InvalidProofOfWork
+        );
+    }
+
+    #[test]
+    fn test_equal_work_keeps_first_seen_chain() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+        let competing = child_of(&genesis, 2);
+        let first_hash = first.hash();
+
+        tree.insert(first).unwrap();
+        assert!(matches!(
+            tree.insert(competing),
+            Ok(InsertOutcome::SideChain)
+        ));
+        assert_eq!(tree.head().hash(), first_hash);
+    }
+
+    #[test]
+    fn test_longer_side_chain_reorganizes() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+        let competing = child_of(&genesis, 2);
+        let competing_next = child_of(&competing, 3);
+        let first_hash = first.hash();
+
+        tree.insert(first).unwrap();
+        tree.insert(competing.clone()).unwrap();
+
+        let reorg = match tree.insert(competing_next.clone()) {
+            Ok(InsertOutcome::Reorganized(reorg)) => reorg,
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        };
+
+        assert_eq!(reorg.orphaned.len(), 1);
+        assert_eq!(reorg.orphaned[0].hash(), first_hash);
+        assert_eq!(reorg.adopted.len(), 2);
+        assert_eq!(reorg.adopted[0].hash(), competing.hash());
+        assert_eq!(tree.chain().len(), 3);
+        assert_eq!(tree.head().hash(), competing_next.hash());
+    }
+
+    #[test]
+    fn test_snapshot_is_dropped_when_orphaned() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 4);
+        let competing = child_of(&genesis, 2);
+        let competing_next = child_of(&competing, 3);
+
+        tree.insert(first).unwrap();
+        tree.set_snapshot(Snapshot::of(tree.chain())).unwrap();
+        assert_eq!(tree.balance(4, 0), Some(1.0));
+
+        tree.insert(competing).unwrap();
+        assert!(tree.snapshot().is_some());
+        tree.insert(competing_next).unwrap();
+        assert!(tree.snapshot().is_none());
+        assert_eq!(tree.balance(4, 0), None);
+        assert_eq!(tree.balance(1, 0), Some(-2.0));
+    }
+
+    #[test]
+    fn test_prune_needs_snapshot_and_keeps_recent_blocks() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let mut parent = genesis;
+        for marker in 2..7 {
+            let block = child_of(&parent, marker);
+            tree.insert(block.clone()).unwrap();
+            parent = block;
+        }
+        let head_hash = tree.head().hash();
+        let removed = tree.chain()[2].transactions[0].id;
+
+        assert_eq!(tree.prune(1), 0);
+        tree.set_snapshot(Snapshot::of(&tree.chain()[..4])).unwrap();
+        assert_eq!(tree.prune(2), 3);
+        assert_eq!(tree.prune(2), 0);
+
+        assert_eq!(tree.pruned_through(), Some(3));
+        assert!(!tree.chain()[0].is_pruned());
+        assert!(!tree.chain()[4].is_pruned());
+        assert_eq!(tree.head().hash(), head_hash);
+        assert_eq!(tree.find_pruned_transaction(removed), Some(2));
+        assert!(tree.contains_account(2));
+        assert_eq!(tree.balance(1, 0), Some(-5.0));
+        assert_eq!(tree.supply(0), Supply::of_chain(&tree.chain, 0));
+    }
+
+    #[test]
+    fn test_confirmations_and_finality() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 2);
+        let first = child_of(&genesis, 1);
+
+        assert_eq!(tree.confirmations(0), 1);
+        assert!(!tree.is_final(0));
+
+        tree.insert(first).unwrap();
+        assert_eq!(tree.confirmations(0), 2);
+        assert_eq!(tree.confirmations(1), 1);
+        assert_eq!(tree.confirmations(2), 0);
+        assert!(tree.is_final(0));
+        assert!(!tree.is_final(1));
+    }
+
+    #[test]
+    fn test_reorg_of_final_blocks_is_rejected() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 2);
+        let first = child_of(&genesis, 1);
+        let first_next = child_of(&first, 2);
+        let first_hash = first_next.hash();
+
+        let competing = child_of(&genesis, 3);
+        let competing_next = child_of(&competing, 4);
+        let competing_last = child_of(&competing_next, 5);
+
+        tree.insert(first).unwrap();
+        tree.insert(first_next).unwrap();
+        tree.insert(competing).unwrap();
+        tree.insert(competing_next).unwrap();
+
+        assert_eq!(
+            tree.insert(competing_last).unwrap_err(),
+            InsertError::FinalityViolation
+        );
+        assert_eq!(tree.head().hash(), first_hash);
+    }
+
+    #[test]
+    fn test_heavier_chain_wins_over_longer_chain() {
+        let genesis = create_genesis();
+        let mut tree = BlockTree::new(genesis.clone(), 0);
+        let first = child_of(&genesis, 1);
+        let first_next = child_of(&first, 2);
+
+        let mut heavy = child_of(&genesis, 3);
+        heavy.difficulty = 4;
+        crate::core::pow::mine(&mut heavy);
+
+        tree.insert(first).unwrap();
+        tree.insert(first_next).unwrap();
+
+        assert!(matches!(
+            tree.insert(heavy.clone()),
+            Ok(InsertOutcome::Reorganized(_))
+        ));
+        assert_eq!(tree.chain().len(), 2);
+        assert_eq!(tree.head().hash(), heavy.hash());
+    }
+}
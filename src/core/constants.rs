@@ -1,3 +1,30 @@
-use super::types::Id;
+use super::types::{AssetId, Id};
 
 pub const MASTER_ID: Id = Id::MAX;
+/// Receives transfer fees; funds sent here are out of circulation
+pub const BURN_ID: Id = Id::MAX - 1;
+/// Holds funds of open escrows until they are released or refunded
+pub const ESCROW_ID: Id = Id::MAX - 2;
+
+/// The implicit currency of the chain; fees, rewards and the supply cap are in this asset
+pub const NATIVE_ASSET: AssetId = 0;
+pub const NATIVE_SYMBOL: &str = "B";
+
+pub const DEFAULT_CHAIN_ID: &str = "b-local";
+
+pub const DEFAULT_DIFFICULTY: u32 = 16;
+pub const MAX_DIFFICULTY: u32 = 64;
+pub const RETARGET_WINDOW: u64 = 5;
+/// How far (in ms) ahead of a node's clock the timestamp of a peer's block may be
+pub const MAX_BLOCK_DRIFT: u64 = 120_000;
+pub const DEFAULT_REWARD: f64 = 50.0;
+pub const DEFAULT_FINALITY: u64 = 6;
+/// Number of blocks a multisig proposal can be approved for
+pub const DEFAULT_PROPOSAL_TTL: u64 = 100;
+/// How far (in ms) the timestamp of a signed admin command may be from the node's clock
+pub const ADMIN_SIGNATURE_WINDOW: u64 = 60_000;
+/// File in a node's data directory holding its canonical chain as a JSON Lines dump
+pub const CHAIN_FILE: &str = "chain.jsonl";
+/// File in a node's data directory holding its latest state snapshot
+pub const SNAPSHOT_FILE: &str = "snapshot.json";
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 100;
@@ -0,0 +1,181 @@
+use super::types::{AccountId, Blocks, Receipt, Transaction, TransactionKind};
+use super::vm::{execute, Program, Storage, Word};
+use std::collections::HashMap;
+
+/// Looks up the code of a contract among `transactions`.
+pub fn find_contract<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    contract: AccountId,
+) -> Option<Program> {
+    transactions
+        .into_iter()
+        .find_map(|transaction| match transaction.kind() {
+            TransactionKind::Deploy { code } if transaction.id == contract => Some(code.clone()),
+            _ => None,
+        })
+}
+
+fn apply_writes(storage: &mut Storage, receipt: &Receipt) {
+    for (key, value) in &receipt.writes {
+        match value {
+            0 => storage.remove(key),
+            _ => storage.insert(*key, *value),
+        };
+    }
+}
+
+/// Storage of a contract after every receipt of the chain.
+pub fn contract_storage(chain: &Blocks, contract: AccountId) -> Storage {
+    let mut storage = Storage::new();
+    for receipt in chain
+        .iter()
+        .flat_map(|block| &block.receipts)
+        .filter(|receipt| receipt.contract == contract)
+    {
+        apply_writes(&mut storage, receipt);
+    }
+    storage
+}
+
+fn diff(before: &Storage, after: &Storage) -> Vec<(Word, Word)> {
+    let removed = before
+        .keys()
+        .filter(|key| !after.contains_key(key))
+        .map(|key| (*key, 0));
+    let changed = after
+        .iter()
+        .filter(|(key, value)| before.get(key) != Some(value))
+        .map(|(key, value)| (*key, *value));
+    removed.chain(changed).collect()
+}
+
+/// Executes the contract calls among `transactions`, which are about to be
+/// sealed on top of `chain`, and returns one receipt per call.
+pub fn execute_calls(chain: &Blocks, transactions: &[Transaction]) -> Vec<Receipt> {
+    let mut storages: HashMap<AccountId, Storage> = HashMap::new();
+    let mut receipts = Vec::new();
+
+    for (index, transaction) in transactions.iter().enumerate() {
+        let (args, gas_limit) = match transaction.kind() {
+            TransactionKind::Call { args, gas_limit } => (args, *gas_limit),
+            _ => continue,
+        };
+        let contract = transaction.to();
+
+        let deployed = chain
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .chain(&transactions[..index]);
+        let program = match find_contract(deployed, contract) {
+            Some(program) => program,
+            None => {
+                receipts.push(Receipt {
+                    transaction_id: transaction.id,
+                    contract,
+                    gas_used: 0,
+                    result: Err(format!("Contract not found: {}", contract)),
+                    writes: Vec::new(),
+                });
+                continue;
+            }
+        };
+
+        let storage = storages
+            .entry(contract)
+            .or_insert_with(|| contract_storage(chain, contract));
+        let receipt = match execute(
+            &program,
+            storage,
+            transaction.from() as Word,
+            args,
+            gas_limit,
+        ) {
+            Ok(execution) => {
+                let writes = diff(storage, &execution.storage);
+                *storage = execution.storage;
+                Receipt {
+                    transaction_id: transaction.id,
+                    contract,
+                    gas_used: execution.gas_used,
+                    result: Ok(execution.result),
+                    writes,
+                }
+            }
+            Err(error) => Receipt {
+                transaction_id: transaction.id,
+                contract,
+                gas_used: error.gas_used,
+                result: Err(error.message),
+                writes: Vec::new(),
+            },
+        };
+        receipts.push(receipt);
+    }
+
+    receipts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::Block;
+    use crate::core::vm::{assemble, DEFAULT_GAS_LIMIT};
+
+    const COUNTER: &str = "push 0\npush 0\nload\npush 0\narg\nadd\nstore\npush 0\nload\nreturn";
+
+    fn call(contract: AccountId, amount: i64) -> Transaction {
+        Transaction::new(contract, 1, 0.0).with_kind(TransactionKind::Call {
+            args: vec![amount],
+            gas_limit: DEFAULT_GAS_LIMIT,
+        })
+    }
+
+    #[test]
+    fn test_calls_in_block_see_earlier_writes() {
+        let deploy = Transaction::new(1, 1, 0.0).with_kind(TransactionKind::Deploy {
+            code: assemble(COUNTER).unwrap(),
+        });
+        let contract = deploy.id;
+        let chain = vec![Block::new(0, [0; 32], vec![deploy])];
+
+        let receipts = execute_calls(&chain, &[call(contract, 2), call(contract, 3)]);
+
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].result, Ok(Some(2)));
+        assert_eq!(receipts[1].result, Ok(Some(5)));
+        assert_eq!(receipts[1].writes, vec![(0, 5)]);
+    }
+
+    #[test]
+    fn test_storage_is_replayed_from_receipts() {
+        let deploy = Transaction::new(1, 1, 0.0).with_kind(TransactionKind::Deploy {
+            code: assemble(COUNTER).unwrap(),
+        });
+        let contract = deploy.id;
+        let first_call = call(contract, 4);
+
+        let mut block = Block::new(0, [0; 32], vec![deploy, first_call]);
+        block.receipts = execute_calls(&Vec::new(), &block.transactions);
+        let chain = vec![block];
+
+        assert_eq!(contract_storage(&chain, contract), Storage::from([(0, 4)]));
+        let receipts = execute_calls(&chain, &[call(contract, -4)]);
+        assert_eq!(receipts[0].writes, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn test_failed_calls_do_not_write() {
+        let deploy = Transaction::new(1, 1, 0.0).with_kind(TransactionKind::Deploy {
+            code: assemble("push 0\npush 1\nstore\nrevert").unwrap(),
+        });
+        let contract = deploy.id;
+        let chain = vec![Block::new(0, [0; 32], vec![deploy])];
+
+        let receipts = execute_calls(&chain, &[call(contract, 0), call(contract + 1, 0)]);
+
+        assert_eq!(receipts[0].result, Err("reverted".to_string()));
+        assert!(receipts[0].writes.is_empty());
+        assert_eq!(receipts[0].gas_used, 23);
+        assert!(receipts[1].result.is_err());
+    }
+}
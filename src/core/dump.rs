@@ -0,0 +1,246 @@
+use super::block_tree::{BlockTree, InsertOutcome};
+use super::constants::NATIVE_ASSET;
+use super::monetary::check_supply_invariant;
+use super::rules::{check_block, ChainRules};
+use super::snapshot::Snapshot;
+use super::types::{Block, Blocks};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// Encoding of a chain dump: one record per block, in chain order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum DumpFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// A sequence of CBOR data items
+    Cbor,
+}
+
+pub fn write_blocks<'a>(
+    writer: &mut impl Write,
+    format: DumpFormat,
+    blocks: impl IntoIterator<Item = &'a Block>,
+) -> Result<(), String> {
+    for block in blocks {
+        match format {
+            DumpFormat::Jsonl => serde_json::to_writer(&mut *writer, block)
+                .map_err(|e| e.to_string())
+                .and_then(|()| writeln!(writer).map_err(|e| e.to_string())),
+            DumpFormat::Cbor => {
+                ciborium::ser::into_writer(block, &mut *writer).map_err(|e| e.to_string())
+            }
+        }
+        .map_err(|e| format!("could not write block {}: {}", block.id, e))?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Reads the next block of a dump, `None` at its end.
+fn read_block(reader: &mut impl BufRead, format: DumpFormat) -> Result<Option<Block>, String> {
+    match format {
+        DumpFormat::Jsonl => {
+            let mut line = String::new();
+            while line.trim().is_empty() {
+                line.clear();
+                if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                    return Ok(None);
+                }
+            }
+            serde_json::from_str(&line)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+        DumpFormat::Cbor => match reader.fill_buf().map_err(|e| e.to_string())?.is_empty() {
+            true => Ok(None),
+            false => ciborium::de::from_reader(reader)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+        },
+    }
+}
+
+/// Blocks of a dump, stopping after the first one that cannot be decoded.
+pub fn read_blocks(
+    mut reader: impl BufRead,
+    format: DumpFormat,
+) -> impl Iterator<Item = Result<Block, String>> {
+    let mut failed = false;
+    let mut count = 0;
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let block = read_block(&mut reader, format).transpose()?;
+        count += 1;
+        failed = block.is_err();
+        Some(block.map_err(|e| format!("could not decode record {}: {}", count, e)))
+    })
+}
+
+/// Rebuilds a chain from a dump that starts at its genesis block, checking
+/// each block as it is read against `rules`. Blocks covered by `snapshot` are
+/// only checked to link up to the snapshot's block.
+pub fn import_chain(
+    blocks: impl IntoIterator<Item = Result<Block, String>>,
+    snapshot: Option<&Snapshot>,
+    rules: &ChainRules,
+) -> Result<Blocks, String> {
+    let covered = |block: &Block| snapshot.is_some_and(|snapshot| block.id <= snapshot.block_id);
+    let mut blocks = blocks.into_iter();
+    let genesis = blocks.next().ok_or("the dump is empty")??;
+    if genesis.id != 0 {
+        return Err(format!(
+            "the dump starts at block {}; only dumps from block 0 can be imported",
+            genesis.id
+        ));
+    }
+    let mut balances = match snapshot {
+        Some(snapshot) => snapshot.clone(),
+        None => Snapshot::of(&[]),
+    };
+    if !covered(&genesis) {
+        check_block(rules, &Vec::new(), &mut balances, &genesis)
+            .map_err(|e| format!("block 0: {}", e))?;
+    }
+
+    let mut tree = BlockTree::new(genesis, 0);
+    for block in blocks {
+        let block = block?;
+        let block_id = block.id;
+        if !covered(&block) {
+            check_block(rules, tree.chain(), &mut balances, &block)
+                .map_err(|e| format!("block {}: {}", block_id, e))?;
+        }
+        match tree.insert(block) {
+            Ok(InsertOutcome::Extended) => {}
+            Ok(_) => {
+                return Err(format!(
+                    "block {} does not extend block {}",
+                    block_id,
+                    tree.head().id
+                ))
+            }
+            Err(e) => return Err(format!("block {}: {:?}", block_id, e)),
+        }
+    }
+
+    match snapshot {
+        Some(snapshot) => snapshot.verify(tree.chain())?,
+        None => {
+            check_supply_invariant(None, tree.chain(), NATIVE_ASSET)?;
+        }
+    }
+    Ok(tree.chain().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::Transaction;
+
+    fn create_chain() -> Blocks {
+        let genesis = Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)]);
+        let block = Block::new(1, genesis.hash(), vec![Transaction::new(2, 1, 10.0)]);
+        vec![genesis, block]
+    }
+
+    fn round_trip(chain: &Blocks, format: DumpFormat) -> Result<Blocks, String> {
+        let mut dump = Vec::new();
+        write_blocks(&mut dump, format, chain)?;
+        import_chain(
+            read_blocks(dump.as_slice(), format),
+            None,
+            &ChainRules::default(),
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chain = create_chain();
+        for format in [DumpFormat::Jsonl, DumpFormat::Cbor] {
+            let imported = round_trip(&chain, format).unwrap();
+            let hashes = |blocks: &Blocks| blocks.iter().map(Block::hash).collect::<Vec<_>>();
+            assert_eq!(hashes(&imported), hashes(&chain));
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_dumps() {
+        let chain = create_chain();
+        assert!(round_trip(&chain[1..].to_vec(), DumpFormat::Jsonl).is_err());
+
+        let mut broken_link = chain.clone();
+        broken_link[1].previous_hash = [1; 32];
+        assert!(round_trip(&broken_link, DumpFormat::Jsonl).is_err());
+
+        let mut negative = chain.clone();
+        negative[1].transactions = vec![Transaction::new(1, 2, -10.0)];
+        negative[1].previous_hash = negative[0].hash();
+        assert!(round_trip(&negative, DumpFormat::Cbor).is_err());
+
+        let mut overdraft = chain.clone();
+        overdraft[1].transactions = vec![Transaction::new(2, 1, 150.0)];
+        assert!(round_trip(&overdraft, DumpFormat::Jsonl)
+            .unwrap_err()
+            .contains("overdraws"));
+
+        assert!(import_chain(
+            read_blocks(b"{}\n".as_slice(), DumpFormat::Jsonl),
+            None,
+            &ChainRules::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_import_checks_snapshot() {
+        let chain = create_chain();
+        let mut dump = Vec::new();
+        write_blocks(&mut dump, DumpFormat::Jsonl, &chain).unwrap();
+        let import = |snapshot: &Snapshot| {
+            import_chain(
+                read_blocks(dump.as_slice(), DumpFormat::Jsonl),
+                Some(snapshot),
+                &ChainRules::default(),
+            )
+        };
+
+        assert!(import(&Snapshot::of(&chain)).is_ok());
+        assert!(import(&Snapshot::of(&chain[..1])).is_ok());
+
+        let mut pruned = chain.clone();
+        pruned[1].prune();
+        let mut pruned_dump = Vec::new();
+        write_blocks(&mut pruned_dump, DumpFormat::Jsonl, &pruned).unwrap();
+        let import_pruned = |snapshot: Option<&Snapshot>| {
+            import_chain(
+                read_blocks(pruned_dump.as_slice(), DumpFormat::Jsonl),
+                snapshot,
+                &ChainRules::default(),
+            )
+        };
+        assert!(import_pruned(None).is_err());
+        assert!(import_pruned(Some(&Snapshot::of(&chain))).is_ok());
+
+        let mut overdraft = chain.clone();
+        overdraft.push(Block::new(
+            2,
+            chain[1].hash(),
+            vec![Transaction::new(3, 2, 20.0)],
+        ));
+        let mut overdraft_dump = Vec::new();
+        write_blocks(&mut overdraft_dump, DumpFormat::Jsonl, &overdraft).unwrap();
+        assert!(import_chain(
+            read_blocks(overdraft_dump.as_slice(), DumpFormat::Jsonl),
+            Some(&Snapshot::of(&chain)),
+            &ChainRules::default(),
+        )
+        .is_err());
+
+        let mut other = chain.clone();
+        other[1].nonce = 1;
+        assert!(import(&Snapshot::of(&other)).is_err());
+    }
+}
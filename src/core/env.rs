@@ -0,0 +1,120 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of time for a node: transaction ids, block timestamps, faucet
+/// windows and the pause between interval blocks.
+pub trait Clock: Send + Sync {
+    /// Time since the UNIX epoch
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+
+    fn now_millis(&self) -> u64 {
+        self.now().as_millis() as u64
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Clock that only moves when told to; sleeping advances it instead of waiting.
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start: Duration) -> ManualClock {
+        ManualClock {
+            nanos: AtomicU64::new(start.as_nanos() as u64),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Source of randomness for a node, such as the ids of new accounts.
+pub trait Random: Send + Sync {
+    fn next_u64(&self) -> u64;
+
+    /// Uniform enough for picking among `bound` choices; `bound` must not be 0.
+    fn below(&self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+pub struct SystemRandom;
+
+impl Random for SystemRandom {
+    fn next_u64(&self) -> u64 {
+        rand::random()
+    }
+}
+
+/// Randomness that repeats exactly for the same seed.
+pub struct SeededRandom {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl Random for SeededRandom {
+    fn next_u64(&self) -> u64 {
+        self.rng.lock().unwrap().next_u64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_only_moves_when_told() {
+        let clock = ManualClock::new(Duration::from_secs(10));
+        assert_eq!(clock.now_millis(), 10_000);
+
+        clock.sleep(Duration::from_millis(5));
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(clock.now_millis(), 10_006);
+    }
+
+    #[test]
+    fn test_seeded_random_repeats() {
+        let draw = |seed| {
+            let random = SeededRandom::new(seed);
+            (0..8).map(|_| random.below(1000)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+    }
+}
@@ -0,0 +1,291 @@
+use super::constants::ESCROW_ID;
+use super::keys::verify;
+use super::types::{
+    AccountId, AssetId, BlockId, EscrowTerms, Transaction, TransactionId, TransactionKind,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EscrowState {
+    Open,
+    /// Paid out to the payee, revealing the preimage if one was used
+    Released {
+        preimage: Option<String>,
+    },
+    Refunded,
+}
+
+/// An escrow as recorded by its `EscrowOpen` transaction and any settlement.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Escrow {
+    pub id: TransactionId,
+    pub payer: AccountId,
+    pub amount: f64,
+    pub asset: AssetId,
+    pub terms: EscrowTerms,
+    pub state: EscrowState,
+}
+
+pub fn release_message(escrow: TransactionId) -> Vec<u8> {
+    [b"b-escrow-release:".as_slice(), &escrow.to_be_bytes()].concat()
+}
+
+pub fn refund_message(escrow: TransactionId) -> Vec<u8> {
+    [b"b-escrow-refund:".as_slice(), &escrow.to_be_bytes()].concat()
+}
+
+pub fn hash_preimage(preimage: &str) -> [u8; 32] {
+    Sha256::digest(preimage.as_bytes()).into()
+}
+
+/// Looks up an escrow and its current state among `transactions`.
+pub fn find_escrow<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    escrow_id: TransactionId,
+) -> Option<Escrow> {
+    let mut escrow: Option<Escrow> = None;
+    let mut state = EscrowState::Open;
+    for transaction in transactions {
+        match transaction.kind() {
+            TransactionKind::EscrowOpen(terms) if transaction.id == escrow_id => {
+                escrow = Some(Escrow {
+                    id: transaction.id,
+                    payer: transaction.from(),
+                    amount: transaction.amount(),
+                    asset: transaction.asset(),
+                    terms: terms.clone(),
+                    state: EscrowState::Open,
+                });
+            }
+            TransactionKind::EscrowRelease {
+                escrow: id,
+                preimage,
+                ..
+            } if *id == escrow_id => {
+                state = EscrowState::Released {
+                    preimage: preimage.clone(),
+                }
+            }
+            TransactionKind::EscrowRefund { escrow: id, .. } if *id == escrow_id => {
+                state = EscrowState::Refunded
+            }
+            _ => {}
+        }
+    }
+    escrow.map(|escrow| Escrow { state, ..escrow })
+}
+
+impl EscrowTerms {
+    pub fn validate(&self, height: BlockId) -> Result<(), String> {
+        if self.arbiter.is_none() && self.hashlock.is_none() {
+            return Err("Escrows need an arbiter, a hashlock or both".to_string());
+        }
+        if self.timeout <= height {
+            return Err(format!(
+                "Escrow timeout {} must be after the current block {}",
+                self.timeout, height
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Escrow {
+    fn check_open(&self) -> Result<(), String> {
+        match self.state {
+            EscrowState::Open => Ok(()),
+            _ => Err(format!("Escrow {} is already settled", self.id)),
+        }
+    }
+
+    fn signed_by_arbiter(&self, message: &[u8], signature: Option<&[u8]>) -> bool {
+        match (self.terms.arbiter, signature) {
+            (Some(arbiter), Some(signature)) => verify(&arbiter, message, signature),
+            _ => false,
+        }
+    }
+
+    /// Builds the transaction paying the escrow out to the payee at chain
+    /// height `height`, stamped with `time` (since the UNIX epoch). Needs the arbiter's signature, or the preimage of the
+    /// hashlock before the timeout; both go into the transaction.
+    pub fn release(
+        &self,
+        height: BlockId,
+        time: Duration,
+        preimage: Option<String>,
+        signature: Option<&[u8]>,
+    ) -> Result<Transaction, String> {
+        self.check_open()?;
+
+        let by_arbiter = self.signed_by_arbiter(&release_message(self.id), signature);
+        let by_preimage = match (&self.terms.hashlock, &preimage) {
+            (Some(hashlock), Some(preimage)) => *hashlock == hash_preimage(preimage),
+            _ => false,
+        };
+
+        match (by_arbiter, by_preimage) {
+            (true, _) => {}
+            (false, true) if height < self.terms.timeout => {}
+            (false, true) => {
+                return Err(format!(
+                    "Escrow {} timed out at block {}; it can only be refunded",
+                    self.id, self.terms.timeout
+                ))
+            }
+            (false, false) => {
+                return Err(format!(
+                    "Releasing escrow {} needs the arbiter's signature or the hashlock preimage",
+                    self.id
+                ))
+            }
+        }
+
+        Ok(
+            Transaction::at(self.terms.payee, ESCROW_ID, self.amount, self.asset, time).with_kind(
+                TransactionKind::EscrowRelease {
+                    escrow: self.id,
+                    preimage,
+                    signature: signature.map(<[u8]>::to_vec),
+                },
+            ),
+        )
+    }
+
+    /// Builds the transaction returning the escrow to the payer at chain
+    /// height `height`, stamped with `time`. Needs the arbiter's signature
+    /// before the timeout.
+    pub fn refund(
+        &self,
+        height: BlockId,
+        time: Duration,
+        signature: Option<&[u8]>,
+    ) -> Result<Transaction, String> {
+        self.check_open()?;
+
+        if height < self.terms.timeout
+            && !self.signed_by_arbiter(&refund_message(self.id), signature)
+        {
+            return Err(format!(
+                "Escrow {} can only be refunded by its arbiter before block {}",
+                self.id, self.terms.timeout
+            ));
+        }
+
+        Ok(
+            Transaction::at(self.payer, ESCROW_ID, self.amount, self.asset, time).with_kind(
+                TransactionKind::EscrowRefund {
+                    escrow: self.id,
+                    signature: signature.map(<[u8]>::to_vec),
+                },
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::{generate_secret_key, public_key, sign, SecretKey};
+
+    const NOW: Duration = Duration::from_secs(1_700_000_000);
+
+    fn open_escrow(arbiter: Option<&SecretKey>, preimage: Option<&str>) -> Transaction {
+        Transaction::new(ESCROW_ID, 1, 10.0).with_kind(TransactionKind::EscrowOpen(EscrowTerms {
+            payee: 2,
+            arbiter: arbiter.map(public_key),
+            hashlock: preimage.map(hash_preimage),
+            timeout: 10,
+        }))
+    }
+
+    #[test]
+    fn test_find_escrow_tracks_state() {
+        let open = open_escrow(None, Some("secret"));
+        let escrow = find_escrow([&open], open.id).unwrap();
+
+        assert_eq!(escrow.payer, 1);
+        assert_eq!(escrow.terms.payee, 2);
+        assert_eq!(escrow.state, EscrowState::Open);
+        assert!(find_escrow([&open], open.id + 1).is_none());
+
+        let release = escrow
+            .release(5, NOW, Some("secret".to_string()), None)
+            .unwrap();
+        assert_eq!(release.to(), 2);
+        assert_eq!(release.from(), ESCROW_ID);
+        assert_eq!(
+            find_escrow([&open, &release], open.id).unwrap().state,
+            EscrowState::Released {
+                preimage: Some("secret".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_terms() {
+        let terms = |arbiter, hashlock, timeout| EscrowTerms {
+            payee: 2,
+            arbiter,
+            hashlock,
+            timeout,
+        };
+
+        assert!(terms(None, Some([0; 32]), 10).validate(5).is_ok());
+        assert!(terms(Some([0; 32]), None, 10).validate(5).is_ok());
+        assert!(terms(None, None, 10).validate(5).is_err());
+        assert!(terms(None, Some([0; 32]), 5).validate(5).is_err());
+    }
+
+    #[test]
+    fn test_release_with_preimage_before_timeout() {
+        let open = open_escrow(None, Some("secret"));
+        let escrow = find_escrow([&open], open.id).unwrap();
+
+        assert!(escrow
+            .release(5, NOW, Some("wrong".to_string()), None)
+            .is_err());
+        assert!(escrow.release(5, NOW, None, None).is_err());
+        assert!(escrow
+            .release(10, NOW, Some("secret".to_string()), None)
+            .is_err());
+        assert!(escrow
+            .release(9, NOW, Some("secret".to_string()), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_arbiter_can_release_and_refund() {
+        let arbiter = generate_secret_key();
+        let open = open_escrow(Some(&arbiter), None);
+        let escrow = find_escrow([&open], open.id).unwrap();
+
+        let release = sign(&arbiter, &release_message(open.id));
+        let refund = sign(&arbiter, &refund_message(open.id));
+        let outsider = sign(&generate_secret_key(), &release_message(open.id));
+
+        assert!(escrow.release(20, NOW, None, Some(&release)).is_ok());
+        assert!(escrow.release(5, NOW, None, Some(&refund)).is_err());
+        assert!(escrow.release(5, NOW, None, Some(&outsider)).is_err());
+        assert!(escrow.refund(5, NOW, Some(&refund)).is_ok());
+        assert!(escrow.refund(5, NOW, Some(&release)).is_err());
+    }
+
+    #[test]
+    fn test_refund_after_timeout() {
+        let open = open_escrow(None, Some("secret"));
+        let escrow = find_escrow([&open], open.id).unwrap();
+
+        assert!(escrow.refund(9, NOW, None).is_err());
+        let refund = escrow.refund(10, NOW, None).unwrap();
+        assert_eq!(refund.to(), 1);
+
+        let settled = find_escrow([&open, &refund], open.id).unwrap();
+        assert_eq!(settled.state, EscrowState::Refunded);
+        assert!(settled.refund(10, NOW, None).is_err());
+        assert!(settled
+            .release(5, NOW, Some("secret".to_string()), None)
+            .is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use super::constants::{BURN_ID, DEFAULT_CHAIN_ID, MASTER_ID};
+use super::types::{AccountId, Block, Hash, Transaction};
+use super::validation::validate_balance;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Parameters every node of a chain has to agree on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainParameters {
+    /// Seconds between blocks (target block time with proof-of-work)
+    pub block_interval: u64,
+    /// Flat fee burned for every transfer, paid by the sender
+    pub transfer_fee: f64,
+    /// Maximum number of mempool transactions sealed into one block
+    pub max_block_transactions: usize,
+    /// Largest amount a single transfer may move
+    pub max_transfer_amount: Option<f64>,
+    /// Total amount that can ever be minted from MASTER_ID
+    pub supply_cap: Option<f64>,
+}
+
+impl Default for ChainParameters {
+    fn default() -> ChainParameters {
+        ChainParameters {
+            block_interval: 10,
+            transfer_fee: 0.0,
+            max_block_transactions: 1000,
+            max_transfer_amount: None,
+            supply_cap: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisAccount {
+    pub id: AccountId,
+    pub balance: f64,
+}
+
+/// Initial state of a chain, usually read from a TOML file:
+///
+/// ```toml
+/// chain_id = "classroom"
+///
+/// [parameters]
+/// block_interval = 5
+/// transfer_fee = 0.1
+///
+/// [[accounts]]
+/// id = 12345
+/// balance = 1000.0
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Genesis {
+    pub chain_id: String,
+    #[serde(default)]
+    pub parameters: ChainParameters,
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccount>,
+}
+
+impl Genesis {
+    /// Genesis used when no file is given: no accounts and the given block interval.
+    pub fn local(block_interval: u64) -> Genesis {
+        Genesis {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            parameters: ChainParameters {
+                block_interval,
+                ..ChainParameters::default()
+            },
+            accounts: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Genesis, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read genesis file {}: {}", path.display(), e))?;
+        let genesis: Genesis = toml::from_str(&contents)
+            .map_err(|e| format!("could not parse genesis file {}: {}", path.display(), e))?;
+        genesis.validate()?;
+        Ok(genesis)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.chain_id.is_empty() {
+            return Err("chain_id must not be empty".to_string());
+        }
+        if self.parameters.block_interval == 0 {
+            return Err("block_interval must be at least 1 second".to_string());
+        }
+        if !(self.parameters.transfer_fee >= 0.0 && self.parameters.transfer_fee.is_finite()) {
+            return Err("transfer_fee must be a non-negative number".to_string());
+        }
+        if self.parameters.max_block_transactions == 0 {
+            return Err("max_block_transactions must be at least 1".to_string());
+        }
+        if let Some(max) = self.parameters.max_transfer_amount {
+            if max.is_nan() || max <= 0.0 {
+                return Err("max_transfer_amount must be positive".to_string());
+            }
+        }
+
+        if let Some(cap) = self.parameters.supply_cap {
+            if cap.is_nan() || cap <= 0.0 {
+                return Err("supply_cap must be positive".to_string());
+            }
+            let allocated: f64 = self.accounts.iter().map(|account| account.balance).sum();
+            if allocated > cap {
+                return Err(format!(
+                    "genesis allocates {} which exceeds the supply cap of {}",
+                    allocated, cap
+                ));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for account in &self.accounts {
+            if account.id == MASTER_ID || account.id == BURN_ID {
+                return Err(format!("account id {} is reserved", account.id));
+            }
+            if !seen.insert(account.id) {
+                return Err(format!("account {} is listed more than once", account.id));
+            }
+            validate_balance(account.balance)
+                .map_err(|e| format!("account {}: {}", account.id, e))?;
+        }
+        Ok(())
+    }
+
+    /// Block 0 of the chain. It is fully determined by the genesis
+    /// configuration: its `previous_hash` commits to the chain id and
+    /// parameters and it holds one allocation per account.
+    pub fn block(&self) -> Block {
+        let mut hasher = Sha256::new();
+        hasher.update(
+            bincode::serialize(&(&self.chain_id, &self.parameters))
+                .expect("Failed to serialize chain parameters"),
+        );
+        let config_hash: Hash = hasher.finalize().into();
+
+        let transactions = self
+            .accounts
+            .iter()
+            .map(|account| Transaction::genesis(account.id, account.balance))
+            .collect();
+
+        Block::new(0, config_hash, transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::constants::NATIVE_ASSET;
+    use crate::core::traits::TransactionInfo;
+
+    fn create_genesis() -> Genesis {
+        toml::from_str(
+            r#"
+            chain_id = "test"
+
+            [parameters]
+            block_interval = 5
+            transfer_fee = 0.5
+
+            [[accounts]]
+            id = 1
+            balance = 100.0
+
+            [[accounts]]
+            id = 2
+            balance = 50.0
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_fills_default_parameters() {
+        let genesis = create_genesis();
+
+        assert_eq!(genesis.parameters.block_interval, 5);
+        assert_eq!(genesis.parameters.transfer_fee, 0.5);
+        assert_eq!(genesis.parameters.max_block_transactions, 1000);
+        assert_eq!(genesis.accounts.len(), 2);
+        assert!(genesis.validate().is_ok());
+    }
+
+    #[test]
+    fn test_block_is_deterministic() {
+        let genesis = create_genesis();
+        assert_eq!(genesis.block().hash(), genesis.block().hash());
+    }
+
+    #[test]
+    fn test_block_allocates_balances() {
+        let block = create_genesis().block();
+
+        assert_eq!(block.id, 0);
+        assert_eq!(block.calculate_total(1, NATIVE_ASSET), Some(100.0));
+        assert_eq!(block.calculate_total(2, NATIVE_ASSET), Some(50.0));
+    }
+
+    #[test]
+    fn test_block_hash_depends_on_chain_id_and_parameters() {
+        let genesis = create_genesis();
+
+        let mut other_chain = genesis.clone();
+        other_chain.chain_id = "other".to_string();
+        assert_ne!(genesis.block().hash(), other_chain.block().hash());
+
+        let mut other_fee = genesis.clone();
+        other_fee.parameters.transfer_fee = 1.0;
+        assert_ne!(genesis.block().hash(), other_fee.block().hash());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_accounts() {
+        let mut genesis = create_genesis();
+        genesis.accounts.push(GenesisAccount {
+            id: 1,
+            balance: 1.0,
+        });
+        assert!(genesis.validate().is_err());
+
+        let mut genesis = create_genesis();
+        genesis.accounts[0].id = MASTER_ID;
+        assert!(genesis.validate().is_err());
+
+        let mut genesis = create_genesis();
+        genesis.accounts[0].balance = -1.0;
+        assert!(genesis.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_parameters() {
+        let mut genesis = create_genesis();
+        genesis.parameters.block_interval = 0;
+        assert!(genesis.validate().is_err());
+
+        let mut genesis = create_genesis();
+        genesis.parameters.transfer_fee = f64::NAN;
+        assert!(genesis.validate().is_err());
+
+        let mut genesis = create_genesis();
+        genesis.parameters.supply_cap = Some(120.0);
+        assert!(genesis.validate().is_err());
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let result: Result<Genesis, _> = toml::from_str("chain_id = \"x\"\nblock_time = 3\n");
+        assert!(result.is_err());
+    }
+}
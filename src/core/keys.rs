@@ -0,0 +1,68 @@
+use super::utilities::from_hex;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+pub type PublicKey = [u8; 32];
+pub type SecretKey = [u8; 32];
+
+pub fn generate_secret_key() -> SecretKey {
+    rand::random()
+}
+
+pub fn public_key(secret: &SecretKey) -> PublicKey {
+    SigningKey::from_bytes(secret).verifying_key().to_bytes()
+}
+
+pub fn parse_key(hex: &str) -> Result<[u8; 32], String> {
+    from_hex(hex)?
+        .try_into()
+        .map_err(|_| "keys must be 32 bytes (64 hex characters)".to_string())
+}
+
+pub fn sign(secret: &SecretKey, message: &[u8]) -> Vec<u8> {
+    SigningKey::from_bytes(secret)
+        .sign(message)
+        .to_bytes()
+        .to_vec()
+}
+
+pub fn verify(signer: &PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    let key = match VerifyingKey::from_bytes(signer) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let signature = match Signature::from_slice(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    key.verify(message, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::utilities::to_hex;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = generate_secret_key();
+        let signer = public_key(&key);
+        let signature = sign(&key, b"message");
+
+        assert!(verify(&signer, b"message", &signature));
+        assert!(!verify(&signer, b"other message", &signature));
+        assert!(!verify(&signer, b"message", &signature[1..]));
+        assert!(!verify(
+            &public_key(&generate_secret_key()),
+            b"message",
+            &signature
+        ));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key = generate_secret_key();
+
+        assert_eq!(parse_key(&to_hex(&key)), Ok(key));
+        assert!(parse_key("abcd").is_err());
+    }
+}
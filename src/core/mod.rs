@@ -1,4 +1,23 @@
+pub mod accounts;
+pub mod address;
+pub mod admin;
+pub mod block_tree;
 pub mod constants;
+pub mod contracts;
+pub mod dump;
+pub mod env;
+pub mod escrow;
+pub mod genesis;
+pub mod keys;
+pub mod monetary;
+pub mod multisig;
+pub mod names;
+pub mod pow;
+pub mod rules;
+pub mod schedule;
+pub mod snapshot;
 pub mod traits;
 pub mod types;
 pub mod utilities;
+pub mod validation;
+pub mod vm;
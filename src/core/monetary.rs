@@ -0,0 +1,332 @@
+use super::constants::{BURN_ID, MASTER_ID};
+use super::snapshot::Snapshot;
+use super::types::{AccountId, AssetId, Blocks, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Money supply of one asset derived from transactions.
+///
+/// Everything sent from `MASTER_ID` is minted and everything sent to
+/// `BURN_ID` is burned; the rest is in circulation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Supply {
+    pub minted: f64,
+    pub burned: f64,
+    pub circulating: f64,
+}
+
+impl Supply {
+    pub fn of<'a>(
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+        asset: AssetId,
+    ) -> Supply {
+        let mut supply = Supply::default();
+        for transaction in transactions.into_iter().filter(|t| t.asset() == asset) {
+            if transaction.from() == MASTER_ID {
+                supply.minted += transaction.amount();
+            }
+            if transaction.to() == BURN_ID {
+                supply.burned += transaction.amount();
+            }
+        }
+        supply.circulating = supply.minted - supply.burned;
+        supply
+    }
+
+    pub fn of_chain(chain: &Blocks, asset: AssetId) -> Supply {
+        Supply::of(
+            chain.iter().flat_map(|block| block.all_transactions()),
+            asset,
+        )
+    }
+}
+
+pub fn balances<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    asset: AssetId,
+) -> HashMap<AccountId, f64> {
+    let mut balances = HashMap::new();
+    for transaction in transactions.into_iter().filter(|t| t.asset() == asset) {
+        *balances.entry(transaction.to()).or_insert(0.0) += transaction.amount();
+        *balances.entry(transaction.from()).or_insert(0.0) -= transaction.amount();
+    }
+    balances
+}
+
+/// Replays `chain` from `snapshot` (or from its genesis block) and checks
+/// that no block overdraws an account, and that what the accounts hold plus
+/// what was burned adds up to what was minted: funds sent back to
+/// `MASTER_ID` or out of `BURN_ID` break it. Returns the supply of `asset`.
+pub fn check_supply_invariant(
+    snapshot: Option<&Snapshot>,
+    chain: &Blocks,
+    asset: AssetId,
+) -> Result<Supply, String> {
+    let (mut replayed, start) = match snapshot {
+        Some(snapshot) => (snapshot.clone(), snapshot.block_id as usize + 1),
+        None => (Snapshot::of(&[]), 0),
+    };
+    let balance = |replayed: &Snapshot, account| {
+        replayed
+            .balances
+            .get(&asset)
+            .and_then(|balances| balances.get(&account))
+            .copied()
+            .unwrap_or(0.0)
+    };
+    let mut supply = Supply {
+        minted: -balance(&replayed, MASTER_ID),
+        burned: balance(&replayed, BURN_ID),
+        circulating: 0.0,
+    };
+    for block in chain.iter().skip(start) {
+        replayed
+            .spend(block.all_transactions())
+            .map_err(|e| format!("supply invariant violated: block {}: {}", block.id, e))?;
+        let flows = Supply::of(block.all_transactions(), asset);
+        supply.minted += flows.minted;
+        supply.burned += flows.burned;
+    }
+    supply.circulating = supply.minted - supply.burned;
+
+    let held: f64 = replayed
+        .balances
+        .get(&asset)
+        .into_iter()
+        .flatten()
+        .filter(|(account, _)| **account != MASTER_ID && **account != BURN_ID)
+        .map(|(_, balance)| balance)
+        .sum();
+    let tolerance = 1e-9 * supply.minted.abs().max(1.0);
+    match (held + supply.burned - supply.minted).abs() <= tolerance {
+        true => Ok(supply),
+        false => Err(format!(
+            "supply invariant violated: accounts hold {} and {} was burned, but {} was minted",
+            held, supply.burned, supply.minted
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonetaryPolicy {
+    pub supply_cap: Option<f64>,
+}
+
+impl MonetaryPolicy {
+    /// Amount that can still be minted, `None` if the supply is uncapped.
+    pub fn remaining(&self, supply: &Supply) -> Option<f64> {
+        self.supply_cap.map(|cap| (cap - supply.minted).max(0.0))
+    }
+
+    pub fn check_mint(&self, supply: &Supply, amount: f64) -> Result<(), String> {
+        match self.remaining(supply) {
+            Some(remaining) if amount > remaining => Err(format!(
+                "Minting {} would exceed the supply cap; only {} left",
+                amount, remaining
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Coinbase reward that fits under the cap, `None` once the cap is reached.
+    pub fn coinbase_reward(&self, supply: &Supply, reward: f64) -> Option<f64> {
+        let reward = match self.remaining(supply) {
+            Some(remaining) => reward.min(remaining),
+            None => reward,
+        };
+        (reward > 0.0).then_some(reward)
+    }
+}
+
+/// Limits how much the node mints through `CreateAccount` within a sliding
+/// time window. Clients cannot be told apart (the node only listens on
+/// localhost and accounts are new), so the limit is shared by all of them.
+#[derive(Debug)]
+pub struct FaucetLimiter {
+    limit: Option<f64>,
+    window: Duration,
+    /// Grants with their time since the UNIX epoch
+    grants: Vec<(Duration, f64)>,
+}
+
+impl FaucetLimiter {
+    pub fn new(limit: Option<f64>, window: Duration) -> FaucetLimiter {
+        FaucetLimiter {
+            limit,
+            window,
+            grants: Vec::new(),
+        }
+    }
+
+    pub fn try_grant(&mut self, amount: f64, now: Duration) -> Result<(), String> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let window = self.window;
+        self.grants
+            .retain(|(granted_at, _)| now.saturating_sub(*granted_at) < window);
+
+        let granted: f64 = self.grants.iter().map(|(_, amount)| amount).sum();
+        if granted + amount > limit {
+            return Err(format!(
+                "Faucet limit reached: {} of {} granted in the last {} seconds",
+                granted,
+                limit,
+                window.as_secs()
+            ));
+        }
+
+        self.grants.push((now, amount));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::constants::NATIVE_ASSET;
+    use crate::core::types::Block;
+
+    fn create_chain() -> Blocks {
+        let genesis = Block::new(
+            0,
+            [0; 32],
+            vec![
+                Transaction::genesis(1, 100.0),
+                Transaction::genesis(2, 50.0),
+            ],
+        );
+
+        let mut block = Block::new(
+            1,
+            genesis.hash(),
+            vec![
+                Transaction::new(2, 1, 10.0),
+                Transaction::new(BURN_ID, 1, 0.5),
+                Transaction::with_asset(1, MASTER_ID, 1000.0, 7),
+            ],
+        );
+        block.coinbase = Some(Transaction::new(3, MASTER_ID, 25.0));
+
+        vec![genesis, block]
+    }
+
+    #[test]
+    fn test_supply_of_chain() {
+        let supply = Supply::of_chain(&create_chain(), NATIVE_ASSET);
+
+        assert_eq!(supply.minted, 175.0);
+        assert_eq!(supply.burned, 0.5);
+        assert_eq!(supply.circulating, 174.5);
+    }
+
+    #[test]
+    fn test_supply_is_per_asset() {
+        let supply = Supply::of_chain(&create_chain(), 7);
+
+        assert_eq!(supply.minted, 1000.0);
+        assert_eq!(supply.burned, 0.0);
+        assert!(check_supply_invariant(None, &create_chain(), 7).is_ok());
+    }
+
+    #[test]
+    fn test_supply_invariant_holds() {
+        assert!(check_supply_invariant(None, &create_chain(), NATIVE_ASSET).is_ok());
+        assert!(check_supply_invariant(None, &Vec::new(), NATIVE_ASSET).is_ok());
+
+        let chain = create_chain();
+        let snapshot = Snapshot::of(&chain[..1]);
+        assert_eq!(
+            check_supply_invariant(Some(&snapshot), &chain, NATIVE_ASSET),
+            check_supply_invariant(None, &chain, NATIVE_ASSET)
+        );
+    }
+
+    #[test]
+    fn test_supply_invariant_catches_overdrafts() {
+        let mut chain = create_chain();
+        chain[1].transactions.push(Transaction::new(1, 2, 100.0));
+        assert!(check_supply_invariant(None, &chain, NATIVE_ASSET).is_err());
+    }
+
+    #[test]
+    fn test_supply_invariant_catches_unminting() {
+        for transaction in [
+            Transaction::new(MASTER_ID, 1, 10.0),
+            Transaction::new(1, BURN_ID, 0.5),
+        ] {
+            let mut chain = create_chain();
+            chain[1].transactions.push(transaction);
+            assert!(check_supply_invariant(None, &chain, NATIVE_ASSET).is_err());
+        }
+    }
+
+    #[test]
+    fn test_balances() {
+        let chain = create_chain();
+        let balances = balances(
+            chain.iter().flat_map(|block| block.all_transactions()),
+            NATIVE_ASSET,
+        );
+
+        assert_eq!(balances[&1], 89.5);
+        assert_eq!(balances[&2], 60.0);
+        assert_eq!(balances[&3], 25.0);
+        assert_eq!(balances[&BURN_ID], 0.5);
+        assert_eq!(balances[&MASTER_ID], -175.0);
+    }
+
+    #[test]
+    fn test_policy_without_cap_allows_everything() {
+        let policy = MonetaryPolicy::default();
+        let supply = Supply::of_chain(&create_chain(), NATIVE_ASSET);
+
+        assert!(policy.check_mint(&supply, 1e12).is_ok());
+        assert_eq!(policy.coinbase_reward(&supply, 50.0), Some(50.0));
+    }
+
+    #[test]
+    fn test_policy_enforces_cap() {
+        let policy = MonetaryPolicy {
+            supply_cap: Some(200.0),
+        };
+        let supply = Supply::of_chain(&create_chain(), NATIVE_ASSET);
+
+        assert_eq!(policy.remaining(&supply), Some(25.0));
+        assert!(policy.check_mint(&supply, 25.0).is_ok());
+        assert!(policy.check_mint(&supply, 25.5).is_err());
+        assert_eq!(policy.coinbase_reward(&supply, 50.0), Some(25.0));
+
+        let exhausted = Supply {
+            minted: 200.0,
+            ..supply
+        };
+        assert_eq!(policy.coinbase_reward(&exhausted, 50.0), None);
+    }
+
+    #[test]
+    fn test_faucet_limiter() {
+        let mut limiter = FaucetLimiter::new(Some(100.0), Duration::from_secs(60));
+        let start = Duration::from_secs(1_000);
+
+        assert!(limiter.try_grant(60.0, start).is_ok());
+        assert!(limiter.try_grant(50.0, start).is_err());
+        assert!(limiter.try_grant(40.0, start).is_ok());
+        assert!(limiter
+            .try_grant(50.0, start + Duration::from_secs(30))
+            .is_err());
+        assert!(limiter
+            .try_grant(50.0, start + Duration::from_secs(61))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_faucet_limiter_without_limit() {
+        let mut limiter = FaucetLimiter::new(None, Duration::from_secs(60));
+
+        assert!(limiter.try_grant(1e12, Duration::ZERO).is_ok());
+    }
+}
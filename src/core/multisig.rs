@@ -0,0 +1,375 @@
+use super::address::to_address;
+use super::keys::{public_key, sign, verify, PublicKey, SecretKey};
+use super::types::{AccountId, AssetId, BlockId, Id};
+use super::utilities::generate_id;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Message a signer signs to approve a proposal; it covers the transfer the
+/// proposal makes, so an approval cannot be used for another transfer.
+fn approval_message(proposal_id: Id, to: AccountId, amount: f64, asset: AssetId) -> Vec<u8> {
+    [
+        b"b-approve:".as_slice(),
+        &proposal_id.to_be_bytes(),
+        &to.to_be_bytes(),
+        &amount.to_be_bytes(),
+        &asset.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Signs the approval of a proposal to transfer `amount` of `asset` to `to`,
+/// returning the signer's public key and the signature.
+pub fn sign_approval(
+    secret: &SecretKey,
+    proposal_id: Id,
+    to: AccountId,
+    amount: f64,
+    asset: AssetId,
+) -> (PublicKey, Vec<u8>) {
+    (
+        public_key(secret),
+        sign(secret, &approval_message(proposal_id, to, amount, asset)),
+    )
+}
+
+pub fn verify_approval(signer: &PublicKey, proposal: &Proposal, signature: &[u8]) -> bool {
+    verify(
+        signer,
+        &approval_message(proposal.id, proposal.to, proposal.amount, proposal.asset),
+        signature,
+    )
+}
+
+/// Outgoing transfers of an account need approvals from `threshold` of `signers`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    pub threshold: usize,
+    pub signers: Vec<PublicKey>,
+}
+
+impl MultisigPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut signers = self.signers.clone();
+        signers.sort();
+        signers.dedup();
+        if signers.len() != self.signers.len() {
+            return Err("Signers must not be listed more than once".to_string());
+        }
+        if self.threshold == 0 || self.threshold > self.signers.len() {
+            return Err(format!(
+                "Threshold must be between 1 and the number of signers ({})",
+                self.signers.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Transfer out of a multisig account waiting for approvals.
+#[derive(Debug, Clone)]
+pub struct Proposal {
+    pub id: Id,
+    pub from: AccountId,
+    pub to: AccountId,
+    pub amount: f64,
+    pub asset: AssetId,
+    /// Last block id at which the proposal can still be approved
+    pub expires_at: BlockId,
+    pub approvals: Vec<PublicKey>,
+}
+
+#[derive(Debug)]
+pub enum ApprovalOutcome {
+    Pending {
+        approvals: usize,
+        threshold: usize,
+    },
+    /// The threshold was met and the proposal is ready to execute; it stays
+    /// open until `complete` or `withdraw` is called
+    Approved(Proposal),
+}
+
+/// Multisig policies and open proposals held by the node.
+#[derive(Debug, Default)]
+pub struct Multisig {
+    policies: HashMap<AccountId, MultisigPolicy>,
+    proposals: HashMap<Id, Proposal>,
+}
+
+impl Multisig {
+    pub fn register(&mut self, account: AccountId, policy: MultisigPolicy) -> Result<(), String> {
+        policy.validate()?;
+        if self.policies.contains_key(&account) {
+            return Err(format!(
+                "Account {} is already a multisig account",
+                to_address(account)
+            ));
+        }
+        self.policies.insert(account, policy);
+        Ok(())
+    }
+
+    pub fn policy(&self, account: AccountId) -> Option<&MultisigPolicy> {
+        self.policies.get(&account)
+    }
+
+    pub fn propose(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        amount: f64,
+        asset: AssetId,
+        expires_at: BlockId,
+        now: Duration,
+    ) -> Result<Id, String> {
+        if !self.policies.contains_key(&from) {
+            return Err(format!(
+                "Account {} is not a multisig account",
+                to_address(from)
+            ));
+        }
+
+        let id = generate_id(to, from, amount, asset, now);
+        self.proposals.insert(
+            id,
+            Proposal {
+                id,
+                from,
+                to,
+                amount,
+                asset,
+                expires_at,
+                approvals: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Records a signed approval made while the chain is at `height`.
+    pub fn approve(
+        &mut self,
+        proposal_id: Id,
+        signer: &PublicKey,
+        signature: &[u8],
+        height: BlockId,
+    ) -> Result<ApprovalOutcome, String> {
+        self.prune_expired(height);
+
+        let proposal = self
+            .proposals
+            .get_mut(&proposal_id)
+            .ok_or_else(|| format!("Proposal not found or expired: {}", proposal_id))?;
+        let policy = &self.policies[&proposal.from];
+
+        if !policy.signers.contains(signer) {
+            return Err(format!(
+                "Key is not a signer of account {}",
+                to_address(proposal.from)
+            ));
+        }
+        if !verify_approval(signer, proposal, signature) {
+            return Err(
+                "Invalid signature; it has to cover the proposal's recipient, amount and asset"
+                    .to_string(),
+            );
+        }
+        if proposal.approvals.contains(signer) {
+            return Err(format!(
+                "Proposal {} was already approved by this key",
+                proposal_id
+            ));
+        }
+        if proposal.approvals.len() >= policy.threshold {
+            return Err(format!(
+                "Proposal {} is already being executed",
+                proposal_id
+            ));
+        }
+
+        proposal.approvals.push(*signer);
+        match proposal.approvals.len() >= policy.threshold {
+            true => Ok(ApprovalOutcome::Approved(proposal.clone())),
+            false => Ok(ApprovalOutcome::Pending {
+                approvals: proposal.approvals.len(),
+                threshold: policy.threshold,
+            }),
+        }
+    }
+
+    /// Removes an approved proposal once its transfer was submitted.
+    pub fn complete(&mut self, proposal_id: Id) {
+        self.proposals.remove(&proposal_id);
+    }
+
+    /// Takes back the approval of `signer` after the transfer of the approved
+    /// proposal could not be submitted, so that it can be approved again.
+    pub fn withdraw(&mut self, proposal_id: Id, signer: &PublicKey) {
+        if let Some(proposal) = self.proposals.get_mut(&proposal_id) {
+            proposal.approvals.retain(|approval| approval != signer);
+        }
+    }
+
+    pub fn prune_expired(&mut self, height: BlockId) {
+        self.proposals
+            .retain(|_, proposal| proposal.expires_at >= height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::constants::NATIVE_ASSET;
+    use crate::core::keys::generate_secret_key;
+
+    fn create_multisig(keys: &[SecretKey], threshold: usize) -> Multisig {
+        let mut multisig = Multisig::default();
+        multisig
+            .register(
+                1,
+                MultisigPolicy {
+                    threshold,
+                    signers: keys.iter().map(public_key).collect(),
+                },
+            )
+            .unwrap();
+        multisig
+    }
+
+    fn approve(
+        multisig: &mut Multisig,
+        key: &SecretKey,
+        id: Id,
+    ) -> Result<ApprovalOutcome, String> {
+        let (signer, signature) = sign_approval(key, id, 2, 10.0, NATIVE_ASSET);
+        multisig.approve(id, &signer, &signature, 1)
+    }
+
+    #[test]
+    fn test_sign_and_verify_approval() {
+        let key = generate_secret_key();
+        let proposal = Proposal {
+            id: 7,
+            from: 1,
+            to: 2,
+            amount: 10.0,
+            asset: NATIVE_ASSET,
+            expires_at: 5,
+            approvals: Vec::new(),
+        };
+        let (signer, signature) = sign_approval(&key, 7, 2, 10.0, NATIVE_ASSET);
+
+        assert_eq!(signer, public_key(&key));
+        assert!(verify_approval(&signer, &proposal, &signature));
+        assert!(!verify_approval(&signer, &proposal, &signature[1..]));
+        for other in [
+            Proposal {
+                id: 8,
+                ..proposal.clone()
+            },
+            Proposal {
+                to: 3,
+                ..proposal.clone()
+            },
+            Proposal {
+                amount: 100.0,
+                ..proposal.clone()
+            },
+            Proposal {
+                asset: 9,
+                ..proposal.clone()
+            },
+        ] {
+            assert!(!verify_approval(&signer, &other, &signature));
+        }
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let keys = [generate_secret_key(), generate_secret_key()];
+        let signers: Vec<PublicKey> = keys.iter().map(public_key).collect();
+
+        let policy = |threshold, signers: &[PublicKey]| MultisigPolicy {
+            threshold,
+            signers: signers.to_vec(),
+        };
+        assert!(policy(2, &signers).validate().is_ok());
+        assert!(policy(0, &signers).validate().is_err());
+        assert!(policy(3, &signers).validate().is_err());
+        assert!(policy(1, &[signers[0], signers[0]]).validate().is_err());
+    }
+
+    #[test]
+    fn test_threshold_approval() {
+        let keys = [
+            generate_secret_key(),
+            generate_secret_key(),
+            generate_secret_key(),
+        ];
+        let mut multisig = create_multisig(&keys, 2);
+        let id = multisig
+            .propose(1, 2, 10.0, NATIVE_ASSET, 5, Duration::ZERO)
+            .unwrap();
+
+        assert!(matches!(
+            approve(&mut multisig, &keys[0], id),
+            Ok(ApprovalOutcome::Pending {
+                approvals: 1,
+                threshold: 2
+            })
+        ));
+        assert!(approve(&mut multisig, &keys[0], id).is_err());
+
+        match approve(&mut multisig, &keys[2], id) {
+            Ok(ApprovalOutcome::Approved(proposal)) => assert_eq!(proposal.amount, 10.0),
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+        assert!(approve(&mut multisig, &keys[1], id).is_err());
+
+        multisig.withdraw(id, &public_key(&keys[2]));
+        assert!(matches!(
+            approve(&mut multisig, &keys[1], id),
+            Ok(ApprovalOutcome::Approved(_))
+        ));
+        multisig.complete(id);
+        assert!(approve(&mut multisig, &keys[2], id).is_err());
+    }
+
+    #[test]
+    fn test_approval_rejects_outsiders_and_bad_signatures() {
+        let keys = [generate_secret_key()];
+        let mut multisig = create_multisig(&keys, 1);
+        let id = multisig
+            .propose(1, 2, 10.0, NATIVE_ASSET, 5, Duration::ZERO)
+            .unwrap();
+
+        assert!(approve(&mut multisig, &generate_secret_key(), id).is_err());
+
+        let (signer, signature) = sign_approval(&keys[0], id + 1, 2, 10.0, NATIVE_ASSET);
+        assert!(multisig.approve(id, &signer, &signature, 1).is_err());
+
+        let (signer, signature) = sign_approval(&keys[0], id, 3, 10.0, NATIVE_ASSET);
+        assert!(multisig.approve(id, &signer, &signature, 1).is_err());
+    }
+
+    #[test]
+    fn test_proposals_expire() {
+        let keys = [generate_secret_key()];
+        let mut multisig = create_multisig(&keys, 1);
+        let id = multisig
+            .propose(1, 2, 10.0, NATIVE_ASSET, 5, Duration::ZERO)
+            .unwrap();
+        let (signer, signature) = sign_approval(&keys[0], id, 2, 10.0, NATIVE_ASSET);
+
+        assert!(multisig.approve(id, &signer, &signature, 6).is_err());
+    }
+
+    #[test]
+    fn test_propose_requires_multisig_account() {
+        let mut multisig = Multisig::default();
+        assert!(multisig
+            .propose(1, 2, 10.0, NATIVE_ASSET, 5, Duration::ZERO)
+            .is_err());
+    }
+}
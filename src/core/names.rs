@@ -0,0 +1,139 @@
+use super::address::{parse_account_id, to_address, ADDRESS_PREFIX};
+use super::types::{AccountId, Transaction, TransactionKind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+pub const MIN_NAME_LENGTH: usize = 3;
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// An account given by its address or a registered name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountRef {
+    Id(AccountId),
+    Name(String),
+}
+
+impl FromStr for AccountRef {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.starts_with(|c: char| c == ADDRESS_PREFIX || c.is_ascii_digit()) {
+            true => parse_account_id(value).map(AccountRef::Id),
+            false => {
+                validate_name(value)?;
+                Ok(AccountRef::Name(value.to_string()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for AccountRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountRef::Id(id) => write!(f, "{}", to_address(*id)),
+            AccountRef::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Names are lowercase ASCII letters, digits, `-` and `_`, and start with a
+/// letter so they can never be mistaken for an account id.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if !(MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&name.len()) {
+        return Err(format!(
+            "Names must be {} to {} characters long",
+            MIN_NAME_LENGTH, MAX_NAME_LENGTH
+        ));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_lowercase())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid name {}: use lowercase letters, digits, '-' and '_', starting with a letter",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Owner of every name registered among `transactions`.
+pub fn name_registry<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+) -> BTreeMap<String, AccountId> {
+    let mut registry = BTreeMap::new();
+    for transaction in transactions {
+        match transaction.kind() {
+            TransactionKind::RegisterName { name } => {
+                registry
+                    .entry(name.clone())
+                    .or_insert_with(|| transaction.from());
+            }
+            TransactionKind::TransferName { name }
+                if registry.get(name) == Some(&transaction.from()) =>
+            {
+                registry.insert(name.clone(), transaction.to());
+            }
+            _ => {}
+        }
+    }
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(account: AccountId, name: &str) -> Transaction {
+        Transaction::new(account, account, 0.0).with_kind(TransactionKind::RegisterName {
+            name: name.to_string(),
+        })
+    }
+
+    fn transfer(from: AccountId, to: AccountId, name: &str) -> Transaction {
+        Transaction::new(to, from, 0.0).with_kind(TransactionKind::TransferName {
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_parse_account_ref() {
+        assert!("12345".parse::<AccountRef>().is_err());
+        assert_eq!(to_address(12345).parse(), Ok(AccountRef::Id(12345)));
+        assert!(to_address(12345)[..6].parse::<AccountRef>().is_err());
+        assert_eq!("alice".parse(), Ok(AccountRef::Name("alice".to_string())));
+        assert!("Alice".parse::<AccountRef>().is_err());
+        assert!("-1".parse::<AccountRef>().is_err());
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("alice-2").is_ok());
+        assert!(validate_name("ab").is_err());
+        assert!(validate_name("2alice").is_err());
+        assert!(validate_name("al ice").is_err());
+        assert!(validate_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_first_registration_wins() {
+        let registry = name_registry(&[register(1, "alice"), register(2, "alice")]);
+
+        assert_eq!(registry.get("alice"), Some(&1));
+    }
+
+    #[test]
+    fn test_only_the_owner_transfers_a_name() {
+        let registry = name_registry(&[
+            register(1, "alice"),
+            transfer(2, 3, "alice"),
+            transfer(1, 4, "alice"),
+            transfer(1, 5, "alice"),
+        ]);
+
+        assert_eq!(registry.get("alice"), Some(&4));
+    }
+}
@@ -0,0 +1,130 @@
+use super::constants::{MAX_DIFFICULTY, RETARGET_WINDOW};
+use super::types::{Block, Blocks, Hash};
+
+pub fn leading_zero_bits(hash: &Hash) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte == 0 {
+            bits += 8;
+            continue;
+        }
+        bits += byte.leading_zeros();
+        break;
+    }
+    bits
+}
+
+pub fn meets_difficulty(hash: &Hash, difficulty: u32) -> bool {
+    leading_zero_bits(hash) >= difficulty
+}
+
+/// Increments the nonce of `block` until its hash meets `block.difficulty`.
+pub fn mine(block: &mut Block) -> Hash {
+    loop {
+        let hash = block.hash();
+        if meets_difficulty(&hash, block.difficulty) {
+            return hash;
+        }
+        block.nonce = block.nonce.wrapping_add(1);
+    }
+}
+
+/// Difficulty for the block following `blocks`.
+///
+/// Every `RETARGET_WINDOW` blocks the average block time of the window is
+/// compared to `target_interval` (milliseconds); the difficulty goes up by one
+/// bit when blocks came more than twice as fast and down by one bit when they
+/// came more than twice as slow.
+pub fn retarget(blocks: &Blocks, initial_difficulty: u32, target_interval: u64) -> u32 {
+    let last = match blocks.last() {
+        Some(block) if block.difficulty > 0 => block,
+        _ => return initial_difficulty,
+    };
+
+    let window = RETARGET_WINDOW as usize;
+    if blocks.len() < window + 1 || !blocks.len().is_multiple_of(window) {
+        return last.difficulty;
+    }
+
+    let first = &blocks[blocks.len() - window - 1];
+    let average = last.timestamp.saturating_sub(first.timestamp) / RETARGET_WINDOW;
+
+    if average < target_interval / 2 {
+        (last.difficulty + 1).min(MAX_DIFFICULTY)
+    } else if average > target_interval.saturating_mul(2) {
+        (last.difficulty - 1).max(1)
+    } else {
+        last.difficulty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_blocks(count: usize, spacing: u64, difficulty: u32) -> Blocks {
+        (0..count)
+            .map(|id| {
+                let mut block = Block::new(id as u64, [0; 32], Vec::new());
+                block.timestamp = id as u64 * spacing;
+                block.difficulty = difficulty;
+                block
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        let mut hash = [0xff; 32];
+        assert_eq!(leading_zero_bits(&hash), 0);
+
+        hash[0] = 0;
+        hash[1] = 0x0f;
+        assert_eq!(leading_zero_bits(&hash), 12);
+
+        assert_eq!(leading_zero_bits(&[0; 32]), 256);
+    }
+
+    #[test]
+    fn test_mine_meets_difficulty() {
+        let mut block = Block::new(0, [0; 32], Vec::new());
+        block.difficulty = 8;
+
+        let hash = mine(&mut block);
+
+        assert!(meets_difficulty(&hash, 8));
+        assert_eq!(hash, block.hash());
+    }
+
+    #[test]
+    fn test_retarget_uses_initial_difficulty_for_unmined_chain() {
+        assert_eq!(retarget(&Vec::new(), 4, 1000), 4);
+        assert_eq!(retarget(&create_blocks(3, 1000, 0), 4, 1000), 4);
+    }
+
+    #[test]
+    fn test_retarget_only_adjusts_at_window_boundary() {
+        let blocks = create_blocks(RETARGET_WINDOW as usize + 2, 1, 10);
+        assert_eq!(retarget(&blocks, 4, 1000), 10);
+    }
+
+    #[test]
+    fn test_retarget_adjusts_towards_target_interval() {
+        let window = RETARGET_WINDOW as usize * 2;
+
+        let fast = create_blocks(window, 10, 10);
+        assert_eq!(retarget(&fast, 4, 1000), 11);
+
+        let slow = create_blocks(window, 5000, 10);
+        assert_eq!(retarget(&slow, 4, 1000), 9);
+
+        let on_target = create_blocks(window, 1000, 10);
+        assert_eq!(retarget(&on_target, 4, 1000), 10);
+    }
+
+    #[test]
+    fn test_retarget_never_drops_below_one() {
+        let slow = create_blocks(RETARGET_WINDOW as usize * 2, 5000, 1);
+        assert_eq!(retarget(&slow, 4, 1000), 1);
+    }
+}
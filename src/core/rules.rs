@@ -0,0 +1,658 @@
+use super::address::to_address;
+use super::admin::{account_status, is_signature_used, verify_admin, AccountStatus, AdminAction};
+use super::constants::{ESCROW_ID, MASTER_ID, NATIVE_ASSET};
+use super::contracts::execute_calls;
+use super::escrow::{find_escrow, Escrow};
+use super::genesis::Genesis;
+use super::keys::PublicKey;
+use super::monetary::MonetaryPolicy;
+use super::pow::retarget;
+use super::snapshot::Snapshot;
+use super::traits::TransactionInfo;
+use super::types::{
+    AccountId, AssetId, Block, BlockId, Blocks, Transaction, TransactionId, TransactionKind,
+};
+use super::utilities::asset_symbol;
+use super::validation::{validate_fees, validate_transaction};
+use std::time::Duration;
+
+/// What a block has to follow besides the amount rules, shared by the blocks
+/// of peers, imported chains and the blocks a node seals itself.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChainRules {
+    /// Operator key admin transactions have to be signed with; without one
+    /// they are refused
+    pub admin_key: Option<PublicKey>,
+    /// Caps what all blocks together may mint
+    pub policy: MonetaryPolicy,
+    /// Difficulty of the first mined block under proof of work, `None` when
+    /// blocks are sealed every interval and carry no work
+    pub initial_difficulty: Option<u32>,
+    /// Block time (in ms) retargeting aims for
+    pub target_interval: u64,
+}
+
+impl ChainRules {
+    /// Rules of the chain starting at `genesis`, run by an operator holding
+    /// the secret of `admin_key` and mined from `initial_difficulty` if given.
+    pub fn new(
+        genesis: &Genesis,
+        admin_key: Option<PublicKey>,
+        initial_difficulty: Option<u32>,
+    ) -> ChainRules {
+        ChainRules {
+            admin_key,
+            policy: MonetaryPolicy {
+                supply_cap: genesis.parameters.supply_cap,
+            },
+            initial_difficulty,
+            target_interval: genesis.parameters.block_interval * 1000,
+        }
+    }
+
+    /// Difficulty of the block following `branch`; the genesis block is
+    /// never mined.
+    pub fn difficulty(&self, branch: &Blocks) -> u32 {
+        match (self.initial_difficulty, branch.is_empty()) {
+            (Some(initial), false) => retarget(branch, initial, self.target_interval),
+            _ => 0,
+        }
+    }
+}
+
+/// Replays the transactions of a block on top of the chain it extends, one
+/// bundle (see `Transaction::is_bundled_with`) at a time.
+pub struct BlockCheck<'a> {
+    rules: &'a ChainRules,
+    /// Block the checked block follows, `None` for the genesis block
+    parent: Option<&'a Block>,
+    timestamp: u64,
+    balances: Snapshot,
+    /// Admin, escrow and time-locked transactions of the chain and of the
+    /// bundles applied so far
+    recorded: Vec<Transaction>,
+}
+
+impl<'a> BlockCheck<'a> {
+    /// Check of a block stamped `timestamp` that follows `chain`, after
+    /// which accounts hold `balances`.
+    pub fn new(
+        rules: &'a ChainRules,
+        chain: &'a Blocks,
+        balances: Snapshot,
+        timestamp: u64,
+    ) -> BlockCheck<'a> {
+        BlockCheck {
+            rules,
+            parent: chain.last(),
+            timestamp,
+            balances,
+            recorded: chain
+                .iter()
+                .flat_map(Block::all_transactions)
+                .filter(|transaction| is_recorded(transaction))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Applies a bundle, or refuses it and leaves the balances as they were.
+    pub fn apply(&mut self, bundle: &[Transaction]) -> Result<(), String> {
+        for transaction in bundle {
+            self.check_transaction(transaction)?;
+        }
+        self.check_sweeps(bundle)?;
+        self.check_mint(bundle)?;
+        self.check_locks(bundle)?;
+        self.balances.spend(bundle)?;
+        self.recorded.extend(
+            bundle
+                .iter()
+                .filter(|transaction| is_recorded(transaction))
+                .cloned(),
+        );
+        Ok(())
+    }
+
+    pub fn into_balances(self) -> Snapshot {
+        self.balances
+    }
+
+    /// Refuses closes that do not sweep exactly what the account holds: one
+    /// sweep per asset with a balance, of that balance, or a single empty
+    /// sweep of the native asset when it holds nothing.
+    fn check_sweeps(&self, bundle: &[Transaction]) -> Result<(), String> {
+        let Some(close) = bundle.first().filter(|transaction| {
+            matches!(
+                transaction.kind(),
+                TransactionKind::Admin {
+                    action: AdminAction::Close { .. },
+                    ..
+                }
+            )
+        }) else {
+            return Ok(());
+        };
+        let account = close.from();
+        let held: Vec<AssetId> = self
+            .balances
+            .balances
+            .iter()
+            .filter(|(_, balances)| balances.get(&account).is_some_and(|balance| *balance > 0.0))
+            .map(|(asset, _)| *asset)
+            .collect();
+        let mut swept: Vec<AssetId> = bundle.iter().map(Transaction::asset).collect();
+        swept.sort_unstable();
+        let is_empty_close = held.is_empty() && swept == [NATIVE_ASSET] && close.amount() == 0.0;
+        if !is_empty_close && held != swept {
+            return Err(format!(
+                "close {} does not sweep each asset {} holds once",
+                close.id,
+                to_address(account)
+            ));
+        }
+        for sweep in bundle {
+            let balance = self.balance(account, sweep.asset());
+            // Rounding may leave the sum of a long history a little off
+            if (sweep.amount() - balance).abs() > 1e-9 * balance {
+                return Err(format!(
+                    "close {} sweeps {} {} of the {} {} holds",
+                    close.id,
+                    sweep.amount(),
+                    asset_symbol(sweep.asset()),
+                    balance,
+                    to_address(account)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuses bundles minting beyond the supply cap, which holds for the
+    /// coinbase and mints of peers just like for local commands.
+    fn check_mint(&self, bundle: &[Transaction]) -> Result<(), String> {
+        let Some(cap) = self.rules.policy.supply_cap else {
+            return Ok(());
+        };
+        let minted: f64 = bundle
+            .iter()
+            .filter(|transaction| transaction.from() == MASTER_ID)
+            .filter(|transaction| transaction.asset() == NATIVE_ASSET)
+            .map(Transaction::amount)
+            .sum();
+        let before = -self.balance(MASTER_ID, NATIVE_ASSET);
+        // Rounding may leave a little above the cap after minting all that is left
+        match minted > 0.0 && before + minted > cap + 1e-9 * cap {
+            true => Err(format!(
+                "it mints {} with {} of the supply cap of {} left",
+                minted,
+                (cap - before).max(0.0),
+                cap
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Refuses bundles spending funds that are still locked at the parent
+    /// block, which the balance command counts as locked at the head.
+    fn check_locks(&self, bundle: &[Transaction]) -> Result<(), String> {
+        let Some(parent) = self.parent else {
+            return Ok(());
+        };
+        for sender in bundle.iter().filter(|t| t.from() != MASTER_ID) {
+            let (account, asset) = (sender.from(), sender.asset());
+            let locked = self.recorded.calculate_locked(account, asset, parent);
+            if locked <= 0.0 {
+                continue;
+            }
+            let left = bundle
+                .iter()
+                .filter(|transaction| transaction.asset() == asset)
+                .fold(self.balance(account, asset), |balance, transaction| match (
+                    transaction.to() == account,
+                    transaction.from() == account,
+                ) {
+                    (true, false) => balance + transaction.amount(),
+                    (false, true) => balance - transaction.amount(),
+                    _ => balance,
+                });
+            // Rounding leaves a little below the locked amount after spending the rest
+            if left < locked - 1e-9 * locked {
+                return Err(format!(
+                    "it spends locked funds of {}, leaving {} {} with {} locked",
+                    to_address(account),
+                    left,
+                    asset_symbol(asset),
+                    locked
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn balance(&self, account: AccountId, asset: AssetId) -> f64 {
+        self.balances
+            .balances
+            .get(&asset)
+            .and_then(|balances| balances.get(&account))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    fn status(&self, account: AccountId) -> AccountStatus {
+        account_status(&self.recorded, account)
+    }
+
+    fn height(&self) -> BlockId {
+        self.parent.map_or(0, |parent| parent.id)
+    }
+
+    fn escrow(&self, escrow_id: TransactionId) -> Result<Escrow, String> {
+        find_escrow(&self.recorded, escrow_id).ok_or(format!("escrow {} does not exist", escrow_id))
+    }
+
+    fn check_transaction(&self, transaction: &Transaction) -> Result<(), String> {
+        let from = transaction.from();
+        match transaction.kind() {
+            TransactionKind::Admin {
+                action,
+                timestamp,
+                signature,
+            } => {
+                let admin_key = self
+                    .rules
+                    .admin_key
+                    .ok_or("it holds an admin transaction and no admin key is set")?;
+                verify_admin(
+                    &admin_key,
+                    *action,
+                    from,
+                    *timestamp,
+                    signature,
+                    self.timestamp,
+                )?;
+                if is_signature_used(&self.recorded, signature) {
+                    return Err("Admin signature was already used".to_string());
+                }
+                let target = match action {
+                    AdminAction::Close { sweep_to } => *sweep_to,
+                    _ => from,
+                };
+                if transaction.to() != target {
+                    return Err(format!(
+                        "admin transaction {} does not go to the account it was signed for",
+                        transaction.id
+                    ));
+                }
+                if self.status(from) == AccountStatus::Closed {
+                    return Err(format!(
+                        "admin transaction {} acts on a closed account",
+                        transaction.id
+                    ));
+                }
+            }
+            TransactionKind::EscrowRelease {
+                escrow,
+                preimage,
+                signature,
+            } => {
+                let release = self.escrow(*escrow)?.release(
+                    self.height(),
+                    Duration::ZERO,
+                    preimage.clone(),
+                    signature.as_deref(),
+                )?;
+                check_settlement(transaction, &release)?;
+            }
+            TransactionKind::EscrowRefund { escrow, signature } => {
+                let refund = self.escrow(*escrow)?.refund(
+                    self.height(),
+                    Duration::ZERO,
+                    signature.as_deref(),
+                )?;
+                check_settlement(transaction, &refund)?;
+            }
+            kind => {
+                if from == ESCROW_ID {
+                    return Err(format!(
+                        "transaction {} moves escrowed funds without settling an escrow",
+                        transaction.id
+                    ));
+                }
+                if matches!(kind, TransactionKind::EscrowOpen(_)) && transaction.to() != ESCROW_ID {
+                    return Err(format!(
+                        "escrow {} does not hold its funds in the escrow account",
+                        transaction.id
+                    ));
+                }
+                self.status(from).check_can_send(from)?;
+                // Issuing an asset acts for the issuer just like a transfer
+                if from == MASTER_ID && transaction.asset() != NATIVE_ASSET {
+                    self.status(transaction.to())
+                        .check_can_send(transaction.to())?;
+                }
+            }
+        }
+        self.status(transaction.to())
+            .check_can_receive(transaction.to())
+    }
+}
+
+/// Whether later transactions are checked against the transaction.
+fn is_recorded(transaction: &Transaction) -> bool {
+    matches!(
+        transaction.kind(),
+        TransactionKind::Admin { .. }
+            | TransactionKind::EscrowOpen(_)
+            | TransactionKind::EscrowRelease { .. }
+            | TransactionKind::EscrowRefund { .. }
+    ) || transaction.lock().is_some()
+}
+
+/// Checks that an escrow settlement is the one its terms allow at the height
+/// it is sealed at; only its id, which depends on when it was made, may differ.
+fn check_settlement(transaction: &Transaction, settlement: &Transaction) -> Result<(), String> {
+    match transaction.to() == settlement.to()
+        && transaction.from() == settlement.from()
+        && transaction.amount() == settlement.amount()
+        && transaction.asset() == settlement.asset()
+        && transaction.lock() == settlement.lock()
+        && transaction.kind() == settlement.kind()
+    {
+        true => Ok(()),
+        false => Err(format!(
+            "transaction {} does not settle its escrow as the escrow's terms say",
+            transaction.id
+        )),
+    }
+}
+
+/// Checks a block against the chain it is appended to: it has to be whole,
+/// carry the difficulty retargeting asks for and not be stamped before its
+/// parent, every amount has to follow the validation rules, every fee has to
+/// follow the transaction it pays for and the receipts have to be the ones
+/// its contract calls produce. Its bundles are then replayed on the `balances`
+/// before it, which are moved past the block, and must not overdraw an
+/// account, spend locked funds, move funds of frozen or closed accounts,
+/// issue assets to them,
+/// mint beyond the supply cap, carry admin transactions the operator did not
+/// sign, close accounts without sweeping their balances or settle escrows
+/// other than their terms allow.
+pub fn check_block(
+    rules: &ChainRules,
+    chain: &Blocks,
+    balances: &mut Snapshot,
+    block: &Block,
+) -> Result<(), String> {
+    if block.is_pruned() {
+        return Err("its transactions were pruned and no snapshot covers it".to_string());
+    }
+    let difficulty = rules.difficulty(chain);
+    if block.difficulty != difficulty {
+        return Err(format!(
+            "its difficulty is {} where the chain asks for {}",
+            block.difficulty, difficulty
+        ));
+    }
+    if let Some(parent) = chain
+        .last()
+        .filter(|parent| block.timestamp < parent.timestamp)
+    {
+        return Err(format!(
+            "it is stamped {} ms before block {}",
+            parent.timestamp - block.timestamp,
+            parent.id
+        ));
+    }
+    block
+        .all_transactions()
+        .try_for_each(validate_transaction)?;
+    validate_fees(&block.transactions)?;
+    if execute_calls(chain, &block.transactions) != block.receipts {
+        return Err("receipts do not match its contract calls".to_string());
+    }
+
+    let mut check = BlockCheck::new(rules, chain, balances.clone(), block.timestamp);
+    if let Some(coinbase) = &block.coinbase {
+        check.apply(std::slice::from_ref(coinbase))?;
+    }
+    for bundle in block
+        .transactions
+        .chunk_by(|previous, next| next.is_bundled_with(previous))
+    {
+        check.apply(bundle)?;
+    }
+    *balances = check.into_balances();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::admin::admin_message;
+    use crate::core::escrow::hash_preimage;
+    use crate::core::keys::{generate_secret_key, public_key, sign};
+    use crate::core::types::{EscrowTerms, TimeLock};
+
+    const NOW: u64 = 1_700_000_000_000;
+
+    fn close(account: u64, sweep_to: u64, amount: f64, signature: Vec<u8>) -> Transaction {
+        Transaction::new(sweep_to, account, amount).with_kind(TransactionKind::Admin {
+            action: AdminAction::Close { sweep_to },
+            timestamp: NOW,
+            signature,
+        })
+    }
+
+    fn check(
+        rules: &ChainRules,
+        chain: &Blocks,
+        transactions: Vec<Transaction>,
+    ) -> Result<(), String> {
+        let mut block = Block::new(
+            chain.len() as u64,
+            chain.last().unwrap().hash(),
+            transactions,
+        );
+        block.timestamp = NOW;
+        check_block(rules, chain, &mut Snapshot::of(chain), &block)
+    }
+
+    #[test]
+    fn test_admin_transactions_need_the_operator_signature() {
+        let key = generate_secret_key();
+        let rules = ChainRules {
+            admin_key: Some(public_key(&key)),
+            ..ChainRules::default()
+        };
+        let chain = vec![Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)])];
+        let signed = sign(
+            &key,
+            &admin_message(AdminAction::Close { sweep_to: 2 }, 1, NOW),
+        );
+
+        assert!(check(&rules, &chain, vec![close(1, 2, 100.0, signed.clone())]).is_ok());
+        assert!(check(&rules, &chain, vec![close(1, 3, 100.0, signed.clone())]).is_err());
+        assert!(check(&rules, &chain, vec![close(1, 2, 100.0, vec![0; 64])]).is_err());
+        assert!(check(
+            &ChainRules::default(),
+            &chain,
+            vec![close(1, 2, 100.0, signed)]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_frozen_and_closed_accounts_cannot_send() {
+        let key = generate_secret_key();
+        let rules = ChainRules {
+            admin_key: Some(public_key(&key)),
+            ..ChainRules::default()
+        };
+        let signed = sign(
+            &key,
+            &admin_message(AdminAction::Close { sweep_to: 2 }, 1, NOW),
+        );
+        let genesis = Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)]);
+        let mut closed = Block::new(1, genesis.hash(), vec![close(1, 2, 100.0, signed.clone())]);
+        closed.timestamp = NOW;
+        let chain = vec![genesis, closed];
+
+        assert!(check(&rules, &chain, vec![Transaction::new(2, 1, 10.0)])
+            .unwrap_err()
+            .contains("closed"));
+        assert!(check(&rules, &chain, vec![Transaction::new(1, 2, 10.0)]).is_err());
+        assert!(check(&rules, &chain, vec![close(1, 2, 100.0, signed)]).is_err());
+    }
+
+    #[test]
+    fn test_closes_sweep_every_balance() {
+        let key = generate_secret_key();
+        let rules = ChainRules {
+            admin_key: Some(public_key(&key)),
+            ..ChainRules::default()
+        };
+        let chain = vec![Block::new(
+            0,
+            [0; 32],
+            vec![
+                Transaction::genesis(1, 100.0),
+                Transaction::with_asset(1, MASTER_ID, 5.0, 7),
+            ],
+        )];
+        let signed = sign(
+            &key,
+            &admin_message(AdminAction::Close { sweep_to: 2 }, 1, NOW),
+        );
+        let sweep = |amount: f64, asset: AssetId| {
+            let close = close(1, 2, amount, signed.clone());
+            Transaction::with_asset(2, 1, amount, asset).with_kind(close.kind().clone())
+        };
+
+        assert!(check(&rules, &chain, vec![sweep(100.0, 0), sweep(5.0, 7)]).is_ok());
+        assert!(check(&rules, &chain, vec![sweep(60.0, 0), sweep(5.0, 7)])
+            .unwrap_err()
+            .contains("sweeps 60"));
+        assert!(check(&rules, &chain, vec![sweep(100.0, 0)]).is_err());
+        assert!(check(
+            &rules,
+            &chain,
+            vec![sweep(100.0, 0), sweep(100.0, 0), sweep(5.0, 7)]
+        )
+        .is_err());
+        assert!(check(&rules, &chain, vec![sweep(0.0, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_frozen_and_closed_accounts_cannot_issue() {
+        let key = generate_secret_key();
+        let rules = ChainRules {
+            admin_key: Some(public_key(&key)),
+            ..ChainRules::default()
+        };
+        let genesis = Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)]);
+        let freeze = Transaction::new(1, 1, 0.0).with_kind(TransactionKind::Admin {
+            action: AdminAction::Freeze,
+            timestamp: NOW,
+            signature: sign(&key, &admin_message(AdminAction::Freeze, 1, NOW)),
+        });
+        let mut frozen = Block::new(1, genesis.hash(), vec![freeze]);
+        frozen.timestamp = NOW;
+        let chain = vec![genesis, frozen];
+        let issue = |issuer| vec![Transaction::with_asset(issuer, MASTER_ID, 10.0, 7)];
+
+        assert!(check(&rules, &chain, issue(1))
+            .unwrap_err()
+            .contains("frozen"));
+        assert!(check(&rules, &chain, issue(2)).is_ok());
+        assert!(check(&rules, &chain[..1].to_vec(), issue(1)).is_ok());
+    }
+
+    #[test]
+    fn test_mints_stay_under_the_supply_cap() {
+        let rules = ChainRules {
+            policy: MonetaryPolicy {
+                supply_cap: Some(120.0),
+            },
+            ..ChainRules::default()
+        };
+        let chain = vec![Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)])];
+
+        assert!(check(&rules, &chain, vec![Transaction::genesis(2, 20.0)]).is_ok());
+        assert!(check(&rules, &chain, vec![Transaction::genesis(2, 21.0)])
+            .unwrap_err()
+            .contains("supply cap"));
+        assert!(check(
+            &ChainRules::default(),
+            &chain,
+            vec![Transaction::genesis(2, 1e9)]
+        )
+        .is_ok());
+
+        let mut coinbase = Block::new(1, chain[0].hash(), Vec::new());
+        coinbase.coinbase = Some(Transaction::genesis(2, 50.0));
+        assert!(check_block(&rules, &chain, &mut Snapshot::of(&chain), &coinbase).is_err());
+    }
+
+    #[test]
+    fn test_escrows_are_settled_as_their_terms_allow() {
+        let genesis = Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)]);
+        let open = Transaction::new(ESCROW_ID, 1, 10.0).with_kind(TransactionKind::EscrowOpen(
+            EscrowTerms {
+                payee: 2,
+                arbiter: None,
+                hashlock: Some(hash_preimage("secret")),
+                timeout: 10,
+            },
+        ));
+        let escrow = find_escrow([&open], open.id).unwrap();
+        let chain = vec![genesis.clone(), Block::new(1, genesis.hash(), vec![open])];
+        let rules = ChainRules::default();
+        let release = |preimage: &str| {
+            escrow
+                .release(1, Duration::ZERO, Some(preimage.to_string()), None)
+                .unwrap()
+        };
+
+        assert!(check(&rules, &chain, vec![release("secret")]).is_ok());
+        let stolen =
+            Transaction::new(3, ESCROW_ID, 10.0).with_kind(release("secret").kind().clone());
+        assert!(check(&rules, &chain, vec![stolen]).is_err());
+        let unproven =
+            Transaction::new(2, ESCROW_ID, 10.0).with_kind(TransactionKind::EscrowRelease {
+                escrow: escrow.id,
+                preimage: Some("guess".to_string()),
+                signature: None,
+            });
+        assert!(check(&rules, &chain, vec![unproven]).is_err());
+        assert!(check(&rules, &chain, vec![release("secret"), release("secret")]).is_err());
+        assert!(check(&rules, &chain, vec![Transaction::new(3, ESCROW_ID, 10.0)]).is_err());
+    }
+
+    #[test]
+    fn test_locked_funds_cannot_be_spent() {
+        let genesis = Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)]);
+        let locked = Transaction::new(2, 1, 50.0).with_lock(TimeLock::Height(3));
+        let mut chain = vec![genesis.clone(), Block::new(1, genesis.hash(), vec![locked])];
+        let rules = ChainRules::default();
+        let spend = || vec![Transaction::new(3, 2, 10.0)];
+
+        assert!(check(&rules, &chain, spend())
+            .unwrap_err()
+            .contains("locked"));
+        let top_up = Transaction::genesis(2, 10.0);
+        assert!(check(
+            &rules,
+            &chain,
+            vec![top_up.clone(), Transaction::new(3, 2, 10.0)]
+        )
+        .is_ok());
+        assert!(check(&rules, &chain, vec![top_up, Transaction::new(3, 2, 10.5)]).is_err());
+
+        while chain.len() <= 3 {
+            let head = chain.last().unwrap();
+            chain.push(Block::new(head.id + 1, head.hash(), Vec::new()));
+        }
+        assert!(check(&rules, &chain, spend()).is_ok());
+    }
+}
@@ -0,0 +1,129 @@
+use super::types::{AccountId, AssetId, BlockId, Id};
+use serde::{Deserialize, Serialize};
+
+/// Recurring transfer submitted by the block processor every `every` blocks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: Id,
+    pub from: AccountId,
+    pub to: AccountId,
+    pub amount: f64,
+    pub asset: AssetId,
+    pub every: u64,
+    /// Id of the block the next transfer is submitted for
+    pub next_height: BlockId,
+    /// Number of transfers left, `None` for a schedule that runs until cancelled
+    pub remaining: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    schedules: Vec<Schedule>,
+    next_id: Id,
+}
+
+impl Scheduler {
+    /// Registers a schedule under a fresh id, which is returned.
+    pub fn add(&mut self, mut schedule: Schedule) -> Result<Id, String> {
+        if schedule.every == 0 {
+            return Err("Schedules must repeat at least every block".to_string());
+        }
+        if schedule.remaining == Some(0) {
+            return Err("Schedules must run at least once".to_string());
+        }
+
+        self.next_id += 1;
+        schedule.id = self.next_id;
+        self.schedules.push(schedule);
+        Ok(self.next_id)
+    }
+
+    pub fn cancel(&mut self, id: Id) -> Result<Schedule, String> {
+        match self.schedules.iter().position(|schedule| schedule.id == id) {
+            Some(index) => Ok(self.schedules.remove(index)),
+            None => Err(format!("Schedule not found: {}", id)),
+        }
+    }
+
+    /// Returns the schedules due for the block at `height` and advances them
+    /// to their next run, dropping those that are exhausted.
+    pub fn take_due(&mut self, height: BlockId) -> Vec<Schedule> {
+        let due: Vec<Schedule> = self
+            .schedules
+            .iter()
+            .filter(|schedule| schedule.next_height <= height)
+            .cloned()
+            .collect();
+
+        for schedule in self.schedules.iter_mut() {
+            if schedule.next_height <= height {
+                schedule.next_height = height + schedule.every;
+                schedule.remaining = schedule.remaining.map(|remaining| remaining - 1);
+            }
+        }
+        self.schedules
+            .retain(|schedule| schedule.remaining != Some(0));
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::constants::NATIVE_ASSET;
+
+    fn create_schedule(every: u64, count: Option<u64>, first_height: BlockId) -> Schedule {
+        Schedule {
+            id: 0,
+            from: 1,
+            to: 2,
+            amount: 1.0,
+            asset: NATIVE_ASSET,
+            every,
+            next_height: first_height,
+            remaining: count,
+        }
+    }
+
+    #[test]
+    fn test_add_rejects_invalid_schedules() {
+        let mut scheduler = Scheduler::default();
+
+        assert!(scheduler.add(create_schedule(0, None, 1)).is_err());
+        assert!(scheduler.add(create_schedule(1, Some(0), 1)).is_err());
+    }
+
+    #[test]
+    fn test_take_due_repeats_every_n_blocks() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add(create_schedule(3, None, 2)).unwrap();
+
+        assert!(scheduler.take_due(1).is_empty());
+        assert_eq!(scheduler.take_due(2).len(), 1);
+        assert!(scheduler.take_due(3).is_empty());
+        assert!(scheduler.take_due(4).is_empty());
+        assert_eq!(scheduler.take_due(5).len(), 1);
+    }
+
+    #[test]
+    fn test_take_due_drops_exhausted_schedules() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add(create_schedule(1, Some(2), 1)).unwrap();
+
+        assert_eq!(scheduler.take_due(1).len(), 1);
+        assert_eq!(scheduler.take_due(2).len(), 1);
+        assert!(scheduler.take_due(3).is_empty());
+        assert!(scheduler.cancel(1).is_err());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut scheduler = Scheduler::default();
+        let id = scheduler.add(create_schedule(1, None, 1)).unwrap();
+
+        assert_eq!(scheduler.cancel(id).unwrap().to, 2);
+        assert!(scheduler.take_due(1).is_empty());
+        assert!(scheduler.cancel(id).is_err());
+    }
+}
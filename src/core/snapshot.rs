@@ -0,0 +1,261 @@
+use super::address::to_address;
+use super::constants::{BURN_ID, MASTER_ID};
+use super::monetary::Supply;
+use super::traits::TransactionInfo;
+use super::types::{AccountId, AssetId, Block, BlockId, Blocks, Hash, Transaction};
+use super::utilities::asset_symbol;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Balances of every account after a block, so that balances can be derived
+/// from the blocks after it instead of the whole chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub block_id: BlockId,
+    pub block_hash: Hash,
+    /// Balance per asset of every account that took part in a transaction up to the block
+    pub balances: BTreeMap<AssetId, BTreeMap<AccountId, f64>>,
+}
+
+impl Snapshot {
+    /// Snapshot after the last of `blocks`, which start at the genesis block.
+    pub fn of(blocks: &[Block]) -> Snapshot {
+        let genesis = Snapshot {
+            block_id: 0,
+            block_hash: [0; 32],
+            balances: BTreeMap::new(),
+        };
+        genesis.apply(blocks)
+    }
+
+    /// Snapshot after the last of `blocks`, which follow the block of this snapshot.
+    pub fn extend(&self, blocks: &[Block]) -> Snapshot {
+        self.clone().apply(blocks)
+    }
+
+    fn apply(mut self, blocks: &[Block]) -> Snapshot {
+        for transaction in blocks.iter().flat_map(Block::all_transactions) {
+            let balances = self.balances.entry(transaction.asset()).or_default();
+            *balances.entry(transaction.to()).or_insert(0.0) += transaction.amount();
+            *balances.entry(transaction.from()).or_insert(0.0) -= transaction.amount();
+        }
+        if let Some(last) = blocks.last() {
+            self.block_id = last.id;
+            self.block_hash = last.hash();
+        }
+        self
+    }
+
+    /// Applies the block that follows the block of this snapshot, refusing it
+    /// if it overdraws an account (see `spend`).
+    pub fn apply_checked(&mut self, block: &Block) -> Result<(), String> {
+        self.spend(block.all_transactions())
+            .map_err(|e| format!("block {}: {}", block.id, e))?;
+        self.block_id = block.id;
+        self.block_hash = block.hash();
+        Ok(())
+    }
+
+    /// Applies transactions that are not sealed yet, refusing them and keeping
+    /// the balances as they were if they leave an account other than
+    /// `MASTER_ID` with a negative balance in any asset.
+    pub fn spend<'a>(
+        &mut self,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<(), String> {
+        let mut changed: BTreeMap<(AssetId, AccountId), f64> = BTreeMap::new();
+        let mut senders = Vec::new();
+        let mut scale: f64 = 1.0;
+        for transaction in transactions {
+            let asset = transaction.asset();
+            for (account, amount) in [
+                (transaction.to(), transaction.amount()),
+                (transaction.from(), -transaction.amount()),
+            ] {
+                let balance = changed.entry((asset, account)).or_insert_with(|| {
+                    self.balances
+                        .get(&asset)
+                        .and_then(|balances| balances.get(&account))
+                        .copied()
+                        .unwrap_or(0.0)
+                });
+                *balance += amount;
+            }
+            senders.push((asset, transaction.from()));
+            scale = scale.max(transaction.amount());
+        }
+
+        // Rounding leaves a little below zero after spending everything
+        if let Some(&(asset, account)) = senders
+            .iter()
+            .find(|key| key.1 != MASTER_ID && changed[*key] < -1e-9 * scale)
+        {
+            return Err(format!(
+                "it overdraws {}, leaving {} {}",
+                to_address(account),
+                changed[&(asset, account)],
+                asset_symbol(asset)
+            ));
+        }
+        for ((asset, account), balance) in changed {
+            self.balances
+                .entry(asset)
+                .or_default()
+                .insert(account, balance);
+        }
+        Ok(())
+    }
+
+    /// Checks that the block of this snapshot is part of `chain`.
+    pub fn verify(&self, chain: &Blocks) -> Result<(), String> {
+        match chain.get(self.block_id as usize).map(Block::hash) == Some(self.block_hash) {
+            true => Ok(()),
+            false => Err(format!(
+                "Snapshot of block {} does not match the chain (head block {})",
+                self.block_id,
+                chain.len() - 1
+            )),
+        }
+    }
+
+    /// Balance of an account in an asset over `chain`, which this snapshot was
+    /// verified against; only the blocks after the snapshot are replayed.
+    pub fn balance(&self, chain: &Blocks, account: AccountId, asset: AssetId) -> Option<f64> {
+        let balance = self
+            .balances
+            .get(&asset)
+            .and_then(|balances| balances.get(&account))
+            .copied();
+        chain
+            .iter()
+            .skip(self.block_id as usize + 1)
+            .filter_map(|block| block.calculate_total(account, asset))
+            .fold(balance, |acc, amount| Some(acc.unwrap_or(0.0) + amount))
+    }
+
+    pub fn contains_account(&self, account: AccountId) -> bool {
+        self.balances
+            .values()
+            .any(|balances| balances.contains_key(&account))
+    }
+
+    /// Supply of an asset over `chain`, which this snapshot was verified against.
+    pub fn supply(&self, chain: &Blocks, asset: AssetId) -> Supply {
+        let balance = |account| {
+            self.balances
+                .get(&asset)
+                .and_then(|balances| balances.get(&account))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        let after = Supply::of(
+            chain
+                .iter()
+                .skip(self.block_id as usize + 1)
+                .flat_map(Block::all_transactions),
+            asset,
+        );
+        let minted = after.minted - balance(MASTER_ID);
+        let burned = after.burned + balance(BURN_ID);
+        Supply {
+            minted,
+            burned,
+            circulating: minted - burned,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read snapshot {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("could not parse snapshot {}: {}", path.display(), e))
+    }
+
+    /// Writes the snapshot next to `path` first so a crash never leaves a partial file.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let partial = path.with_extension("partial");
+        let contents = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(&partial, contents)
+            .and_then(|()| fs::rename(&partial, path))
+            .map_err(|e| format!("could not write snapshot {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::Transaction;
+
+    fn create_chain() -> Blocks {
+        let genesis = Block::new(0, [0; 32], vec![Transaction::genesis(1, 100.0)]);
+        let first = Block::new(1, genesis.hash(), vec![Transaction::new(2, 1, 10.0)]);
+        let second = Block::new(
+            2,
+            first.hash(),
+            vec![
+                Transaction::new(3, 2, 4.0),
+                Transaction::with_asset(3, 1, 1.0, 7),
+            ],
+        );
+        vec![genesis, first, second]
+    }
+
+    #[test]
+    fn test_snapshot_balances_match_replay() {
+        let chain = create_chain();
+        for block_id in 0..chain.len() {
+            let snapshot = Snapshot::of(&chain[..=block_id]);
+            assert_eq!(snapshot.verify(&chain), Ok(()));
+            for (account, asset) in [(1, 0), (2, 0), (3, 0), (3, 7), (4, 0)] {
+                assert_eq!(
+                    snapshot.balance(&chain, account, asset),
+                    chain.calculate_total(account, asset)
+                );
+            }
+            assert_eq!(snapshot.supply(&chain, 0), Supply::of_chain(&chain, 0));
+            assert!(snapshot.contains_account(1));
+            assert!(!snapshot.contains_account(4));
+        }
+    }
+
+    #[test]
+    fn test_extend_matches_full_snapshot() {
+        let chain = create_chain();
+        let snapshot = Snapshot::of(&chain[..2]);
+
+        assert_eq!(snapshot.extend(&chain[2..]), Snapshot::of(&chain));
+    }
+
+    #[test]
+    fn test_spend_refuses_overdrafts() {
+        let chain = create_chain();
+        let mut snapshot = Snapshot::of(&chain);
+        let before = snapshot.clone();
+
+        assert!(snapshot.spend(&[Transaction::new(1, 3, 4.0)]).is_ok());
+        assert_eq!(snapshot.balances[&0][&3], 0.0);
+        assert!(snapshot.spend(&[Transaction::new(1, 3, 0.5)]).is_err());
+        assert!(snapshot
+            .spend(&[Transaction::new(3, 1, 1.0), Transaction::new(1, 3, 1.5)])
+            .is_err());
+        assert_eq!(snapshot.balances[&0][&3], 0.0);
+
+        let overdraft = Block::new(3, chain[2].hash(), vec![Transaction::new(4, 2, 7.0)]);
+        let mut snapshot = before;
+        assert!(snapshot.apply_checked(&overdraft).is_err());
+        assert_eq!(snapshot.block_id, 2);
+    }
+
+    #[test]
+    fn test_verify_rejects_other_chains() {
+        let chain = create_chain();
+        let snapshot = Snapshot::of(&chain);
+        let mut other = chain.clone();
+        other[2].nonce = 1;
+
+        assert!(snapshot.verify(&other).is_err());
+        assert!(snapshot.verify(&chain[..2].to_vec()).is_err());
+    }
+}
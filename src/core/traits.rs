@@ -1,10 +1,15 @@
-use super::types::{AccountId, BlockId, TransactionId};
+use super::types::{AccountId, AssetId, Block, BlockId, TransactionId};
 
 pub trait TransactionInfo {
     fn contains_account(&self, account: AccountId) -> bool;
-    fn calculate_total(&self, account: AccountId) -> Option<f64>;
+    fn calculate_total(&self, account: AccountId, asset: AssetId) -> Option<f64>;
+    /// Part of the total received by `account` that is still time-locked at `head`.
+    fn calculate_locked(&self, account: AccountId, asset: AssetId, head: &Block) -> f64;
 }
 
 pub trait BlockInfo {
     fn contains_transaction(&self, block: BlockId, transaction: TransactionId) -> bool;
+    fn find_transaction(&self, transaction: TransactionId) -> Option<BlockId>;
+    /// Id of the first block with a transaction to or from `account`.
+    fn find_account(&self, account: AccountId) -> Option<BlockId>;
 }
@@ -1,5 +1,12 @@
+use super::admin::AdminAction;
+use super::constants::{MASTER_ID, NATIVE_ASSET};
+use super::keys::PublicKey;
 use super::traits::{BlockInfo, TransactionInfo};
 use super::utilities::generate_id;
+use super::vm::{Program, Word};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
@@ -7,27 +14,210 @@ pub type Id = u64;
 pub type AccountId = Id;
 pub type TransactionId = Id;
 pub type BlockId = Id;
+pub type AssetId = Id;
 pub type Transactions = Vec<Transaction>;
 pub type Blocks = Vec<Block>;
+pub type Hash = [u8; 32];
+
+/// Condition that has to be met before the recipient can spend the amount of
+/// a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TimeLock {
+    /// Spendable once the canonical chain reaches this block id
+    Height(BlockId),
+    /// Spendable once the head block is at least this old (milliseconds since the UNIX epoch)
+    Timestamp(u64),
+}
+
+impl TimeLock {
+    pub fn is_unlocked(&self, head: &Block) -> bool {
+        match *self {
+            TimeLock::Height(height) => head.id >= height,
+            TimeLock::Timestamp(timestamp) => head.timestamp >= timestamp,
+        }
+    }
+}
+
+/// Conditions under which escrowed funds go to the payee or back to the payer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EscrowTerms {
+    pub payee: AccountId,
+    /// Key that can release or refund the escrow at any time
+    pub arbiter: Option<PublicKey>,
+    /// SHA-256 hash whose preimage releases the escrow before the timeout
+    pub hashlock: Option<Hash>,
+    /// Block id from which the payer can take the funds back
+    pub timeout: BlockId,
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TransactionKind {
+    #[default]
+    Transfer,
+    /// Transfer fee burned for the transaction right before it in the same block
+    Fee { transaction: TransactionId },
+    /// Moves funds from the payer to `ESCROW_ID`; the transaction id is the escrow id
+    EscrowOpen(EscrowTerms),
+    /// Pays escrowed funds out to the payee, carrying what authorized it so
+    /// that peers can check it
+    EscrowRelease {
+        escrow: TransactionId,
+        preimage: Option<String>,
+        /// Signature of the arbiter
+        #[serde(default)]
+        signature: Option<Vec<u8>>,
+    },
+    /// Returns escrowed funds to the payer
+    EscrowRefund {
+        escrow: TransactionId,
+        /// Signature of the arbiter, needed before the timeout
+        #[serde(default)]
+        signature: Option<Vec<u8>>,
+    },
+    /// Creates a contract whose account id is the transaction id
+    Deploy { code: Program },
+    /// Calls the contract the transaction is sent to
+    Call { args: Vec<Word>, gas_limit: u64 },
+    /// Registers a name for the sending account
+    RegisterName { name: String },
+    /// Hands a name owned by the sender over to the receiver
+    TransferName { name: String },
+    /// Account change signed by the node operator key
+    Admin {
+        action: AdminAction,
+        timestamp: u64,
+        signature: Vec<u8>,
+    },
+}
+
+/// Outcome of a contract call, recorded by the block that executed it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub transaction_id: TransactionId,
+    pub contract: AccountId,
+    pub gas_used: u64,
+    pub result: Result<Option<Word>, String>,
+    /// Storage entries changed by the call; a value of 0 deletes the key
+    pub writes: Vec<(Word, Word)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: TransactionId,
     to: AccountId,
     from: AccountId,
     amount: f64,
+    asset: AssetId,
+    lock: Option<TimeLock>,
+    kind: TransactionKind,
 }
 
 impl Transaction {
     pub fn new(to: AccountId, from: AccountId, amount: f64) -> Transaction {
+        Transaction::with_asset(to, from, amount, NATIVE_ASSET)
+    }
+
+    pub fn with_asset(to: AccountId, from: AccountId, amount: f64, asset: AssetId) -> Transaction {
         let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let id = generate_id(to, from, amount, current_time);
+        Transaction::at(to, from, amount, asset, current_time)
+    }
+
+    /// Transaction whose id is derived from `time` (since the UNIX epoch)
+    /// rather than the system clock.
+    pub fn at(
+        to: AccountId,
+        from: AccountId,
+        amount: f64,
+        asset: AssetId,
+        time: Duration,
+    ) -> Transaction {
+        let id = generate_id(to, from, amount, asset, time);
 
         Transaction {
             id,
             to,
             from,
             amount,
+            asset,
+            lock: None,
+            kind: TransactionKind::Transfer,
+        }
+    }
+
+    /// Mixes `nonce` into the id, so that equal transactions made within the
+    /// same clock tick still get different ids.
+    pub fn with_nonce(mut self, nonce: u64) -> Transaction {
+        let mut hasher = Sha256::new();
+        hasher.update(self.id.to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
+        let digest = hasher.finalize();
+
+        let mut id_bytes = [0u8; 8];
+        id_bytes.copy_from_slice(&digest[0..8]);
+        self.id = u64::from_be_bytes(id_bytes);
+        self
+    }
+
+    /// Locks the amount for the recipient until `lock` is met.
+    pub fn with_lock(mut self, lock: TimeLock) -> Transaction {
+        self.lock = Some(lock);
+        self
+    }
+
+    pub fn with_kind(mut self, kind: TransactionKind) -> Transaction {
+        self.kind = kind;
+        self
+    }
+
+    /// Allocation from MASTER_ID with an id that does not depend on the clock,
+    /// so every node derives the same genesis block.
+    pub fn genesis(to: AccountId, amount: f64) -> Transaction {
+        Transaction {
+            id: generate_id(to, MASTER_ID, amount, NATIVE_ASSET, Duration::ZERO),
+            to,
+            from: MASTER_ID,
+            amount,
+            asset: NATIVE_ASSET,
+            lock: None,
+            kind: TransactionKind::Transfer,
+        }
+    }
+
+    pub fn to(&self) -> AccountId {
+        self.to
+    }
+
+    pub fn from(&self) -> AccountId {
+        self.from
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.amount
+    }
+
+    pub fn asset(&self) -> AssetId {
+        self.asset
+    }
+
+    pub fn lock(&self) -> Option<TimeLock> {
+        self.lock
+    }
+
+    pub fn kind(&self) -> &TransactionKind {
+        &self.kind
+    }
+
+    /// Whether the transaction has to be sealed in the same block as the one
+    /// right before it: a fee goes with the transaction it pays for and the
+    /// sweeps of a close go together.
+    pub fn is_bundled_with(&self, previous: &Transaction) -> bool {
+        match &self.kind {
+            TransactionKind::Fee { transaction } => *transaction == previous.id,
+            TransactionKind::Admin {
+                action: AdminAction::Close { .. },
+                ..
+            } => self.from == previous.from && self.kind == previous.kind,
+            _ => false,
         }
     }
 }
@@ -37,10 +227,14 @@ impl TransactionInfo for Transactions {
         self.iter().any(|t| t.to == account || t.from == account)
     }
 
-    fn calculate_total(&self, account: AccountId) -> Option<f64> {
+    fn calculate_total(&self, account: AccountId, asset: AssetId) -> Option<f64> {
         let sum: Option<f64> = self
             .iter()
+            .filter(|t| t.asset == asset)
             .filter_map(|t| {
+                if t.to == account && t.from == account {
+                    return Some(0.0);
+                }
                 if t.to == account {
                     return Some(t.amount);
                 }
@@ -52,20 +246,176 @@ impl TransactionInfo for Transactions {
             .fold(None, |acc, amount| Some(acc.unwrap_or(0.0) + amount));
         sum
     }
+
+    fn calculate_locked(&self, account: AccountId, asset: AssetId, head: &Block) -> f64 {
+        self.iter()
+            .filter(|t| t.to == account && t.asset == asset)
+            .filter(|t| t.lock.is_some_and(|lock| !lock.is_unlocked(head)))
+            .fold(0.0, |acc, t| acc + t.amount)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
     pub id: BlockId,
+    pub previous_hash: Hash,
+    /// Milliseconds since the UNIX epoch at which the block was assembled
+    pub timestamp: u64,
+    /// Number of leading zero bits the block hash must have (0 for unmined blocks)
+    pub difficulty: u32,
+    pub nonce: u64,
+    /// Reward minted for the producer of the block; never returned to the mempool on a reorg
+    pub coinbase: Option<Transaction>,
     pub transactions: Transactions,
+    /// Results of the contract calls in `transactions`, in order
+    pub receipts: Vec<Receipt>,
+    /// Set once the transactions nothing but balances depend on were discarded
+    #[serde(default)]
+    pub pruned: Option<PrunedBody>,
+}
+
+/// What a pruned block keeps of the transactions it discarded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PrunedBody {
+    /// Digest of the transactions and receipts the block was sealed with
+    pub digest: Hash,
+    pub transaction_ids: Vec<TransactionId>,
+}
+
+impl Block {
+    /// Block with a zero timestamp; whoever seals it sets `timestamp` from
+    /// their clock.
+    pub fn new(id: BlockId, previous_hash: Hash, transactions: Transactions) -> Block {
+        Block {
+            id,
+            previous_hash,
+            timestamp: 0,
+            difficulty: 0,
+            nonce: 0,
+            coinbase: None,
+            transactions,
+            receipts: Vec::new(),
+            pruned: None,
+        }
+    }
+
+    /// Coinbase followed by the regular transactions of the block.
+    pub fn all_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.coinbase.iter().chain(&self.transactions)
+    }
+
+    pub fn hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+
+        hasher.update(self.id.to_be_bytes());
+        hasher.update(self.previous_hash);
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update(self.difficulty.to_be_bytes());
+        hasher.update(self.nonce.to_be_bytes());
+        hasher.update(self.body_digest());
+
+        hasher.finalize().into()
+    }
+
+    /// Digest of the transactions and receipts, which the block hash commits to.
+    fn body_digest(&self) -> Hash {
+        if let Some(pruned) = &self.pruned {
+            return pruned.digest;
+        }
+
+        let mut hasher = Sha256::new();
+        for transaction in self.all_transactions() {
+            hasher.update(transaction.id.to_be_bytes());
+            hasher.update(transaction.to.to_be_bytes());
+            hasher.update(transaction.from.to_be_bytes());
+            hasher.update(transaction.amount.to_be_bytes());
+            hasher.update(transaction.asset.to_be_bytes());
+            match transaction.lock {
+                None => hasher.update([0]),
+                Some(TimeLock::Height(height)) => {
+                    hasher.update([1]);
+                    hasher.update(height.to_be_bytes());
+                }
+                Some(TimeLock::Timestamp(timestamp)) => {
+                    hasher.update([2]);
+                    hasher.update(timestamp.to_be_bytes());
+                }
+            }
+            hasher.update(
+                bincode::serialize(&transaction.kind)
+                    .expect("Failed to serialize transaction kind"),
+            );
+        }
+
+        hasher.update(bincode::serialize(&self.receipts).expect("Failed to serialize receipts"));
+
+        hasher.finalize().into()
+    }
+
+    pub fn is_pruned(&self) -> bool {
+        self.pruned.is_some()
+    }
+
+    /// Discards the coinbase, fees and plain transfers without a time lock,
+    /// keeping the hash. Everything else derived from the chain (names,
+    /// account status, escrows, contracts and locks) only depends on the
+    /// transactions that remain; balances have to come from a snapshot.
+    pub fn prune(&mut self) {
+        if self.is_pruned() {
+            return;
+        }
+        let digest = self.body_digest();
+        let is_prunable = |t: &Transaction| {
+            matches!(
+                t.kind,
+                TransactionKind::Transfer | TransactionKind::Fee { .. }
+            ) && t.lock.is_none()
+        };
+
+        let mut transaction_ids: Vec<TransactionId> =
+            self.coinbase.take().map(|t| t.id).into_iter().collect();
+        self.transactions.retain(|t| match is_prunable(t) {
+            true => {
+                transaction_ids.push(t.id);
+                false
+            }
+            false => true,
+        });
+        self.pruned = Some(PrunedBody {
+            digest,
+            transaction_ids,
+        });
+    }
 }
 
 impl TransactionInfo for Block {
     fn contains_account(&self, account: AccountId) -> bool {
-        self.transactions.contains_account(account)
+        self.coinbase
+            .iter()
+            .any(|t| t.to == account || t.from == account)
+            || self.transactions.contains_account(account)
+    }
+    fn calculate_total(&self, account: AccountId, asset: AssetId) -> Option<f64> {
+        let coinbase_total = self
+            .coinbase
+            .iter()
+            .cloned()
+            .collect::<Transactions>()
+            .calculate_total(account, asset);
+
+        match (
+            coinbase_total,
+            self.transactions.calculate_total(account, asset),
+        ) {
+            (Some(val1), Some(val2)) => Some(val1 + val2),
+            (Some(val), None) | (None, Some(val)) => Some(val),
+            (None, None) => None,
+        }
     }
-    fn calculate_total(&self, account: AccountId) -> Option<f64> {
-        self.transactions.calculate_total(account)
+
+    /// The coinbase is never locked.
+    fn calculate_locked(&self, account: AccountId, asset: AssetId, head: &Block) -> f64 {
+        self.transactions.calculate_locked(account, asset, head)
     }
 }
 
@@ -74,13 +424,19 @@ impl TransactionInfo for Blocks {
         self.iter().any(|b| b.contains_account(account))
     }
 
-    fn calculate_total(&self, account: AccountId) -> Option<f64> {
+    fn calculate_total(&self, account: AccountId, asset: AssetId) -> Option<f64> {
         let sum: Option<f64> = self
             .iter()
-            .filter_map(|block| block.calculate_total(account))
+            .filter_map(|block| block.calculate_total(account, asset))
             .fold(None, |acc, amount| Some(acc.unwrap_or(0.0) + amount));
         sum
     }
+
+    fn calculate_locked(&self, account: AccountId, asset: AssetId, head: &Block) -> f64 {
+        self.iter().fold(0.0, |acc, block| {
+            acc + block.calculate_locked(account, asset, head)
+        })
+    }
 }
 
 impl BlockInfo for Blocks {
@@ -92,7 +448,19 @@ impl BlockInfo for Blocks {
             }
         };
 
-        block.transactions.iter().any(|t| t.id == transaction_id)
+        block.all_transactions().any(|t| t.id == transaction_id)
+    }
+
+    fn find_transaction(&self, transaction_id: TransactionId) -> Option<BlockId> {
+        (0..self.len() as BlockId)
+            .rev()
+            .find(|block_id| self.contains_transaction(*block_id, transaction_id))
+    }
+
+    fn find_account(&self, account: AccountId) -> Option<BlockId> {
+        self.iter()
+            .find(|block| block.contains_account(account))
+            .map(|block| block.id)
     }
 }
 
@@ -110,12 +478,18 @@ mod tests {
                     to: 1,
                     from: 2,
                     amount: 2.34,
+                    asset: NATIVE_ASSET,
+                    lock: None,
+                    kind: TransactionKind::Transfer,
                 },
                 Transaction {
                     id: 2,
                     to: 1,
                     from: 2,
                     amount: 10.00,
+                    asset: NATIVE_ASSET,
+                    lock: None,
+                    kind: TransactionKind::Transfer,
                 },
             ]
         }
@@ -124,11 +498,11 @@ mod tests {
         fn test_calculate_total_for_existing_id() {
             let transactions = create_transcations();
 
-            let result = transactions.calculate_total(1);
+            let result = transactions.calculate_total(1, NATIVE_ASSET);
             assert!(result.is_some());
             assert_eq!(result, Some(12.34));
 
-            let result = transactions.calculate_total(2);
+            let result = transactions.calculate_total(2, NATIVE_ASSET);
             assert!(result.is_some());
             assert_eq!(result, Some(-12.34));
         }
@@ -137,10 +511,33 @@ mod tests {
         fn test_calculate_total_no_id_returns_none() {
             let transactions = create_transcations();
 
-            let result = transactions.calculate_total(3);
+            let result = transactions.calculate_total(3, NATIVE_ASSET);
             assert!(result.is_none());
         }
 
+        #[test]
+        fn test_calculate_total_of_self_transfer_is_zero() {
+            let transactions = vec![Transaction::new(4, 4, 5.0)];
+
+            assert_eq!(transactions.calculate_total(4, NATIVE_ASSET), Some(0.0));
+        }
+
+        #[test]
+        fn test_calculate_locked() {
+            let mut head = Block::new(5, [0; 32], Vec::new());
+            head.timestamp = 1_000;
+            let transactions = vec![
+                Transaction::new(1, 2, 10.0).with_lock(TimeLock::Height(6)),
+                Transaction::new(1, 2, 20.0).with_lock(TimeLock::Height(5)),
+                Transaction::new(1, 2, 40.0).with_lock(TimeLock::Timestamp(2_000)),
+                Transaction::new(1, 2, 80.0),
+            ];
+
+            assert_eq!(transactions.calculate_locked(1, NATIVE_ASSET, &head), 50.0);
+            assert_eq!(transactions.calculate_locked(2, NATIVE_ASSET, &head), 0.0);
+            assert_eq!(transactions.calculate_total(1, NATIVE_ASSET), Some(150.0));
+        }
+
         #[test]
         fn test_contains_account() {
             let transactions = create_transcations();
@@ -159,34 +556,41 @@ mod tests {
             use super::*;
 
             fn create_block() -> Block {
-                Block {
-                    id: 0,
-                    transactions: vec![
+                Block::new(
+                    0,
+                    [0; 32],
+                    vec![
                         Transaction {
                             id: 1,
                             to: 1,
                             from: 2,
                             amount: 2.34,
+                            asset: NATIVE_ASSET,
+                            lock: None,
+                            kind: TransactionKind::Transfer,
                         },
                         Transaction {
                             id: 2,
                             to: 1,
                             from: 2,
                             amount: 10.00,
+                            asset: NATIVE_ASSET,
+                            lock: None,
+                            kind: TransactionKind::Transfer,
                         },
                     ],
-                }
+                )
             }
 
             #[test]
             fn test_calculate_total_for_existing_id() {
                 let block = create_block();
 
-                let result = block.calculate_total(1);
+                let result = block.calculate_total(1, NATIVE_ASSET);
                 assert!(result.is_some());
                 assert_eq!(result, Some(12.34));
 
-                let result = block.calculate_total(2);
+                let result = block.calculate_total(2, NATIVE_ASSET);
                 assert!(result.is_some());
                 assert_eq!(result, Some(-12.34));
             }
@@ -195,10 +599,41 @@ mod tests {
             fn test_calculate_total_no_id_returns_none() {
                 let block = create_block();
 
-                let result = block.calculate_total(3);
+                let result = block.calculate_total(3, NATIVE_ASSET);
                 assert!(result.is_none());
             }
 
+            #[test]
+            fn test_calculate_total_is_per_asset() {
+                let mut block = create_block();
+                block
+                    .transactions
+                    .push(Transaction::with_asset(1, 2, 7.0, 42));
+
+                assert_eq!(block.calculate_total(1, NATIVE_ASSET), Some(12.34));
+                assert_eq!(block.calculate_total(1, 42), Some(7.0));
+                assert_eq!(block.calculate_total(2, 42), Some(-7.0));
+                assert!(block.calculate_total(1, 43).is_none());
+            }
+
+            #[test]
+            fn test_calculate_total_includes_coinbase() {
+                let mut block = create_block();
+                block.coinbase = Some(Transaction {
+                    id: 3,
+                    to: 3,
+                    from: 4,
+                    amount: 50.0,
+                    asset: NATIVE_ASSET,
+                    lock: None,
+                    kind: TransactionKind::Transfer,
+                });
+
+                assert_eq!(block.calculate_total(3, NATIVE_ASSET), Some(50.0));
+                assert_eq!(block.calculate_total(1, NATIVE_ASSET), Some(12.34));
+                assert!(block.contains_account(4));
+            }
+
             #[test]
             fn test_contains_account() {
                 let block = create_block();
@@ -212,6 +647,31 @@ mod tests {
                 let result = block.contains_account(3);
                 assert!(!result);
             }
+
+            #[test]
+            fn test_prune_keeps_hash_and_other_kinds() {
+                let mut block = create_block();
+                block.coinbase = Some(Transaction::new(3, MASTER_ID, 50.0));
+                block
+                    .transactions
+                    .push(
+                        Transaction::new(4, 4, 0.0).with_kind(TransactionKind::RegisterName {
+                            name: "alice".to_string(),
+                        }),
+                    );
+                block
+                    .transactions
+                    .push(Transaction::new(5, 2, 1.0).with_lock(TimeLock::Height(9)));
+                let hash = block.hash();
+
+                block.prune();
+
+                assert!(block.is_pruned());
+                assert_eq!(block.hash(), hash);
+                assert!(block.coinbase.is_none());
+                assert_eq!(block.transactions.len(), 2);
+                assert_eq!(block.pruned.as_ref().unwrap().transaction_ids.len(), 3);
+            }
         }
 
         mod blocks_tests {
@@ -219,40 +679,54 @@ mod tests {
 
             fn create_blocks() -> Blocks {
                 vec![
-                    Block {
-                        id: 0,
-                        transactions: vec![
+                    Block::new(
+                        0,
+                        [0; 32],
+                        vec![
                             Transaction {
                                 id: 1,
                                 to: 1,
                                 from: 2,
                                 amount: 2.34,
+                                asset: NATIVE_ASSET,
+                                lock: None,
+                                kind: TransactionKind::Transfer,
                             },
                             Transaction {
                                 id: 2,
                                 to: 1,
                                 from: 2,
                                 amount: 10.00,
+                                asset: NATIVE_ASSET,
+                                lock: None,
+                                kind: TransactionKind::Transfer,
                             },
                         ],
-                    },
-                    Block {
-                        id: 1,
-                        transactions: vec![
+                    ),
+                    Block::new(
+                        1,
+                        [0; 32],
+                        vec![
                             Transaction {
                                 id: 3,
                                 to: 1,
                                 from: 2,
                                 amount: 200.00,
+                                asset: NATIVE_ASSET,
+                                lock: None,
+                                kind: TransactionKind::Transfer,
                             },
                             Transaction {
                                 id: 4,
                                 to: 1,
                                 from: 2,
                                 amount: 3000.00,
+                                asset: NATIVE_ASSET,
+                                lock: None,
+                                kind: TransactionKind::Transfer,
                             },
                         ],
-                    },
+                    ),
                 ]
             }
 
@@ -260,11 +734,11 @@ mod tests {
             fn test_calculate_total_for_existing_id() {
                 let blocks = create_blocks();
 
-                let result = blocks.calculate_total(1);
+                let result = blocks.calculate_total(1, NATIVE_ASSET);
                 assert!(result.is_some());
                 assert_eq!(result, Some(3212.34));
 
-                let result = blocks.calculate_total(2);
+                let result = blocks.calculate_total(2, NATIVE_ASSET);
                 assert!(result.is_some());
                 assert_eq!(result, Some(-3212.34));
             }
@@ -273,7 +747,7 @@ mod tests {
             fn test_calculate_total_no_id_returns_none() {
                 let blocks = create_blocks();
 
-                let result = blocks.calculate_total(3);
+                let result = blocks.calculate_total(3, NATIVE_ASSET);
                 assert!(result.is_none());
             }
 
@@ -284,7 +758,26 @@ mod tests {
                 assert!(result);
 
                 let result = blocks.contains_transaction(1, 1);
-                assert_eq!(result, false);
+                assert!(!result);
+            }
+
+            #[test]
+            fn test_find_transaction() {
+                let blocks = create_blocks();
+
+                assert_eq!(blocks.find_transaction(1), Some(0));
+                assert_eq!(blocks.find_transaction(4), Some(1));
+                assert_eq!(blocks.find_transaction(5), None);
+            }
+
+            #[test]
+            fn test_find_account() {
+                let mut blocks = create_blocks();
+                blocks[1].transactions.push(Transaction::new(3, 1, 1.0));
+
+                assert_eq!(blocks.find_account(1), Some(0));
+                assert_eq!(blocks.find_account(3), Some(1));
+                assert_eq!(blocks.find_account(4), None);
             }
 
             #[test]
@@ -292,7 +785,7 @@ mod tests {
                 let blocks: Blocks = Vec::new();
 
                 let result = blocks.contains_transaction(0, 1);
-                assert_eq!(result, false);
+                assert!(!result);
             }
 
             #[test]
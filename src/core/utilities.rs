@@ -1,8 +1,15 @@
-use super::types::Id;
+use super::constants::{NATIVE_ASSET, NATIVE_SYMBOL};
+use super::types::{AssetId, Hash, Id};
 use sha2::{Digest, Sha256};
 use std::time::Duration;
 
-pub fn generate_id(transaction_id: Id, account_id: Id, amount: f64, duration: Duration) -> Id {
+pub fn generate_id(
+    transaction_id: Id,
+    account_id: Id,
+    amount: f64,
+    asset: AssetId,
+    duration: Duration,
+) -> Id {
     let current_time = duration.as_secs();
 
     let mut hasher = Sha256::new();
@@ -10,6 +17,7 @@ pub fn generate_id(transaction_id: Id, account_id: Id, amount: f64, duration: Du
     hasher.update(transaction_id.to_be_bytes());
     hasher.update(account_id.to_be_bytes());
     hasher.update(amount.to_be_bytes());
+    hasher.update(asset.to_be_bytes());
     hasher.update(current_time.to_be_bytes());
 
     let result = hasher.finalize();
@@ -19,6 +27,63 @@ pub fn generate_id(transaction_id: Id, account_id: Id, amount: f64, duration: Du
     u64::from_be_bytes(int_bytes)
 }
 
+/// Asset ids are the asset symbol (1 to 8 characters of `A-Z0-9`) packed
+/// into a `u64`, so the symbol can always be recovered from the id.
+pub fn asset_id(symbol: &str) -> Result<AssetId, String> {
+    let symbol = symbol.to_ascii_uppercase();
+    if symbol == NATIVE_SYMBOL {
+        return Ok(NATIVE_ASSET);
+    }
+    if symbol.is_empty()
+        || symbol.len() > 8
+        || !symbol
+            .bytes()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return Err(format!(
+            "invalid asset symbol {:?}: expected 1 to 8 letters or digits",
+            symbol
+        ));
+    }
+
+    let mut bytes = [0u8; 8];
+    bytes[..symbol.len()].copy_from_slice(symbol.as_bytes());
+    Ok(u64::from_be_bytes(bytes))
+}
+
+pub fn asset_symbol(asset: AssetId) -> String {
+    if asset == NATIVE_ASSET {
+        return NATIVE_SYMBOL.to_string();
+    }
+    asset
+        .to_be_bytes()
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .map(|byte| *byte as char)
+        .collect()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex string {:?}", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+pub fn parse_hash(hex: &str) -> Result<Hash, String> {
+    from_hex(hex)?
+        .try_into()
+        .map_err(|_| "hashes must be 32 bytes (64 hex characters)".to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -27,28 +92,78 @@ mod tests {
     fn test_generate_id_produces_same_output() {
         let duration = Duration::new(0, 0);
 
-        let id_1 = generate_id(1, 2, 1.1, duration);
-        let id_2 = generate_id(1, 2, 1.1, duration);
+        let id_1 = generate_id(1, 2, 1.1, 0, duration);
+        let id_2 = generate_id(1, 2, 1.1, 0, duration);
 
         assert_eq!(id_1, id_2);
     }
 
     #[test]
     fn test_generate_id_produces_different_outputs() {
-        let id_1 = generate_id(1, 3, 1.1, Duration::new(0, 0));
-        let id_2 = generate_id(1, 2, 1.1, Duration::new(0, 0));
+        let id_1 = generate_id(1, 3, 1.1, 0, Duration::new(0, 0));
+        let id_2 = generate_id(1, 2, 1.1, 0, Duration::new(0, 0));
         assert_ne!(id_1, id_2);
 
-        let id_1 = generate_id(2, 2, 1.1, Duration::new(0, 0));
-        let id_2 = generate_id(1, 2, 1.1, Duration::new(0, 0));
+        let id_1 = generate_id(2, 2, 1.1, 0, Duration::new(0, 0));
+        let id_2 = generate_id(1, 2, 1.1, 0, Duration::new(0, 0));
         assert_ne!(id_1, id_2);
 
-        let id_1 = generate_id(1, 2, 3.3, Duration::new(0, 0));
-        let id_2 = generate_id(1, 2, 1.1, Duration::new(0, 0));
+        let id_1 = generate_id(1, 2, 3.3, 0, Duration::new(0, 0));
+        let id_2 = generate_id(1, 2, 1.1, 0, Duration::new(0, 0));
         assert_ne!(id_1, id_2);
 
-        let id_1 = generate_id(1, 2, 1.1, Duration::new(0, 0));
-        let id_2 = generate_id(1, 2, 1.1, Duration::new(1, 0));
+        let id_1 = generate_id(1, 2, 1.1, 0, Duration::new(0, 0));
+        let id_2 = generate_id(1, 2, 1.1, 0, Duration::new(1, 0));
         assert_ne!(id_1, id_2);
+
+        let id_1 = generate_id(1, 2, 1.1, 0, Duration::new(0, 0));
+        let id_2 = generate_id(1, 2, 1.1, 1, Duration::new(0, 0));
+        assert_ne!(id_1, id_2);
+    }
+
+    #[test]
+    fn test_asset_id_round_trips_symbol() {
+        let id = asset_id("points").unwrap();
+
+        assert_ne!(id, NATIVE_ASSET);
+        assert_eq!(asset_symbol(id), "POINTS");
+        assert_eq!(asset_id("POINTS"), Ok(id));
+        assert_eq!(asset_symbol(asset_id("ABCDEFG8").unwrap()), "ABCDEFG8");
+    }
+
+    #[test]
+    fn test_asset_id_native() {
+        assert_eq!(asset_id("B"), Ok(NATIVE_ASSET));
+        assert_eq!(asset_symbol(NATIVE_ASSET), "B");
+    }
+
+    #[test]
+    fn test_asset_id_rejects_invalid_symbols() {
+        assert!(asset_id("").is_err());
+        assert!(asset_id("TOOLONGSYM").is_err());
+        assert!(asset_id("NO-DASH").is_err());
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xab]), "000fab");
+        assert_eq!(to_hex(&[]), "");
+    }
+
+    #[test]
+    fn test_from_hex_round_trip() {
+        let bytes = vec![0x00, 0x0f, 0xa0, 0xff];
+
+        assert_eq!(from_hex(&to_hex(&bytes)), Ok(bytes));
+        assert_eq!(from_hex("0A0b\n"), Ok(vec![0x0a, 0x0b]));
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("+1").is_err());
+    }
+
+    #[test]
+    fn test_parse_hash() {
+        assert_eq!(parse_hash(&"ab".repeat(32)), Ok([0xab; 32]));
+        assert!(parse_hash("abcd").is_err());
     }
 }
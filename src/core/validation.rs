@@ -0,0 +1,239 @@
+use super::address::to_address;
+use super::admin::AdminAction;
+use super::constants::{BURN_ID, MASTER_ID, NATIVE_ASSET};
+use super::types::{Transaction, TransactionKind};
+
+/// Amounts moved between accounts must be finite and greater than zero.
+/// A negative amount would move funds the other way and NaN compares false
+/// against every balance check.
+pub fn validate_amount(amount: f64) -> Result<(), String> {
+    match amount.is_finite() && amount > 0.0 {
+        true => Ok(()),
+        false => Err(format!(
+            "Amount must be a finite number greater than zero, got {}",
+            amount
+        )),
+    }
+}
+
+/// Balances set up front (starting balances, genesis allocations, sweeps of
+/// empty accounts) may also be zero.
+pub fn validate_balance(balance: f64) -> Result<(), String> {
+    match balance.is_finite() && balance >= 0.0 {
+        true => Ok(()),
+        false => Err(format!(
+            "Balance must be a finite number of zero or more, got {}",
+            balance
+        )),
+    }
+}
+
+/// Checks the amount of a transaction against the rules for its kind:
+/// mints and sweeps follow `validate_balance`, other movements of funds
+/// `validate_amount`, and transactions that move no funds must carry zero.
+pub fn validate_transaction(transaction: &Transaction) -> Result<(), String> {
+    let amount = transaction.amount();
+    match transaction.kind() {
+        TransactionKind::Admin {
+            action: AdminAction::Close { .. },
+            ..
+        } => validate_balance(amount),
+        TransactionKind::Transfer
+        | TransactionKind::EscrowOpen(_)
+        | TransactionKind::EscrowRelease { .. }
+        | TransactionKind::EscrowRefund { .. } => match transaction.from() == MASTER_ID {
+            true => validate_balance(amount),
+            false => validate_amount(amount),
+        },
+        TransactionKind::Fee { .. } => validate_amount(amount),
+        TransactionKind::Deploy { .. }
+        | TransactionKind::Call { .. }
+        | TransactionKind::RegisterName { .. }
+        | TransactionKind::TransferName { .. }
+        | TransactionKind::Admin { .. } => match amount == 0.0 {
+            true => Ok(()),
+            false => Err(format!(
+                "Transaction {} must not move funds",
+                transaction.id
+            )),
+        },
+    }
+}
+
+/// Checks that every fee among the transactions of a block is burned in the
+/// native asset by the sender of the transaction right before it, which it
+/// pays for; the two are sealed and reorganized together.
+pub fn validate_fees(transactions: &[Transaction]) -> Result<(), String> {
+    let mut previous: Option<&Transaction> = None;
+    for transaction in transactions {
+        if let TransactionKind::Fee { transaction: paid } = transaction.kind() {
+            if transaction.to() != BURN_ID || transaction.asset() != NATIVE_ASSET {
+                return Err(format!(
+                    "Fee {} is not burned in the native asset",
+                    transaction.id
+                ));
+            }
+            if !previous.is_some_and(|p| p.id == *paid && p.from() == transaction.from()) {
+                return Err(format!(
+                    "Fee {} of {} does not follow transaction {}",
+                    transaction.id,
+                    to_address(transaction.from()),
+                    paid
+                ));
+            }
+        }
+        previous = Some(transaction);
+    }
+    Ok(())
+}
+
+/// Command line pre-flight for amounts; the node checks them again.
+pub fn parse_amount(value: &str) -> Result<f64, String> {
+    let amount = value
+        .parse()
+        .map_err(|_| format!("invalid amount: {}", value))?;
+    validate_amount(amount)?;
+    Ok(amount)
+}
+
+/// Command line pre-flight for starting balances; the node checks them again.
+pub fn parse_balance(value: &str) -> Result<f64, String> {
+    let balance = value
+        .parse()
+        .map_err(|_| format!("invalid balance: {}", value))?;
+    validate_balance(balance)?;
+    Ok(balance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::snapshot::Snapshot;
+    use crate::core::types::Block;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_special_values() {
+        for amount in [0.0, -0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(validate_amount(amount).is_err(), "{}", amount);
+        }
+        assert!(validate_balance(0.0).is_ok());
+        assert!(validate_balance(-0.0).is_ok());
+        assert!(validate_balance(f64::NAN).is_err());
+        assert!(parse_amount("NaN").is_err());
+        assert!(parse_amount("inf").is_err());
+        assert!(parse_amount("-100").is_err());
+        assert_eq!(parse_amount("2.5"), Ok(2.5));
+    }
+
+    #[test]
+    fn test_zero_amount_kinds() {
+        let call = Transaction::new(1, 2, 0.0).with_kind(TransactionKind::Call {
+            args: Vec::new(),
+            gas_limit: 1,
+        });
+        assert!(validate_transaction(&call).is_ok());
+
+        let paying_call = Transaction::new(1, 2, 1.0).with_kind(call.kind().clone());
+        assert!(validate_transaction(&paying_call).is_err());
+
+        assert!(validate_transaction(&Transaction::new(1, MASTER_ID, 0.0)).is_ok());
+        assert!(validate_transaction(&Transaction::new(1, 2, 0.0)).is_err());
+    }
+
+    #[test]
+    fn test_fees_follow_their_transaction() {
+        let transfer = Transaction::new(1, 2, 5.0);
+        let fee = |from| {
+            Transaction::new(BURN_ID, from, 0.1).with_kind(TransactionKind::Fee {
+                transaction: transfer.id,
+            })
+        };
+        assert!(validate_fees(&[transfer.clone(), fee(2)]).is_ok());
+        assert!(fee(2).is_bundled_with(&transfer));
+
+        assert!(validate_fees(&[fee(2)]).is_err());
+        assert!(validate_fees(&[fee(2), transfer.clone()]).is_err());
+        assert!(validate_fees(&[transfer.clone(), fee(3)]).is_err());
+        let unburned = Transaction::new(3, 2, 0.1).with_kind(fee(2).kind().clone());
+        assert!(validate_fees(&[transfer, unburned]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_positive_finite_amounts_are_valid(amount in f64::MIN_POSITIVE..f64::MAX) {
+            prop_assert!(validate_amount(amount).is_ok());
+            prop_assert_eq!(parse_amount(&amount.to_string()), Ok(amount));
+        }
+
+        #[test]
+        fn prop_non_positive_amounts_are_rejected(amount in f64::MIN..=0.0) {
+            prop_assert!(validate_amount(amount).is_err());
+            prop_assert!(parse_amount(&amount.to_string()).is_err());
+            prop_assert!(validate_transaction(&Transaction::new(1, 2, amount)).is_err());
+        }
+
+        #[test]
+        fn prop_non_finite_amounts_are_rejected(
+            amount in prop_oneof![Just(f64::NAN), Just(f64::INFINITY), Just(f64::NEG_INFINITY)]
+        ) {
+            prop_assert!(validate_amount(amount).is_err());
+            prop_assert!(validate_balance(amount).is_err());
+            prop_assert!(parse_amount(&amount.to_string()).is_err());
+        }
+
+        /// Transfers that pass validation and the balance check move exactly
+        /// their amount plus the fee; refused ones move nothing.
+        #[test]
+        fn prop_valid_transfers_conserve_supply(
+            starting in prop::collection::vec(0.0..1e6f64, 1..5),
+            transfers in prop::collection::vec((0usize..5, 0usize..5, -1e6..1e6f64), 0..50),
+            fee in prop_oneof![Just(0.0), 0.0..10.0f64],
+        ) {
+            let accounts = starting.len();
+            let mints: Vec<Transaction> = starting
+                .iter()
+                .enumerate()
+                .map(|(account, balance)| Transaction::new(account as u64, MASTER_ID, *balance))
+                .collect();
+            let mut snapshot = Snapshot::of(&[Block::new(0, [0; 32], mints)]);
+            let balance = |snapshot: &Snapshot, account: usize| {
+                snapshot.balances[&0].get(&(account as u64)).copied().unwrap_or(0.0)
+            };
+
+            for (from, to, amount) in transfers {
+                let (from, to) = (from % accounts, to % accounts);
+                let transfer = Transaction::new(to as u64, from as u64, amount);
+                let mut bundle = vec![transfer.clone()];
+                if fee > 0.0 {
+                    bundle.push(Transaction::new(BURN_ID, from as u64, fee).with_kind(
+                        TransactionKind::Fee { transaction: transfer.id },
+                    ));
+                }
+                let before = snapshot.clone();
+                let accepted = bundle.iter().try_for_each(validate_transaction).is_ok()
+                    && validate_fees(&bundle).is_ok()
+                    && snapshot.spend(&bundle).is_ok();
+
+                match accepted {
+                    true => {
+                        let paid = amount + fee;
+                        let lost = balance(&before, from) - balance(&snapshot, from);
+                        let gained = balance(&snapshot, to) - balance(&before, to);
+                        match from == to {
+                            true => prop_assert!((lost - fee).abs() <= 1e-6),
+                            false => {
+                                prop_assert!((lost - paid).abs() <= 1e-6);
+                                prop_assert!((gained - amount).abs() <= 1e-6);
+                            }
+                        }
+                    }
+                    false => prop_assert_eq!(&snapshot, &before),
+                }
+                for account in 0..accounts {
+                    prop_assert!(balance(&snapshot, account) >= -1e-6);
+                }
+            }
+        }
+    }
+}
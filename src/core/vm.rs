@@ -0,0 +1,443 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+pub type Word = i64;
+pub type Program = Vec<Instruction>;
+pub type Storage = BTreeMap<Word, Word>;
+
+pub const MAX_PROGRAM_LENGTH: usize = 1024;
+pub const MAX_STACK_DEPTH: usize = 1024;
+pub const DEFAULT_GAS_LIMIT: u64 = 10_000;
+pub const MAX_GAS_LIMIT: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Instruction {
+    Push(Word),
+    Pop,
+    Dup,
+    Swap,
+    Over,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Lt,
+    Gt,
+    Not,
+    /// Jumps to the instruction at the given index
+    Jump(usize),
+    /// Pops a condition and jumps if it is not zero
+    JumpIf(usize),
+    /// Pops a key and pushes its stored value (0 if unset)
+    Load,
+    /// Pops a value, then a key, and stores the value under the key
+    Store,
+    /// Pushes the account id of the caller
+    Caller,
+    /// Pops an index and pushes that call argument
+    Arg,
+    /// Pushes the number of call arguments
+    ArgCount,
+    /// Pops a value and ends the call successfully with it as the result
+    Return,
+    /// Ends the call successfully without a result
+    Stop,
+    /// Ends the call and discards every storage write
+    Revert,
+}
+
+impl Instruction {
+    fn gas(&self) -> u64 {
+        match self {
+            Instruction::Load => 5,
+            Instruction::Store => 20,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Execution {
+    pub result: Option<Word>,
+    pub gas_used: u64,
+    /// Storage after the call
+    pub storage: Storage,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VmError {
+    pub message: String,
+    pub gas_used: u64,
+}
+
+/// Translates contract source into a program.
+///
+/// Every line holds one instruction (`push 5`, `add`, `jumpi done`, ...) or a
+/// label (`done:`). Jump targets are labels or instruction indices; `#` starts
+/// a comment.
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let lines: Vec<(usize, &str)> = source
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+
+    let mut labels = HashMap::new();
+    let mut index = 0;
+    for (number, line) in &lines {
+        match line.strip_suffix(':') {
+            Some(label) => {
+                if labels.insert(label.to_string(), index).is_some() {
+                    return Err(format!("line {}: label {} is defined twice", number, label));
+                }
+            }
+            None => index += 1,
+        }
+    }
+
+    let mut program = Vec::new();
+    for (number, line) in lines {
+        if line.ends_with(':') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let name = parts.next().unwrap_or("").to_ascii_lowercase();
+        let operand = parts.next();
+        if parts.next().is_some() {
+            return Err(format!("line {}: too many operands", number));
+        }
+
+        let target = |operand: Option<&str>| -> Result<usize, String> {
+            let operand = operand.ok_or(format!("line {}: {} needs a target", number, name))?;
+            match labels.get(operand) {
+                Some(index) => Ok(*index),
+                None => operand
+                    .parse()
+                    .map_err(|_| format!("line {}: unknown label {}", number, operand)),
+            }
+        };
+
+        let instruction = match name.as_str() {
+            "push" => Instruction::Push(
+                operand
+                    .ok_or(format!("line {}: push needs a value", number))?
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid number", number))?,
+            ),
+            "jump" => Instruction::Jump(target(operand)?),
+            "jumpi" => Instruction::JumpIf(target(operand)?),
+            _ if operand.is_some() => {
+                return Err(format!("line {}: {} takes no operand", number, name))
+            }
+            "pop" => Instruction::Pop,
+            "dup" => Instruction::Dup,
+            "swap" => Instruction::Swap,
+            "over" => Instruction::Over,
+            "add" => Instruction::Add,
+            "sub" => Instruction::Sub,
+            "mul" => Instruction::Mul,
+            "div" => Instruction::Div,
+            "mod" => Instruction::Mod,
+            "eq" => Instruction::Eq,
+            "lt" => Instruction::Lt,
+            "gt" => Instruction::Gt,
+            "not" => Instruction::Not,
+            "load" => Instruction::Load,
+            "store" => Instruction::Store,
+            "caller" => Instruction::Caller,
+            "arg" => Instruction::Arg,
+            "argc" => Instruction::ArgCount,
+            "return" => Instruction::Return,
+            "stop" => Instruction::Stop,
+            "revert" => Instruction::Revert,
+            _ => return Err(format!("line {}: unknown instruction {}", number, name)),
+        };
+        program.push(instruction);
+    }
+
+    validate(&program)?;
+    Ok(program)
+}
+
+pub fn validate(program: &Program) -> Result<(), String> {
+    if program.is_empty() {
+        return Err("programs must not be empty".to_string());
+    }
+    if program.len() > MAX_PROGRAM_LENGTH {
+        return Err(format!(
+            "programs are limited to {} instructions",
+            MAX_PROGRAM_LENGTH
+        ));
+    }
+    for instruction in program {
+        if let Instruction::Jump(target) | Instruction::JumpIf(target) = instruction {
+            if *target >= program.len() {
+                return Err(format!("jump target {} is out of the program", target));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs `program` against `storage`. Running off the end of the program
+/// behaves like `stop`.
+pub fn execute(
+    program: &Program,
+    storage: &Storage,
+    caller: Word,
+    args: &[Word],
+    gas_limit: u64,
+) -> Result<Execution, VmError> {
+    let mut storage = storage.clone();
+    let mut stack: Vec<Word> = Vec::new();
+    let mut gas_used = 0;
+    let mut pc = 0;
+
+    let fail = |message: &str, gas_used| VmError {
+        message: message.to_string(),
+        gas_used,
+    };
+
+    while let Some(instruction) = program.get(pc) {
+        gas_used += instruction.gas();
+        if gas_used > gas_limit {
+            return Err(fail("out of gas", gas_limit));
+        }
+        pc += 1;
+
+        macro_rules! pop {
+            () => {
+                stack
+                    .pop()
+                    .ok_or_else(|| fail("stack underflow", gas_used))?
+            };
+        }
+
+        match *instruction {
+            Instruction::Push(value) => stack.push(value),
+            Instruction::Pop => {
+                pop!();
+            }
+            Instruction::Dup => {
+                let value = pop!();
+                stack.extend([value, value]);
+            }
+            Instruction::Swap => {
+                let (b, a) = (pop!(), pop!());
+                stack.extend([b, a]);
+            }
+            Instruction::Over => {
+                let (b, a) = (pop!(), pop!());
+                stack.extend([a, b, a]);
+            }
+            Instruction::Add => {
+                let (b, a) = (pop!(), pop!());
+                stack.push(a.wrapping_add(b));
+            }
+            Instruction::Sub => {
+                let (b, a) = (pop!(), pop!());
+                stack.push(a.wrapping_sub(b));
+            }
+            Instruction::Mul => {
+                let (b, a) = (pop!(), pop!());
+                stack.push(a.wrapping_mul(b));
+            }
+            Instruction::Div | Instruction::Mod => {
+                let (b, a) = (pop!(), pop!());
+                if b == 0 {
+                    return Err(fail("division by zero", gas_used));
+                }
+                stack.push(match instruction {
+                    Instruction::Div => a.wrapping_div(b),
+                    _ => a.wrapping_rem(b),
+                });
+            }
+            Instruction::Eq => {
+                let (b, a) = (pop!(), pop!());
+                stack.push((a == b) as Word);
+            }
+            Instruction::Lt => {
+                let (b, a) = (pop!(), pop!());
+                stack.push((a < b) as Word);
+            }
+            Instruction::Gt => {
+                let (b, a) = (pop!(), pop!());
+                stack.push((a > b) as Word);
+            }
+            Instruction::Not => {
+                let value = pop!();
+                stack.push((value == 0) as Word);
+            }
+            Instruction::Jump(target) => pc = target,
+            Instruction::JumpIf(target) => {
+                if pop!() != 0 {
+                    pc = target;
+                }
+            }
+            Instruction::Load => {
+                let key = pop!();
+                stack.push(storage.get(&key).copied().unwrap_or(0));
+            }
+            Instruction::Store => {
+                let (value, key) = (pop!(), pop!());
+                match value {
+                    0 => storage.remove(&key),
+                    _ => storage.insert(key, value),
+                };
+            }
+            Instruction::Caller => stack.push(caller),
+            Instruction::Arg => {
+                let index = pop!();
+                let arg = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| args.get(index))
+                    .ok_or_else(|| fail("argument index out of range", gas_used))?;
+                stack.push(*arg);
+            }
+            Instruction::ArgCount => stack.push(args.len() as Word),
+            Instruction::Return => {
+                let result = pop!();
+                return Ok(Execution {
+                    result: Some(result),
+                    gas_used,
+                    storage,
+                });
+            }
+            Instruction::Stop => break,
+            Instruction::Revert => return Err(fail("reverted", gas_used)),
+        }
+
+        if stack.len() > MAX_STACK_DEPTH {
+            return Err(fail("stack overflow", gas_used));
+        }
+    }
+
+    Ok(Execution {
+        result: None,
+        gas_used,
+        storage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds the first argument to a counter stored under key 0 and returns it.
+    const COUNTER: &str = "
+        push 0
+        push 0
+        load
+        push 0
+        arg
+        add      # counter + argument
+        store
+        push 0
+        load
+        return
+    ";
+
+    /// Transfers `amount` (argument 1) tokens to `account` (argument 0), reverting if the caller has too few.
+    const TOKEN: &str = "
+        caller
+        load
+        push 1
+        arg
+        lt
+        jumpi fail
+        caller
+        caller
+        load
+        push 1
+        arg
+        sub
+        store
+        push 0
+        arg
+        push 0
+        arg
+        load
+        push 1
+        arg
+        add
+        store
+        stop
+    fail:
+        revert
+    ";
+
+    #[test]
+    fn test_counter() {
+        let program = assemble(COUNTER).unwrap();
+
+        let first = execute(&program, &Storage::new(), 1, &[5], DEFAULT_GAS_LIMIT).unwrap();
+        assert_eq!(first.result, Some(5));
+
+        let second = execute(&program, &first.storage, 1, &[3], DEFAULT_GAS_LIMIT).unwrap();
+        assert_eq!(second.result, Some(8));
+        assert_eq!(second.storage[&0], 8);
+    }
+
+    #[test]
+    fn test_token_transfer_and_revert() {
+        let program = assemble(TOKEN).unwrap();
+        let storage = Storage::from([(1, 100)]);
+
+        let execution = execute(&program, &storage, 1, &[2, 30], DEFAULT_GAS_LIMIT).unwrap();
+        assert_eq!(execution.storage[&1], 70);
+        assert_eq!(execution.storage[&2], 30);
+
+        let error = execute(&program, &storage, 1, &[2, 300], DEFAULT_GAS_LIMIT).unwrap_err();
+        assert_eq!(error.message, "reverted");
+    }
+
+    #[test]
+    fn test_gas_metering() {
+        let program = assemble("loop:\njump loop").unwrap();
+
+        let error = execute(&program, &Storage::new(), 1, &[], 100).unwrap_err();
+        assert_eq!(error.message, "out of gas");
+        assert_eq!(error.gas_used, 100);
+
+        let counter = assemble(COUNTER).unwrap();
+        let execution = execute(&counter, &Storage::new(), 1, &[1], DEFAULT_GAS_LIMIT).unwrap();
+        assert_eq!(execution.gas_used, 37);
+        assert!(execute(&counter, &Storage::new(), 1, &[1], 36).is_err());
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let run = |source| execute(&assemble(source).unwrap(), &Storage::new(), 1, &[], 100);
+
+        assert_eq!(run("add").unwrap_err().message, "stack underflow");
+        assert_eq!(
+            run("push 1\npush 0\ndiv").unwrap_err().message,
+            "division by zero"
+        );
+        assert_eq!(
+            run("push 0\narg").unwrap_err().message,
+            "argument index out of range"
+        );
+        assert_eq!(run("push 1").unwrap().result, None);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert!(assemble("").is_err());
+        assert!(assemble("jump nowhere").is_err());
+        assert!(assemble("jump 3").is_err());
+        assert!(assemble("push").is_err());
+        assert!(assemble("add 1").is_err());
+        assert!(assemble("fly").is_err());
+        assert!(assemble("a:\na:\nstop").is_err());
+    }
+
+    #[test]
+    fn test_assemble_labels_and_comments() {
+        let program = assemble("# comment\nstart:\n  PUSH 1 # one\n  jumpi start\n").unwrap();
+        assert_eq!(program, vec![Instruction::Push(1), Instruction::JumpIf(0)]);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::client::ClientCommands;
+use crate::core::address::to_address;
+use crate::core::constants::NATIVE_SYMBOL;
+use crate::core::names::AccountRef;
+use crate::core::types::{AssetId, BlockId};
+use crate::core::utilities::asset_id;
+use crate::rpc::handle_rpc;
+use crate::server::{
+    handle_command, CommandError, NodeEvent, NodeState, ReorgNotice, ServerResponse, Session,
+};
+use log::{debug, error, info};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::thread;
+use tiny_http::{Header, ReadWrite, Request, Response, Server};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+#[derive(Deserialize)]
+struct CreateAccountRequest {
+    starting_balance: f64,
+    #[serde(default = "one_confirmation")]
+    confirmations: u64,
+}
+
+#[derive(Deserialize)]
+struct TransferRequest {
+    /// Address or name
+    from: String,
+    /// Address or name
+    to: String,
+    amount: f64,
+    /// Symbol of the asset
+    #[serde(default = "native_symbol")]
+    asset: String,
+    #[serde(default = "one_confirmation")]
+    confirmations: u64,
+}
+
+fn one_confirmation() -> u64 {
+    1
+}
+
+fn native_symbol() -> String {
+    NATIVE_SYMBOL.to_string()
+}
+
+/// A failed request: the HTTP status and the message sent as `{"error": ...}`.
+type Failure = (u16, String);
+
+/// Answers JSON requests on `port` for the ledger of `state`, one thread per
+/// request, in the background.
+pub(crate) fn start_http(state: NodeState, port: u16) -> Result<(), String> {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let server = Server::http(address)
+        .map_err(|e| format!("Error starting the HTTP gateway on port {} -- {}", port, e))?;
+    info!("HTTP gateway listening on port {}", port);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let request_state = state.clone();
+            thread::spawn(move || handle_request(&request_state, request));
+        }
+    });
+    Ok(())
+}
+
+fn handle_request(state: &NodeState, mut request: Request) {
+    let method = request.method().as_str().to_string();
+    let url = request.url().to_string();
+    if method == "GET" && url.split('?').next() == Some("/events") {
+        return stream_events(state, request);
+    }
+
+    let mut body = String::new();
+    let (status, value) = match request.as_reader().read_to_string(&mut body) {
+        Ok(_) => route(state, &method, &url, &body),
+        Err(e) => (
+            400,
+            json!({ "error": format!("could not read the body: {}", e) }),
+        ),
+    };
+    debug!("HTTP {} {} -> {}", method, url, status);
+    respond(request, status, value);
+}
+
+fn respond(request: Request, status: u16, value: Value) {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    // Nothing is sent back for JSON-RPC notifications
+    let content = match status {
+        204 => String::new(),
+        _ => value.to_string(),
+    };
+    let response = Response::from_string(content)
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        error!("Failed to answer HTTP request: {}", e);
+    }
+}
+
+/// Upgrades a request for `/events` to a WebSocket and streams the node's
+/// events on it as JSON text messages, only those of `?account=` if given.
+fn stream_events(state: &NodeState, request: Request) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| header.value.to_string());
+    let account = request
+        .url()
+        .split_once('?')
+        .and_then(|(_, query)| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| *name == "account")
+        })
+        .map(|(_, account)| account_ref(account))
+        .transpose();
+    let (key, account) = match (key, account) {
+        (Some(key), Ok(account)) => (key, account),
+        (None, _) => {
+            let error = json!({ "error": "/events needs a WebSocket upgrade" });
+            return respond(request, 400, error);
+        }
+        (_, Err((status, e))) => return respond(request, status, json!({ "error": e })),
+    };
+
+    let header = |field: &str, value: &str| Header::from_bytes(field, value).unwrap();
+    let response = Response::empty(101)
+        .with_header(header("Upgrade", "websocket"))
+        .with_header(header("Connection", "Upgrade"))
+        .with_header(header(
+            "Sec-WebSocket-Accept",
+            &derive_accept_key(key.as_bytes()),
+        ));
+    let stream = request.upgrade("websocket", response);
+    let mut session = WebSocketSession {
+        socket: WebSocket::from_raw_socket(stream, Role::Server, None),
+    };
+    if let Err(e) = handle_command(state, ClientCommands::Subscribe { account }, &mut session) {
+        debug!("WebSocket subscription ended: {}", e);
+        let _ = session
+            .socket
+            .send(Message::Text(json!({ "error": e.to_string() }).to_string()));
+        let _ = session.socket.close(None);
+    }
+}
+
+/// Status and JSON body of the answer to a request.
+fn route(state: &NodeState, method: &str, url: &str, body: &str) -> (u16, Value) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query: BTreeMap<&str, &str> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["blocks"]) => return blocks(state, &query),
+        ("GET", ["blocks", "head"]) => return (200, json!(state.blocks().head())),
+        ("GET", ["blocks", block_id]) => return block(state, block_id),
+        ("POST", ["rpc"]) => {
+            return match handle_rpc(state, body) {
+                Some(response) => (200, response),
+                None => (204, Value::Null),
+            }
+        }
+        _ => {}
+    }
+
+    let command = command(method, path, &segments, &query, body);
+
+    let mut session = HttpSession;
+    match command.and_then(|command| {
+        handle_command(state, command, &mut session).map_err(|e| (error_status(&e), e.to_string()))
+    }) {
+        Ok(response) => (200, response_body(&response)),
+        Err((status, e)) => (status, json!({ "error": e })),
+    }
+}
+
+/// Status of an error from the node: 404 when an account, name or
+/// transaction the request names does not exist, 400 otherwise.
+fn error_status(error: &CommandError) -> u16 {
+    match error {
+        CommandError::NotFound(_) => 404,
+        CommandError::Refused(_) => 400,
+    }
+}
+
+/// Command behind a request for the ledger; 404 when no endpoint matches.
+fn command(
+    method: &str,
+    path: &str,
+    segments: &[&str],
+    query: &BTreeMap<&str, &str>,
+    body: &str,
+) -> Result<ClientCommands, Failure> {
+    Ok(match (method, segments) {
+        ("GET", ["chain"]) => ClientCommands::ChainInfo,
+        ("GET", ["supply"]) => ClientCommands::Supply {
+            asset: asset(query)?,
+        },
+        ("POST", ["accounts"]) => {
+            let request: CreateAccountRequest = parse_body(body)?;
+            ClientCommands::CreateAccount {
+                starting_balance: request.starting_balance,
+                confirmations: request.confirmations,
+            }
+        }
+        ("GET", ["accounts", account]) => ClientCommands::AccountInfo {
+            account: account_ref(account)?,
+        },
+        ("GET", ["accounts", account, "balance"]) => ClientCommands::Balance {
+            account: account_ref(account)?,
+            asset: asset(query)?,
+        },
+        ("GET", ["accounts", account, "history"]) => ClientCommands::History {
+            account: account_ref(account)?,
+            from: parameter(query, "from")?.unwrap_or(0),
+        },
+        ("POST", ["transfers"]) => {
+            let request: TransferRequest = parse_body(body)?;
+            ClientCommands::Transfer {
+                from_account: account_ref(&request.from)?,
+                to_account: account_ref(&request.to)?,
+                amount: request.amount,
+                asset: asset_id(&request.asset).map_err(|e| (400, e))?,
+                unlock_height: None,
+                unlock_time: None,
+                confirmations: request.confirmations,
+            }
+        }
+        ("GET", ["transactions", transaction_id]) => ClientCommands::GetTx {
+            transaction_id: path_id(transaction_id)?,
+        },
+        ("GET", ["transactions", transaction_id, "status"]) => ClientCommands::TransactionStatus {
+            transaction_id: path_id(transaction_id)?,
+        },
+        _ => return Err((404, format!("No endpoint for {} {}", method, path))),
+    })
+}
+
+/// The fields of a response without the name of its variant. A new account
+/// is given by its address, which paths and bodies take back, rather than an
+/// id JSON numbers may round.
+pub(crate) fn response_body(response: &ServerResponse) -> Value {
+    let mut body = match serde_json::to_value(response) {
+        Ok(Value::Object(variant)) if variant.len() == 1 => variant
+            .into_iter()
+            .next()
+            .map(|(_, fields)| fields)
+            .unwrap(),
+        Ok(value) => value,
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+    if let ServerResponse::AccountCreated { account, .. } = response {
+        body["account"] = json!(to_address(*account));
+    }
+    body
+}
+
+fn blocks(state: &NodeState, query: &BTreeMap<&str, &str>) -> (u16, Value) {
+    let range = || -> Result<(BlockId, Option<BlockId>), Failure> {
+        Ok((
+            parameter(query, "from")?.unwrap_or(0),
+            parameter(query, "to")?,
+        ))
+    };
+    let (from, to) = match range() {
+        Ok(range) => range,
+        Err((status, e)) => return (status, json!({ "error": e })),
+    };
+    let blocks = state.blocks();
+    let to = to.map_or(blocks.head().id, |to| to.min(blocks.head().id));
+    let chain = blocks.chain();
+    let range = chain.get(from as usize..=to as usize).unwrap_or_default();
+    (200, json!(range))
+}
+
+fn block(state: &NodeState, block_id: &str) -> (u16, Value) {
+    let block_id: BlockId = match path_id(block_id) {
+        Ok(block_id) => block_id,
+        Err((status, e)) => return (status, json!({ "error": e })),
+    };
+    let blocks = state.blocks();
+    match blocks.chain().get(block_id as usize) {
+        Some(block) => (200, json!(block)),
+        None => (
+            404,
+            json!({ "error": format!("No block {} (head block is {})", block_id, blocks.head().id) }),
+        ),
+    }
+}
+
+fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T, Failure> {
+    serde_json::from_str(body).map_err(|e| (400, format!("invalid request body: {}", e)))
+}
+
+fn account_ref(account: &str) -> Result<AccountRef, Failure> {
+    AccountRef::from_str(account).map_err(|e| (400, e))
+}
+
+fn path_id<T: FromStr>(value: &str) -> Result<T, Failure> {
+    value
+        .parse()
+        .map_err(|_| (400, format!("invalid id: {}", value)))
+}
+
+fn parameter<T: FromStr>(query: &BTreeMap<&str, &str>, name: &str) -> Result<Option<T>, Failure> {
+    query
+        .get(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| (400, format!("invalid {}: {}", name, value)))
+        })
+        .transpose()
+}
+
+fn asset(query: &BTreeMap<&str, &str>) -> Result<AssetId, Failure> {
+    asset_id(query.get("asset").copied().unwrap_or(NATIVE_SYMBOL)).map_err(|e| (400, e))
+}
+
+/// Requests are answered once, so reorganizations seen while waiting for
+/// confirmations are only logged.
+pub(crate) struct HttpSession;
+
+impl Session for HttpSession {
+    fn notify_reorg(&mut self, notice: &ReorgNotice) {
+        debug!("HTTP request saw a reorganization: {:?}", notice);
+    }
+}
+
+struct WebSocketSession {
+    socket: WebSocket<Box<dyn ReadWrite + Send>>,
+}
+
+impl Session for WebSocketSession {
+    fn notify_reorg(&mut self, notice: &ReorgNotice) {
+        debug!("WebSocket subscriber saw a reorganization: {:?}", notice);
+    }
+
+    fn send_event(&mut self, event: &NodeEvent) -> Result<(), String> {
+        let event = serde_json::to_string(event).map_err(|e| e.to_string())?;
+        self.socket
+            .send(Message::Text(event))
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::NodeOptions;
+    use crate::Node;
+
+    fn create_state() -> NodeState {
+        Node::for_tests(NodeOptions::default()).into_state()
+    }
+
+    fn get(state: &NodeState, url: &str) -> (u16, Value) {
+        route(state, "GET", url, "")
+    }
+
+    #[test]
+    fn test_read_endpoints() {
+        let state = create_state();
+
+        let (status, chain) = get(&state, "/chain");
+        assert_eq!(status, 200);
+        assert_eq!(chain["head_block_id"], 0);
+
+        let (status, blocks) = get(&state, "/blocks?from=0");
+        assert_eq!(status, 200);
+        assert_eq!(blocks.as_array().map(Vec::len), Some(1));
+        assert_eq!(get(&state, "/blocks/head").1["id"], 0);
+        assert_eq!(get(&state, "/blocks/1").0, 404);
+
+        let (status, supply) = get(&state, "/supply?asset=B");
+        assert_eq!(status, 200);
+        assert_eq!(supply["supply"]["minted"], 0.0);
+    }
+
+    #[test]
+    fn test_created_accounts_are_given_by_address() {
+        // Answered as POST /accounts answers, on a node that seals the block
+        let node = Node::for_tests(NodeOptions::default());
+        let created = node
+            .execute(ClientCommands::CreateAccount {
+                starting_balance: 100.0,
+                confirmations: 1,
+            })
+            .unwrap();
+        let state = node.into_state();
+        let created = response_body(&created);
+        let address = created["account"].as_str().unwrap();
+
+        let (status, balance) = get(&state, &format!("/accounts/{}/balance", address));
+        assert_eq!(status, 200);
+        assert_eq!(balance["balance"], 100.0);
+        assert_eq!(get(&state, &format!("/accounts/{}", address)).0, 200);
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let state = create_state();
+
+        assert_eq!(get(&state, "/nowhere").0, 404);
+        assert_eq!(route(&state, "DELETE", "/chain", "").0, 404);
+        assert_eq!(get(&state, "/accounts/B1/balance").0, 400);
+        assert_eq!(get(&state, "/blocks/x").0, 400);
+        assert_eq!(get(&state, "/supply?asset=!").0, 400);
+        assert_eq!(route(&state, "POST", "/transfers", "{").0, 400);
+
+        let (status, refused) = route(
+            &state,
+            "POST",
+            "/transfers",
+            &format!(
+                r#"{{"from": "{}", "to": "{}", "amount": 5}}"#,
+                to_address(12345),
+                to_address(67890)
+            ),
+        );
+        assert_eq!(status, 404);
+        assert!(refused["error"].is_string());
+
+        let unknown = format!("/accounts/{}/balance", to_address(4242));
+        assert_eq!(get(&state, &unknown).0, 404);
+        assert_eq!(get(&state, "/transactions/7").0, 404);
+        assert_eq!(get(&state, "/transactions/7/status").0, 404);
+    }
+}
@@ -0,0 +1,15 @@
+//! Ledger of the `b` blockchain emulator. `Node` embeds a node in the calling
+//! process and `Client` talks to one started with `b start-node`.
+
+pub mod client;
+pub mod core;
+mod http;
+pub mod node;
+mod rpc;
+pub mod scenario;
+pub mod server;
+pub mod shell;
+pub mod simulation;
+
+pub use client::Client;
+pub use node::Node;
@@ -1,11 +1,16 @@
-mod client;
-mod core;
-mod server;
-
+use b::client::{generate_key, run_client, ClientCommands};
+use b::core::dump::DumpFormat;
+use b::core::genesis::Genesis;
+use b::core::keys::{parse_key, PublicKey};
+use b::core::rules::ChainRules;
+use b::scenario::run_scenario;
+use b::server::{import_dump, start_node, NodeOptions};
+use b::shell::run_shell;
+use b::simulation::{run_simulation, SimulationOptions};
 use clap::{Parser, Subcommand};
-use client::{run_client, ClientCommands};
-use log::info;
-use server::start_node;
+use log::{error, info};
+use std::path::PathBuf;
+use std::process;
 
 #[derive(Parser)]
 #[clap(
@@ -23,7 +28,7 @@ struct Cli {
     #[clap(short, long, action = clap::ArgAction::SetTrue)]
     verbose: bool,
 
-    /// Sets timer interval (in seconds) for start-node command
+    /// Sets timer interval (in seconds) for start-node command (target block time with --consensus pow)
     #[clap(short, long, default_value = "10")]
     interval: u64,
 
@@ -33,17 +38,54 @@ struct Cli {
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum Commands {
-    StartNode,
+    StartNode(NodeOptions),
+    /// Writes a new signing key to a file and prints its public key
+    Keygen {
+        path: PathBuf,
+    },
+    /// Loads a dump written by `export` into the data directory of a new node
+    Import {
+        path: PathBuf,
+        /// Encoding of the dump
+        #[clap(long, value_enum, default_value = "jsonl")]
+        format: DumpFormat,
+        /// Data directory to pass to `start-node --data-dir`
+        #[clap(long)]
+        data_dir: PathBuf,
+        /// Genesis file the node was started with (default: the local chain with the `-i` interval)
+        #[clap(long)]
+        genesis: Option<PathBuf>,
+        /// Operator public key (hex) the admin transactions of the dump have to be signed with
+        #[clap(long, value_parser = parse_key)]
+        admin_key: Option<PublicKey>,
+        /// Initial difficulty the node was started with under `--consensus pow`; without it the blocks must not be mined
+        #[clap(long)]
+        difficulty: Option<u32>,
+    },
+    /// Runs nodes and clients in-process from a seed, checking invariants after every step
+    Simulate(SimulationOptions),
+    /// Starts an interactive shell that sends commands over one connection to the node
+    Shell,
+    /// Runs the steps of a YAML scenario against the node and reports the ones that fail
+    RunScenario {
+        path: PathBuf,
+    },
     #[clap(flatten)]
     Client(ClientCommands), // Include ClientCommands as a variant
 }
 
-fn setup_logger(verbose: bool) -> Result<(), fern::InitError> {
+fn setup_logger(verbose: bool, quiet_node: bool) -> Result<(), fern::InitError> {
     let log_level = if verbose {
         log::LevelFilter::Debug
     } else {
         log::LevelFilter::Info
     };
+    // Simulated nodes only report problems unless asked for more
+    let node_level = if quiet_node && !verbose {
+        log::LevelFilter::Warn
+    } else {
+        log_level
+    };
 
     fern::Dispatch::new()
         .format(|out, message, record| {
@@ -55,6 +97,7 @@ fn setup_logger(verbose: bool) -> Result<(), fern::InitError> {
         })
         .level(log::LevelFilter::Info)
         .level_for("b", log_level)
+        .level_for("b::server", node_level)
         .chain(std::io::stdout())
         .apply()?;
     Ok(())
@@ -63,16 +106,83 @@ fn setup_logger(verbose: bool) -> Result<(), fern::InitError> {
 fn main() {
     let cli = Cli::parse();
 
-    setup_logger(cli.verbose).expect("Failed to initialize logger");
+    let simulating = matches!(cli.command, Commands::Simulate(_));
+    setup_logger(cli.verbose, simulating).expect("Failed to initialize logger");
 
     match cli.command {
-        Commands::StartNode => {
+        Commands::StartNode(options) => {
             info!("Starting the node server on port {}...", cli.port);
-            let _ = start_node(cli.port, cli.interval);
+            let _ = start_node(cli.port, cli.interval, options);
+        }
+        Commands::Keygen { path } => generate_key(&path),
+        Commands::Import {
+            path,
+            format,
+            data_dir,
+            genesis,
+            admin_key,
+            difficulty,
+        } => match genesis
+            .map_or_else(|| Ok(Genesis::local(cli.interval)), |path| Genesis::load(&path))
+            .and_then(|genesis| {
+                let rules = ChainRules::new(&genesis, admin_key, difficulty);
+                import_dump(&path, format, &data_dir, &genesis, &rules)
+            })
+        {
+            Ok(head_block_id) => info!(
+                "imported blocks 0 to {} into {}",
+                head_block_id,
+                data_dir.display()
+            ),
+            Err(e) => error!("{}", e),
+        },
+        Commands::Simulate(options) => match run_simulation(cli.interval, options) {
+            Ok(report) => info!(
+                "seed {} passed\n\thead_block_id: {}\n\taccounts: {}\n\tcommands: {} ({} refused)\n\tmessages: {} delivered, {} dropped\n\tsplit_nodes: {}\n\tdigest: {}",
+                report.seed,
+                report.head_block_id,
+                report.accounts,
+                report.commands,
+                report.refused,
+                report.delivered,
+                report.dropped,
+                report.splits,
+                report.digest
+            ),
+            // A failing seed fails the run so CI notices and it can be replayed
+            Err(e) => {
+                error!("{}", e);
+                process::exit(1);
+            }
+        },
+        Commands::Shell => run_shell(cli.port),
+        Commands::RunScenario { path } => {
+            let passed = match run_scenario(&path, cli.port) {
+                Ok(report) => {
+                    info!(
+                        "scenario {}: {} passed, {} failed",
+                        report.name,
+                        report.passed,
+                        report.failures.len()
+                    );
+                    for failure in &report.failures {
+                        info!("\t{}", failure);
+                    }
+                    report.failures.is_empty()
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    false
+                }
+            };
+            // Scripts and CI jobs tell the outcome from the exit status
+            if !passed {
+                process::exit(1);
+            }
         }
         Commands::Client(client_command) => {
             info!("Connecting to node on port {}...", cli.port);
-            let _ = run_client(client_command, cli.port);
+            run_client(client_command, cli.port);
         }
     }
 }
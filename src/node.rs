@@ -0,0 +1,623 @@
+use crate::client::ClientCommands;
+use crate::core::constants::NATIVE_ASSET;
+use crate::core::env::{Clock, Random, SystemClock, SystemRandom};
+#[cfg(test)]
+use crate::core::env::{ManualClock, SeededRandom};
+use crate::core::names::AccountRef;
+use crate::core::types::{AccountId, Block, TransactionId};
+use crate::server::{
+    handle_command, open_node, seal_block, ChainEvent, NodeEvent, NodeOptions, NodeState,
+    ReorgNotice, ServerResponse, Session,
+};
+#[cfg(test)]
+use crate::simulation::EPOCH;
+use log::debug;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+/// A node embedded in the calling process. It runs the same commands as a
+/// node started with `b start-node`, but nothing listens for clients and no
+/// block processor runs: blocks are sealed by `seal_block`, or on the spot by
+/// commands that wait for confirmations.
+///
+/// Blocks it seals are sent to `options.peers` over TCP; as the node does not
+/// listen itself, peers cannot send blocks back.
+pub struct Node {
+    state: NodeState,
+    options: NodeOptions,
+}
+
+impl Node {
+    /// Opens a node with the system clock; `interval` is the block interval of
+    /// the local chain used when `options.genesis` is not set.
+    pub fn new(interval: u64, options: NodeOptions) -> Result<Node, String> {
+        Node::with_env(
+            interval,
+            options,
+            Arc::new(SystemClock),
+            Arc::new(SystemRandom),
+        )
+    }
+
+    /// Opens a node that reads time and picks account ids from the given
+    /// sources, e.g. a `ManualClock` and a `SeededRandom` for repeatable tests.
+    pub fn with_env(
+        interval: u64,
+        options: NodeOptions,
+        clock: Arc<dyn Clock>,
+        random: Arc<dyn Random>,
+    ) -> Result<Node, String> {
+        let (_, state) = open_node(0, interval, &options, clock, random)?;
+        Ok(Node { state, options })
+    }
+
+    /// Runs a command as if a client on this machine had sent it. Commands that
+    /// need a signature or assembled code have to be prepared the way the CLI does.
+    pub fn execute(&self, command: ClientCommands) -> Result<ServerResponse, String> {
+        let mut session = EmbeddedSession {
+            options: &self.options,
+        };
+        handle_command(&self.state, command, &mut session).map_err(|e| e.to_string())
+    }
+
+    /// Creates an account and returns its id once the mint is sealed.
+    pub fn create_account(&self, starting_balance: f64) -> Result<AccountId, String> {
+        match self.execute(ClientCommands::CreateAccount {
+            starting_balance,
+            confirmations: 1,
+        })? {
+            ServerResponse::AccountCreated { account, .. } => Ok(account),
+            response => Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+
+    /// Transfers native funds and returns the transaction id once it is sealed.
+    pub fn transfer(
+        &self,
+        from: AccountId,
+        to: AccountId,
+        amount: f64,
+    ) -> Result<TransactionId, String> {
+        match self.execute(ClientCommands::Transfer {
+            from_account: AccountRef::Id(from),
+            to_account: AccountRef::Id(to),
+            amount,
+            asset: NATIVE_ASSET,
+            unlock_height: None,
+            unlock_time: None,
+            confirmations: 1,
+        })? {
+            ServerResponse::Transferred { transaction_id, .. } => Ok(transaction_id),
+            response => Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+
+    /// Native balance of an account over the sealed blocks; pending
+    /// transactions are not counted until they are sealed.
+    pub fn balance(&self, account: AccountId) -> Result<f64, String> {
+        match self.execute(ClientCommands::Balance {
+            account: AccountRef::Id(account),
+            asset: NATIVE_ASSET,
+        })? {
+            ServerResponse::Balance { balance, .. } => Ok(balance),
+            response => Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+
+    /// Seals the pending transactions into a block, mined when the consensus is
+    /// proof of work, and returns it.
+    pub fn seal_block(&self) -> Result<Block, String> {
+        seal_block(&self.state, &self.options)
+    }
+
+    /// Events from now on, only those of `account` if given, as `b watch`
+    /// prints them. Events queue up until they are received; a subscriber
+    /// that falls 1024 events behind is dropped.
+    pub fn subscribe(&self, account: Option<AccountId>) -> Receiver<NodeEvent> {
+        self.state.subscribe(account)
+    }
+
+    /// Head block of the canonical chain.
+    pub fn head(&self) -> Block {
+        self.state.blocks().head().clone()
+    }
+}
+
+/// Session of a call on an embedded node: there is no block processor to
+/// wait for, so waiting for confirmations seals a block.
+struct EmbeddedSession<'a> {
+    options: &'a NodeOptions,
+}
+
+impl Session for EmbeddedSession<'_> {
+    fn notify_reorg(&mut self, notice: &ReorgNotice) {
+        debug!("embedded node reorganized: {:?}", notice);
+    }
+
+    fn wait_for_chain(&mut self, state: &NodeState, _seen: &ChainEvent) -> ChainEvent {
+        if let Err(e) = seal_block(state, self.options) {
+            debug!("{}", e);
+        }
+        state.chain_event()
+    }
+}
+
+#[cfg(test)]
+impl Node {
+    /// Opens a node on simulated time from `EPOCH`, with a fixed seed.
+    pub(crate) fn for_tests(options: NodeOptions) -> Node {
+        Node::with_env(
+            10,
+            options,
+            Arc::new(ManualClock::new(EPOCH)),
+            Arc::new(SeededRandom::new(1)),
+        )
+        .unwrap()
+    }
+
+    /// State of the node, for tests of the HTTP and JSON-RPC front ends.
+    pub(crate) fn into_state(self) -> NodeState {
+        self.state
+    }
+
+    /// State of the node, for front end tests that keep sealing blocks.
+    pub(crate) fn state(&self) -> &NodeState {
+        &self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::AdminAuth;
+    use crate::core::accounts::AccountError;
+    use crate::core::admin::{admin_message, AdminAction};
+    use crate::core::constants::DEFAULT_FINALITY;
+    use crate::core::keys::{generate_secret_key, public_key, sign, SecretKey};
+    use crate::core::pow::mine;
+    use crate::core::types::{Transaction, TransactionKind};
+    use crate::core::utilities::asset_id;
+    use crate::server::{Consensus, RecipientPolicy, SUBSCRIBER_BACKLOG};
+    use std::sync::mpsc::TryRecvError;
+
+    fn create_node() -> Node {
+        Node::for_tests(NodeOptions::default())
+    }
+
+    #[test]
+    fn test_embedded_transfers() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        let head = node.head().id;
+
+        node.transfer(alice, bob, 40.0).unwrap();
+        assert_eq!(node.head().id, head + 1);
+        assert_eq!(node.balance(bob), Ok(40.0));
+        assert_eq!(node.balance(alice), Ok(60.0));
+        assert!(node.transfer(bob, alice, 1000.0).is_err());
+
+        assert_eq!(node.seal_block().unwrap().id, head + 2);
+    }
+
+    #[test]
+    fn test_unknown_recipients_are_refused() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let refused = Some(AccountError::RecipientNotFound(4242).to_string());
+
+        assert_eq!(node.transfer(alice, 4242, 1.0).err(), refused);
+        assert_eq!(
+            node.execute(ClientCommands::OpenEscrow {
+                payer: AccountRef::Id(alice),
+                payee: AccountRef::Id(4242),
+                amount: 1.0,
+                asset: NATIVE_ASSET,
+                arbiter: None,
+                hashlock: Some([0; 32]),
+                timeout: 10,
+                confirmations: 1,
+            })
+            .err(),
+            refused
+        );
+        assert_eq!(node.balance(alice), Ok(100.0));
+    }
+
+    #[test]
+    fn test_accounts_holding_only_other_assets_can_send() {
+        let node = Node::for_tests(NodeOptions {
+            recipient_policy: RecipientPolicy::Implicit,
+            ..NodeOptions::default()
+        });
+        let alice = node.create_account(100.0).unwrap();
+        let gold = asset_id("GOLD").unwrap();
+        let transfer = |from, to, amount| {
+            node.execute(ClientCommands::Transfer {
+                from_account: AccountRef::Id(from),
+                to_account: AccountRef::Id(to),
+                amount,
+                asset: gold,
+                unlock_height: None,
+                unlock_time: None,
+                confirmations: 1,
+            })
+        };
+        node.execute(ClientCommands::IssueAsset {
+            asset: gold,
+            supply: 10.0,
+            issuer: AccountRef::Id(alice),
+            confirmations: 1,
+        })
+        .unwrap();
+
+        transfer(alice, 4242, 10.0).unwrap();
+        transfer(4242, alice, 4.0).unwrap();
+        assert!(matches!(
+            transfer(4343, alice, 1.0),
+            Err(e) if e.contains("not found")
+        ));
+    }
+
+    #[test]
+    fn test_identical_transfers_get_distinct_ids() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+
+        let first = node.transfer(alice, bob, 1.0).unwrap();
+        let second = node.transfer(alice, bob, 1.0).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(node.balance(bob), Ok(2.0));
+    }
+
+    /// Signs an admin action at `EPOCH`, the time of a node's clock in tests.
+    fn admin_auth(key: &SecretKey, action: AdminAction, account: AccountId) -> AdminAuth {
+        let timestamp = EPOCH.as_millis() as u64;
+        AdminAuth {
+            timestamp,
+            signature: sign(key, &admin_message(action, account, timestamp)),
+            ..AdminAuth::default()
+        }
+    }
+
+    #[test]
+    fn test_admin_signatures_cannot_be_replayed() {
+        let key = generate_secret_key();
+        let node = Node::for_tests(NodeOptions {
+            admin_key: Some(public_key(&key)),
+            ..NodeOptions::default()
+        });
+        let alice = node.create_account(100.0).unwrap();
+        let freeze = || ClientCommands::FreezeAccount {
+            account: AccountRef::Id(alice),
+            auth: admin_auth(&key, AdminAction::Freeze, alice),
+            confirmations: 1,
+        };
+
+        node.execute(freeze()).unwrap();
+        node.execute(ClientCommands::UnfreezeAccount {
+            account: AccountRef::Id(alice),
+            auth: admin_auth(&key, AdminAction::Unfreeze, alice),
+            confirmations: 1,
+        })
+        .unwrap();
+        assert!(node.execute(freeze()).unwrap_err().contains("already used"));
+    }
+
+    #[test]
+    fn test_frozen_accounts_cannot_issue_assets() {
+        let key = generate_secret_key();
+        let node = Node::for_tests(NodeOptions {
+            admin_key: Some(public_key(&key)),
+            ..NodeOptions::default()
+        });
+        let alice = node.create_account(100.0).unwrap();
+        node.execute(ClientCommands::FreezeAccount {
+            account: AccountRef::Id(alice),
+            auth: admin_auth(&key, AdminAction::Freeze, alice),
+            confirmations: 1,
+        })
+        .unwrap();
+
+        let issued = node.execute(ClientCommands::IssueAsset {
+            asset: asset_id("GOLD").unwrap(),
+            supply: 10.0,
+            issuer: AccountRef::Id(alice),
+            confirmations: 1,
+        });
+        assert!(issued.unwrap_err().contains("is frozen"));
+    }
+
+    #[test]
+    fn test_peer_blocks_with_forged_closes_are_rejected() {
+        let key = generate_secret_key();
+        let node = Node::for_tests(NodeOptions {
+            admin_key: Some(public_key(&key)),
+            ..NodeOptions::default()
+        });
+        let genesis = match node.execute(ClientCommands::ChainInfo).unwrap() {
+            ServerResponse::ChainInfo { genesis_hash, .. } => genesis_hash,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        let alice = node.create_account(100.0).unwrap();
+        let mallory = node.create_account(0.0).unwrap();
+        let head = node.head();
+        let timestamp = EPOCH.as_millis() as u64;
+        let action = AdminAction::Close { sweep_to: mallory };
+        let forged = sign(
+            &generate_secret_key(),
+            &admin_message(action, alice, timestamp),
+        );
+        let close = Transaction::new(mallory, alice, 100.0).with_kind(TransactionKind::Admin {
+            action,
+            timestamp,
+            signature: forged,
+        });
+        let mut block = Block::new(head.id + 1, head.hash(), vec![close]);
+        block.timestamp = timestamp;
+
+        let rejected = node.execute(ClientCommands::SubmitBlock {
+            block,
+            origin: 0,
+            genesis,
+        });
+        assert!(rejected.unwrap_err().contains("Invalid admin signature"));
+        assert_eq!(node.head().id, head.id);
+        assert_eq!(node.balance(alice), Ok(100.0));
+    }
+
+    #[test]
+    fn test_close_sweeps_are_sealed_together() {
+        let genesis =
+            std::env::temp_dir().join(format!("b-close-sweeps-{}.toml", std::process::id()));
+        std::fs::write(
+            &genesis,
+            "chain_id = \"sweeps\"\n[parameters]\nmax_block_transactions = 1\n",
+        )
+        .unwrap();
+        let key = generate_secret_key();
+        let node = Node::for_tests(NodeOptions {
+            genesis: Some(genesis.clone()),
+            admin_key: Some(public_key(&key)),
+            ..NodeOptions::default()
+        });
+        std::fs::remove_file(&genesis).unwrap();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        node.execute(ClientCommands::IssueAsset {
+            asset: asset_id("GOLD").unwrap(),
+            supply: 10.0,
+            issuer: AccountRef::Id(alice),
+            confirmations: 1,
+        })
+        .unwrap();
+
+        node.execute(ClientCommands::CloseAccount {
+            account: AccountRef::Id(alice),
+            sweep_to: AccountRef::Id(bob),
+            auth: admin_auth(&key, AdminAction::Close { sweep_to: bob }, alice),
+            confirmations: 1,
+        })
+        .unwrap();
+        let sweeps = node.head().transactions;
+        assert_eq!(sweeps.len(), 2);
+        assert!(sweeps[1].is_bundled_with(&sweeps[0]));
+        assert_eq!(node.balance(bob), Ok(100.0));
+    }
+
+    #[test]
+    fn test_close_account_after_prune() {
+        let data_dir = std::env::temp_dir().join(format!("b-close-pruned-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let key = generate_secret_key();
+        let node = Node::for_tests(NodeOptions {
+            finality: 1,
+            data_dir: Some(data_dir.clone()),
+            snapshot_interval: 1,
+            prune_keep: Some(1),
+            admin_key: Some(public_key(&key)),
+            ..NodeOptions::default()
+        });
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        for _ in 0..3 {
+            node.seal_block().unwrap();
+        }
+        assert!(node.state.blocks().pruned_through().is_some());
+
+        let closed = node.execute(ClientCommands::CloseAccount {
+            account: AccountRef::Id(alice),
+            sweep_to: AccountRef::Id(bob),
+            auth: admin_auth(&key, AdminAction::Close { sweep_to: bob }, alice),
+            confirmations: 1,
+        });
+        std::fs::remove_dir_all(&data_dir).unwrap();
+
+        assert!(closed.is_ok(), "{:?}", closed);
+        assert_eq!(node.balance(bob), Ok(100.0));
+    }
+
+    #[test]
+    fn test_finality_is_reported() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        let transaction_id = node.transfer(alice, bob, 1.0).unwrap();
+        let is_final = || match node.execute(ClientCommands::TransactionStatus { transaction_id }) {
+            Ok(ServerResponse::TransactionStatus { is_final, .. }) => is_final,
+            response => panic!("unexpected response: {:?}", response),
+        };
+
+        assert!(!is_final());
+        for _ in 1..DEFAULT_FINALITY {
+            node.seal_block().unwrap();
+        }
+        assert!(is_final());
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        let everything = node.subscribe(None);
+        let bobs = node.subscribe(Some(bob));
+
+        node.create_account(5.0).unwrap();
+        node.transfer(alice, bob, 40.0).unwrap();
+
+        let events: Vec<_> = everything.try_iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                NodeEvent::Pending(_),
+                NodeEvent::Block(_),
+                NodeEvent::Pending(transfer),
+                NodeEvent::Block(block),
+            ] if transfer.to() == bob && block.transactions.iter().any(|t| t.id == transfer.id)
+        ));
+        let events: Vec<_> = bobs.try_iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [NodeEvent::Pending(_), NodeEvent::Sealed { block_id, .. }] if *block_id == node.head().id
+        ));
+    }
+
+    #[test]
+    fn test_escrow_payees_see_their_escrows() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        let bobs = node.subscribe(Some(bob));
+
+        node.execute(ClientCommands::OpenEscrow {
+            payer: AccountRef::Id(alice),
+            payee: AccountRef::Id(bob),
+            amount: 10.0,
+            asset: NATIVE_ASSET,
+            arbiter: None,
+            hashlock: Some([0; 32]),
+            timeout: 10,
+            confirmations: 1,
+        })
+        .unwrap();
+        let events: Vec<_> = bobs.try_iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [NodeEvent::Pending(open), NodeEvent::Sealed { transaction, .. }]
+                if transaction.id == open.id
+        ));
+    }
+
+    #[test]
+    fn test_orphaned_transactions_are_pending_again() {
+        let node = create_node();
+        let peer = create_node();
+        let genesis = match node.execute(ClientCommands::ChainInfo).unwrap() {
+            ServerResponse::ChainInfo { genesis_hash, .. } => genesis_hash,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        let alice = node.create_account(100.0).unwrap();
+        let fork: Vec<_> = (0..2).map(|_| peer.seal_block().unwrap()).collect();
+        let events = node.subscribe(None);
+
+        for block in fork {
+            node.execute(ClientCommands::SubmitBlock {
+                block,
+                origin: 0,
+                genesis,
+            })
+            .unwrap();
+        }
+        let events: Vec<_> = events.try_iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                NodeEvent::Reorganized { .. },
+                NodeEvent::Block(_),
+                NodeEvent::Block(_),
+                NodeEvent::Pending(mint),
+            ] if mint.to() == alice
+        ));
+    }
+
+    #[test]
+    fn test_peer_blocks_carry_the_retargeted_difficulty() {
+        let options = || NodeOptions {
+            consensus: Consensus::Pow,
+            difficulty: 4,
+            ..NodeOptions::default()
+        };
+        let node = Node::for_tests(options());
+        let genesis = match node.execute(ClientCommands::ChainInfo).unwrap() {
+            ServerResponse::ChainInfo { genesis_hash, .. } => genesis_hash,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        let submit = |block: Block| {
+            node.execute(ClientCommands::SubmitBlock {
+                block,
+                origin: 0,
+                genesis,
+            })
+        };
+        let head = node.head();
+        let unmined = |difficulty| {
+            let mut block = Block::new(head.id + 1, head.hash(), Vec::new());
+            block.timestamp = EPOCH.as_millis() as u64;
+            block.difficulty = difficulty;
+            block
+        };
+
+        assert!(submit(unmined(0)).unwrap_err().contains("difficulty"));
+        let mut harder = unmined(5);
+        mine(&mut harder);
+        assert!(submit(harder).unwrap_err().contains("difficulty"));
+        let mut ahead = unmined(4);
+        ahead.timestamp += 3_600_000;
+        mine(&mut ahead);
+        assert!(submit(ahead).unwrap_err().contains("ahead"));
+        assert_eq!(node.head().id, head.id);
+
+        let peer = Node::for_tests(options());
+        assert!(submit(peer.seal_block().unwrap()).is_ok());
+        assert_eq!(node.head().id, head.id + 1);
+    }
+
+    #[test]
+    fn test_lagging_subscribers_are_dropped() {
+        let node = create_node();
+        let alice = node.create_account(100.0).unwrap();
+        let bob = node.create_account(0.0).unwrap();
+        let lagging = node.subscribe(None);
+
+        for _ in 0..=SUBSCRIBER_BACKLOG {
+            node.execute(ClientCommands::Transfer {
+                from_account: AccountRef::Id(alice),
+                to_account: AccountRef::Id(bob),
+                amount: 0.01,
+                asset: NATIVE_ASSET,
+                unlock_height: None,
+                unlock_time: None,
+                confirmations: 0,
+            })
+            .unwrap();
+        }
+        assert_eq!(lagging.try_iter().count(), SUBSCRIBER_BACKLOG);
+        assert!(matches!(
+            lagging.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn test_seeded_nodes_repeat() {
+        let ids = || {
+            let node = create_node();
+            (0..3)
+                .map(|_| node.create_account(1.0).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(), ids());
+    }
+}
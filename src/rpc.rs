@@ -0,0 +1,374 @@
+use crate::client::ClientCommands;
+use crate::core::constants::NATIVE_SYMBOL;
+use crate::core::names::AccountRef;
+use crate::core::types::{AssetId, BlockId, TransactionId};
+use crate::core::utilities::asset_id;
+use crate::http::{response_body, HttpSession};
+use crate::server::{handle_command, NodeState};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Start of the range reserved for implementation-defined server errors;
+/// used when the node refuses a command.
+const SERVER_ERROR: i64 = -32000;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parameters of a call, given by position or by name.
+enum Params {
+    ByPosition(Vec<Value>),
+    ByName(Map<String, Value>),
+}
+
+impl Params {
+    fn parse(params: Option<Value>) -> Result<Params, RpcError> {
+        match params {
+            None => Ok(Params::ByPosition(Vec::new())),
+            Some(Value::Array(values)) => Ok(Params::ByPosition(values)),
+            Some(Value::Object(values)) => Ok(Params::ByName(values)),
+            Some(_) => Err(RpcError::new(
+                INVALID_PARAMS,
+                "params must be an array or an object",
+            )),
+        }
+    }
+
+    fn value(&self, position: usize, name: &str) -> Option<&Value> {
+        match self {
+            Params::ByPosition(values) => values.get(position),
+            Params::ByName(values) => values.get(name),
+        }
+        .filter(|value| !value.is_null())
+    }
+
+    fn optional<T: DeserializeOwned>(
+        &self,
+        position: usize,
+        name: &str,
+    ) -> Result<Option<T>, RpcError> {
+        self.value(position, name)
+            .map(|value| {
+                serde_json::from_value(value.clone())
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid {}: {}", name, e)))
+            })
+            .transpose()
+    }
+
+    fn required<T: DeserializeOwned>(&self, position: usize, name: &str) -> Result<T, RpcError> {
+        self.optional(position, name)?
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing {}", name)))
+    }
+
+    /// A transaction id given as a number, or as a decimal string for
+    /// clients whose numbers cannot hold 64-bit integers.
+    fn transaction_id(&self, position: usize) -> Result<TransactionId, RpcError> {
+        match self.value(position, "transaction_id") {
+            Some(Value::String(id)) => id.parse().map_err(|_| {
+                RpcError::new(INVALID_PARAMS, format!("invalid transaction_id: {}", id))
+            }),
+            _ => self.required(position, "transaction_id"),
+        }
+    }
+
+    /// An account given as an address or a name. Numbers are refused like
+    /// plain ids on the command line, as they carry no checksum.
+    fn account(&self, position: usize, name: &str) -> Result<AccountRef, RpcError> {
+        match self.value(position, name) {
+            Some(Value::Number(id)) => Err(RpcError::new(
+                INVALID_PARAMS,
+                format!(
+                    "invalid {}: {} is a plain account id; give its address or name",
+                    name, id
+                ),
+            )),
+            Some(Value::String(account)) => AccountRef::from_str(account)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid {}: {}", name, e))),
+            Some(_) => Err(RpcError::new(INVALID_PARAMS, format!("invalid {}", name))),
+            None => Err(RpcError::new(INVALID_PARAMS, format!("missing {}", name))),
+        }
+    }
+
+    fn asset(&self, position: usize) -> Result<AssetId, RpcError> {
+        let symbol: Option<String> = self.optional(position, "asset")?;
+        asset_id(symbol.as_deref().unwrap_or(NATIVE_SYMBOL))
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e))
+    }
+}
+
+/// Answers a JSON-RPC 2.0 request or batch; `None` when nothing is to be sent
+/// back because it held only notifications.
+pub(crate) fn handle_rpc(state: &NodeState, body: &str) -> Option<Value> {
+    let request: Value = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    match request {
+        Value::Array(batch) if batch.is_empty() => Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Value::Array(batch) => {
+            let responses: Vec<Value> = batch
+                .into_iter()
+                .filter_map(|request| call(state, request))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => call(state, request),
+    }
+}
+
+/// Answer to one request; `None` for a notification, which has no id.
+fn call(state: &NodeState, request: Value) -> Option<Value> {
+    let mut request = match request {
+        Value::Object(request) => request,
+        _ => {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(INVALID_REQUEST, "a request must be an object"),
+            ))
+        }
+    };
+    let id = request.remove("id");
+    let valid_id = matches!(
+        id,
+        None | Some(Value::Null | Value::Number(_) | Value::String(_))
+    );
+    let method = match request.remove("method") {
+        Some(Value::String(method))
+            if valid_id && request.get("jsonrpc") == Some(&json!("2.0")) =>
+        {
+            method
+        }
+        _ => {
+            return Some(error_response(
+                id.filter(|_| valid_id).unwrap_or(Value::Null),
+                RpcError::new(
+                    INVALID_REQUEST,
+                    "expected jsonrpc \"2.0\", a method name and an optional id",
+                ),
+            ))
+        }
+    };
+
+    let result = Params::parse(request.remove("params"))
+        .and_then(|params| dispatch(state, &method, &params));
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_response(id, e),
+    })
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+fn dispatch(state: &NodeState, method: &str, params: &Params) -> Result<Value, RpcError> {
+    let command = match method {
+        "b_createAccount" => ClientCommands::CreateAccount {
+            starting_balance: params.required(0, "starting_balance")?,
+            confirmations: params.optional(1, "confirmations")?.unwrap_or(1),
+        },
+        "b_transfer" => ClientCommands::Transfer {
+            from_account: params.account(0, "from")?,
+            to_account: params.account(1, "to")?,
+            amount: params.required(2, "amount")?,
+            asset: params.asset(3)?,
+            unlock_height: None,
+            unlock_time: None,
+            confirmations: params.optional(4, "confirmations")?.unwrap_or(1),
+        },
+        "b_getBalance" => ClientCommands::Balance {
+            account: params.account(0, "account")?,
+            asset: params.asset(1)?,
+        },
+        "b_getAccount" => ClientCommands::AccountInfo {
+            account: params.account(0, "account")?,
+        },
+        "b_getTransaction" => ClientCommands::GetTx {
+            transaction_id: params.transaction_id(0)?,
+        },
+        "b_chainInfo" => ClientCommands::ChainInfo,
+        "b_blockNumber" => return Ok(json!(state.blocks().head().id)),
+        "b_getBlock" => return get_block(state, params),
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no method {}", method),
+            ))
+        }
+    };
+    let mut session = HttpSession;
+    handle_command(state, command, &mut session)
+        .map(|response| response_body(&response))
+        .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))
+}
+
+/// The block with the given id, or the head block for `"latest"`; `null`
+/// when the chain has no such block.
+fn get_block(state: &NodeState, params: &Params) -> Result<Value, RpcError> {
+    let blocks = state.blocks();
+    let block_id = match params.value(0, "block_id") {
+        Some(Value::String(tag)) if tag == "latest" => blocks.head().id,
+        _ => params.required::<BlockId>(0, "block_id")?,
+    };
+    Ok(json!(blocks.chain().get(block_id as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::to_address;
+    use crate::server::NodeOptions;
+    use crate::Node;
+    use std::thread;
+    use std::time::Duration;
+
+    fn rpc(body: &str) -> Option<Value> {
+        let state = Node::for_tests(NodeOptions::default()).into_state();
+        handle_rpc(&state, body)
+    }
+
+    fn code(response: &Value) -> &Value {
+        &response["error"]["code"]
+    }
+
+    #[test]
+    fn test_calls() {
+        let block =
+            rpc(r#"{"jsonrpc": "2.0", "id": 1, "method": "b_getBlock", "params": [0]}"#).unwrap();
+        assert_eq!(block["id"], 1);
+        assert_eq!(block["result"]["id"], 0);
+
+        let latest = rpc(r#"{"jsonrpc": "2.0", "id": "a", "method": "b_getBlock", "params": {"block_id": "latest"}}"#).unwrap();
+        assert_eq!(latest["result"]["id"], 0);
+
+        let missing =
+            rpc(r#"{"jsonrpc": "2.0", "id": 2, "method": "b_getBlock", "params": [5]}"#).unwrap();
+        assert_eq!(missing["result"], Value::Null);
+
+        let refused = rpc(&format!(
+            r#"{{"jsonrpc": "2.0", "id": 3, "method": "b_getBalance", "params": ["{}"]}}"#,
+            to_address(12345)
+        ))
+        .unwrap();
+        assert_eq!(code(&refused), SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_created_accounts_can_be_used() {
+        let node = Node::for_tests(NodeOptions::default());
+        let state = node.state().clone();
+        let created = thread::spawn(move || {
+            handle_rpc(
+                &state,
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "b_createAccount", "params": [100]}"#,
+            )
+        });
+        // The account is answered once a block seals it
+        while !created.is_finished() {
+            node.seal_block().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let account = created.join().unwrap().unwrap()["result"]["account"].clone();
+        assert!(account.is_string());
+
+        let balance = handle_rpc(
+            node.state(),
+            &json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "b_getBalance",
+                "params": [account],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(balance["result"]["balance"], 100.0);
+    }
+
+    #[test]
+    fn test_standard_errors() {
+        assert_eq!(code(&rpc("{").unwrap()), PARSE_ERROR);
+        assert_eq!(code(&rpc("[]").unwrap()), INVALID_REQUEST);
+        assert_eq!(
+            code(&rpc(r#"{"id": 1, "method": "b_getBlock"}"#).unwrap()),
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            code(&rpc(r#"{"jsonrpc": "2.0", "id": 1, "method": "eth_call"}"#).unwrap()),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(&rpc(r#"{"jsonrpc": "2.0", "id": 1, "method": "b_getBlock"}"#).unwrap()),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(&rpc(r#"{"jsonrpc": "2.0", "id": 1, "method": "b_transfer", "params": ["!", "bob", 1]}"#).unwrap()),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(&rpc(r#"{"jsonrpc": "2.0", "id": 1, "method": "b_transfer", "params": {"from": "alice", "to": 12345, "amount": 1}}"#).unwrap()),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(
+                &rpc(r#"{"jsonrpc": "2.0", "id": 1, "method": "b_blockNumber", "params": 7}"#)
+                    .unwrap()
+            ),
+            INVALID_PARAMS
+        );
+    }
+
+    #[test]
+    fn test_batches_and_notifications() {
+        assert_eq!(
+            rpc(r#"{"jsonrpc": "2.0", "method": "b_blockNumber"}"#),
+            None
+        );
+        assert_eq!(
+            rpc(r#"[{"jsonrpc": "2.0", "method": "b_blockNumber"}]"#),
+            None
+        );
+
+        let batch = rpc(r#"[
+                {"jsonrpc": "2.0", "id": 1, "method": "b_blockNumber"},
+                {"jsonrpc": "2.0", "method": "b_blockNumber"},
+                {"jsonrpc": "2.0", "id": 2, "method": "b_nothing"},
+                7
+            ]"#)
+        .unwrap();
+        let responses = batch.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["result"], 0);
+        assert_eq!(code(&responses[1]), METHOD_NOT_FOUND);
+        assert_eq!(code(&responses[2]), INVALID_REQUEST);
+    }
+}
@@ -0,0 +1,361 @@
+use crate::client::{prepare_command, ClientCommands};
+use crate::core::address::to_address;
+use crate::core::constants::NATIVE_SYMBOL;
+use crate::core::names::AccountRef;
+use crate::core::utilities::asset_id;
+use crate::server::{Answer, ServerResponse};
+use crate::shell::{ShellLine, ShellState};
+use crate::Client;
+use clap::Parser;
+use log::{error, info};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often `wait-blocks` asks the node for its head.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A scenario file: steps run in order against one node.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    /// Seconds a `wait-blocks` step may take before it fails
+    #[serde(default = "default_wait_timeout")]
+    pub wait_timeout: u64,
+    /// Written as `- transfer: {...}` rather than with YAML tags
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub steps: Vec<Step>,
+}
+
+fn default_wait_timeout() -> u64 {
+    60
+}
+
+/// One step of a scenario. Accounts are addresses, names or `$variables`;
+/// `last_tx`, `last_block` and `last_account` are set as in `b shell`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Step {
+    /// Creates an account, kept in the variable `save` if given
+    CreateAccount {
+        balance: f64,
+        save: Option<String>,
+        #[serde(default = "default_confirmations")]
+        confirmations: u64,
+    },
+    Transfer {
+        from: String,
+        to: String,
+        amount: f64,
+        asset: Option<String>,
+        #[serde(default = "default_confirmations")]
+        confirmations: u64,
+    },
+    /// Waits until the chain has grown by this many blocks
+    WaitBlocks(u64),
+    ExpectBalance {
+        account: String,
+        balance: f64,
+        asset: Option<String>,
+    },
+    /// Runs a client command written as on the command line, which must succeed
+    Run(String),
+    /// Runs a client command that the node must refuse with a message
+    /// containing `contains`
+    ExpectError {
+        run: String,
+        #[serde(default)]
+        contains: String,
+    },
+}
+
+fn default_confirmations() -> u64 {
+    1
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::CreateAccount { balance, save, .. } => match save {
+                Some(name) => format!("create-account {} as ${}", balance, name),
+                None => format!("create-account {}", balance),
+            },
+            Step::Transfer {
+                from, to, amount, ..
+            } => format!("transfer {} {} {}", from, to, amount),
+            Step::WaitBlocks(blocks) => format!("wait-blocks {}", blocks),
+            Step::ExpectBalance {
+                account, balance, ..
+            } => format!("expect-balance {} {}", account, balance),
+            Step::Run(line) => line.clone(),
+            Step::ExpectError { run, .. } => format!("expect-error {}", run),
+        }
+    }
+}
+
+/// Outcome of a scenario that ran to its end.
+#[derive(Debug)]
+pub struct Report {
+    pub name: String,
+    pub passed: usize,
+    /// Steps that failed, with the reason
+    pub failures: Vec<String>,
+}
+
+/// Reads a scenario from a YAML file and runs it against the node on
+/// `port`. Failed steps are reported and the run goes on; losing the node
+/// ends it with an error.
+pub fn run_scenario(path: &Path, port: u16) -> Result<Report, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let mut scenario: Scenario = serde_yaml::from_str(&text)
+        .map_err(|e| format!("invalid scenario {}: {}", path.display(), e))?;
+    if scenario.name.is_empty() {
+        scenario.name = path.display().to_string();
+    }
+    let mut client = Client::connect(port)?;
+    run_steps(&scenario, POLL_INTERVAL, &mut |command| {
+        let command = match prepare_command(command, &mut client) {
+            Ok(command) => command,
+            Err(e) => return Ok(Err(e)),
+        };
+        client.exchange(&command, |_, _| {})
+    })
+}
+
+/// Runs the steps, sending commands through `execute` and asking for the
+/// head every `poll_interval` while waiting for blocks.
+fn run_steps(
+    scenario: &Scenario,
+    poll_interval: Duration,
+    execute: &mut dyn FnMut(ClientCommands) -> Answer,
+) -> Result<Report, String> {
+    let mut runner = Runner {
+        variables: ShellState::default(),
+        wait_timeout: Duration::from_secs(scenario.wait_timeout),
+        poll_interval,
+        execute,
+    };
+    let mut report = Report {
+        name: scenario.name.clone(),
+        passed: 0,
+        failures: Vec::new(),
+    };
+    for (index, step) in scenario.steps.iter().enumerate() {
+        let step_name = format!("step {} ({})", index + 1, step.describe());
+        match runner.run(step)? {
+            Ok(()) => {
+                info!("{}: ok", step_name);
+                report.passed += 1;
+            }
+            Err(e) => {
+                error!("{}: FAILED: {}", step_name, e);
+                report.failures.push(format!("{}: {}", step_name, e));
+            }
+        }
+    }
+    Ok(report)
+}
+
+struct Runner<'a> {
+    variables: ShellState,
+    wait_timeout: Duration,
+    poll_interval: Duration,
+    execute: &'a mut dyn FnMut(ClientCommands) -> Answer,
+}
+
+impl Runner<'_> {
+    /// Runs one step: the outer error means the node was lost, the inner
+    /// one that the step failed.
+    fn run(&mut self, step: &Step) -> Result<Result<(), String>, String> {
+        // A command that cannot be built fails the step like an error from
+        // the node, so `expect-error` can match it
+        let answer = match self.command(step) {
+            Ok(command) => (self.execute)(command)?,
+            Err(e) => Err(e),
+        };
+        if let Ok(response) = &answer {
+            self.variables.remember(response);
+        }
+        Ok(match (step, answer) {
+            (Step::ExpectError { contains, .. }, Ok(response)) => Err(format!(
+                "expected an error containing {:?}, got {:?}",
+                contains, response
+            )),
+            (Step::ExpectError { contains, .. }, Err(e)) if !e.contains(contains.as_str()) => Err(
+                format!("expected an error containing {:?}, got {:?}", contains, e),
+            ),
+            (Step::ExpectError { .. }, Err(_)) => Ok(()),
+            (_, Err(e)) => Err(e),
+            (
+                Step::CreateAccount { save, .. },
+                Ok(ServerResponse::AccountCreated { account, .. }),
+            ) => {
+                if let Some(name) = save {
+                    self.variables.set(name, to_address(account));
+                }
+                Ok(())
+            }
+            (Step::WaitBlocks(blocks), Ok(ServerResponse::ChainInfo { head_block_id, .. })) => {
+                self.wait_for_head(head_block_id + blocks)
+            }
+            (
+                Step::ExpectBalance {
+                    account, balance, ..
+                },
+                Ok(ServerResponse::Balance {
+                    balance: actual, ..
+                }),
+            ) => match (actual - balance).abs() <= 1e-9 * balance.abs().max(1.0) {
+                true => Ok(()),
+                false => Err(format!(
+                    "balance of {} is {}, expected {}",
+                    account, actual, balance
+                )),
+            },
+            (Step::Transfer { .. }, Ok(ServerResponse::Transferred { .. }))
+            | (Step::Run(_), Ok(_)) => Ok(()),
+            (_, Ok(response)) => Err(format!("unexpected response: {:?}", response)),
+        })
+    }
+
+    /// Command the step sends first.
+    fn command(&self, step: &Step) -> Result<ClientCommands, String> {
+        Ok(match step {
+            Step::CreateAccount {
+                balance,
+                confirmations,
+                ..
+            } => ClientCommands::CreateAccount {
+                starting_balance: *balance,
+                confirmations: *confirmations,
+            },
+            Step::Transfer {
+                from,
+                to,
+                amount,
+                asset,
+                confirmations,
+            } => ClientCommands::Transfer {
+                from_account: self.account(from)?,
+                to_account: self.account(to)?,
+                amount: *amount,
+                asset: asset_id(asset.as_deref().unwrap_or(NATIVE_SYMBOL))?,
+                unlock_height: None,
+                unlock_time: None,
+                confirmations: *confirmations,
+            },
+            Step::WaitBlocks(_) => ClientCommands::ChainInfo,
+            Step::ExpectBalance { account, asset, .. } => ClientCommands::Balance {
+                account: self.account(account)?,
+                asset: asset_id(asset.as_deref().unwrap_or(NATIVE_SYMBOL))?,
+            },
+            Step::Run(line) | Step::ExpectError { run: line, .. } => {
+                let words = self.variables.expand(line)?;
+                match ShellLine::try_parse_from(&words) {
+                    Ok(ShellLine {
+                        command: ClientCommands::Subscribe { .. },
+                    }) => return Err("watch cannot be run from a scenario".to_string()),
+                    Ok(line) => line.command,
+                    Err(e) => return Err(e.to_string().trim_end().to_string()),
+                }
+            }
+        })
+    }
+
+    fn account(&self, account: &str) -> Result<AccountRef, String> {
+        AccountRef::from_str(&self.variables.substitute(account)?)
+    }
+
+    fn wait_for_head(&mut self, target: u64) -> Result<(), String> {
+        let deadline = Instant::now() + self.wait_timeout;
+        loop {
+            match (self.execute)(ClientCommands::ChainInfo) {
+                Ok(Ok(ServerResponse::ChainInfo { head_block_id, .. })) => {
+                    if head_block_id >= target {
+                        return Ok(());
+                    }
+                }
+                Ok(Ok(response)) => return Err(format!("unexpected response: {:?}", response)),
+                Ok(Err(e)) | Err(e) => return Err(e),
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "the chain did not reach block {} within {:?}",
+                    target, self.wait_timeout
+                ));
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::NodeOptions;
+    use crate::Node;
+
+    fn run(yaml: &str) -> Report {
+        let node = Node::for_tests(NodeOptions::default());
+        let scenario: Scenario = serde_yaml::from_str(yaml).unwrap();
+        // Nothing seals blocks on an embedded node, so every look at the
+        // head seals one
+        run_steps(&scenario, Duration::ZERO, &mut |command| {
+            if matches!(command, ClientCommands::ChainInfo) {
+                node.seal_block()?;
+            }
+            Ok(node.execute(command))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_passing_scenario() {
+        let report = run(r#"
+name: payments
+steps:
+  - create-account: { balance: 100, save: alice }
+  - create-account: { balance: 0, save: bob }
+  - transfer: { from: $alice, to: $bob, amount: 25 }
+  - wait-blocks: 2
+  - expect-balance: { account: $alice, balance: 75 }
+  - expect-balance: { account: $bob, balance: 25 }
+  - run: get-tx $last_tx
+  - expect-error: { run: transfer $bob $alice 1000, contains: Not enough }
+  - expect-error: { run: transfer $alice $bob -5, contains: unexpected argument }
+  - expect-error: { run: balance $nobody, contains: unknown variable }
+"#);
+        assert_eq!(report.name, "payments");
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert_eq!(report.passed, 10);
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let report = run(r#"
+steps:
+  - create-account: { balance: 10, save: alice }
+  - expect-balance: { account: $alice, balance: 11 }
+  - expect-error: { run: balance $alice }
+  - transfer: { from: $nobody, to: $alice, amount: 1 }
+  - run: watch
+  - run: balance $alice
+"#);
+        assert_eq!(report.passed, 2);
+        assert_eq!(report.failures.len(), 4);
+        assert!(report.failures[0].starts_with("step 2 "));
+        assert!(report.failures[2].contains("unknown variable $nobody"));
+    }
+
+    #[test]
+    fn test_invalid_scenarios() {
+        assert!(serde_yaml::from_str::<Scenario>("steps: [{ mint: 5 }]").is_err());
+        assert!(serde_yaml::from_str::<Scenario>("steps: [{ transfer: { from: a } }]").is_err());
+    }
+}
@@ -1,15 +1,49 @@
-use crate::client::ClientCommands;
-use crate::core::constants::MASTER_ID;
+use crate::client::{AdminAuth, ClientCommands};
+use crate::core::accounts::AccountError;
+use crate::core::address::{generate_account_id, parse_account_id, to_address};
+use crate::core::admin::{
+    account_status, held_assets, is_signature_used, verify_admin, AccountStatus, AdminAction,
+};
+use crate::core::block_tree::{BlockTree, InsertOutcome, Reorg};
+use crate::core::constants::{
+    BURN_ID, CHAIN_FILE, DEFAULT_DIFFICULTY, DEFAULT_FINALITY, DEFAULT_REWARD,
+    DEFAULT_SNAPSHOT_INTERVAL, ESCROW_ID, MASTER_ID, MAX_BLOCK_DRIFT, MAX_DIFFICULTY, NATIVE_ASSET,
+    SNAPSHOT_FILE,
+};
+use crate::core::contracts::{contract_storage, execute_calls, find_contract};
+use crate::core::dump::{import_chain, read_blocks, write_blocks, DumpFormat};
+use crate::core::env::{Clock, Random, SystemClock, SystemRandom};
+use crate::core::escrow::{find_escrow, Escrow, EscrowState};
+use crate::core::genesis::{ChainParameters, Genesis};
+use crate::core::keys::{parse_key, PublicKey};
+use crate::core::monetary::{check_supply_invariant, FaucetLimiter, Supply};
+use crate::core::multisig::{ApprovalOutcome, Multisig, MultisigPolicy};
+use crate::core::names::{name_registry, validate_name, AccountRef};
+use crate::core::pow::mine;
+use crate::core::rules::{check_block, BlockCheck, ChainRules};
+use crate::core::schedule::{Schedule, Scheduler};
+use crate::core::snapshot::Snapshot;
 use crate::core::traits::{BlockInfo, TransactionInfo};
 use crate::core::types::{
-    AccountId, Block, BlockId, Blocks, Transaction, TransactionId, Transactions,
+    AccountId, AssetId, Block, BlockId, Blocks, EscrowTerms, Hash, Id, Receipt, TimeLock,
+    Transaction, TransactionId, TransactionKind, Transactions,
 };
-use bincode::{deserialize, serialize};
-use log::{error, info};
+use crate::core::utilities::{asset_symbol, to_hex};
+use crate::core::validation::{validate_amount, validate_balance, validate_transaction};
+use crate::core::vm::{self, Storage, MAX_GAS_LIMIT};
+use crate::http::start_http;
+use bincode::{deserialize_from, serialize_into, ErrorKind};
+use clap::{Args, Parser, ValueEnum};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
-use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard};
 use std::thread;
 use std::time::Duration;
 
@@ -18,70 +52,514 @@ pub enum ServerResponse {
     Transferred {
         block_id: BlockId,
         transaction_id: TransactionId,
+        confirmations: u64,
+        finality: u64,
+        /// Whether the block can no longer be reorganized away
+        is_final: bool,
+    },
+    TransactionStatus {
+        transaction_id: TransactionId,
+        /// `None` while the transaction is waiting in the mempool
+        block_id: Option<BlockId>,
+        confirmations: u64,
+        finality: u64,
+        is_final: bool,
     },
     Balance {
         balance: f64,
+        /// Part of the balance that is still time-locked
+        locked: f64,
+        status: AccountStatus,
+    },
+    AccountCreated {
+        account: AccountId,
+        block_id: BlockId,
+        transaction_id: TransactionId,
+        confirmations: u64,
+        finality: u64,
+        is_final: bool,
+    },
+    Resolved {
+        account: AccountId,
+        names: Vec<String>,
+    },
+    AccountInfo {
+        account: AccountId,
+        exists: bool,
+        /// First block the account appears in, `None` while it only exists in the mempool
+        created_block_id: Option<BlockId>,
+        /// The account may first appear in blocks whose transactions were pruned
+        created_pruned: bool,
+        pending_transactions: usize,
+        status: AccountStatus,
+        names: Vec<String>,
+        multisig_threshold: Option<usize>,
+    },
+    AccountUpdated {
+        account: AccountId,
+        status: AccountStatus,
+        block_id: BlockId,
+        transaction_id: TransactionId,
+    },
+    History {
+        account: AccountId,
+        /// Transactions to or from the account with the id of their block, oldest first
+        transactions: Vec<(BlockId, Transaction)>,
+    },
+    Transaction {
+        /// `None` while the transaction is waiting in the mempool
+        block_id: Option<BlockId>,
+        transaction: Transaction,
+    },
+    Scheduled {
+        schedule_id: Id,
+        /// Block the first transfer is submitted for
+        first_block_id: BlockId,
+    },
+    ScheduleCancelled {
+        schedule_id: Id,
+    },
+    MultisigCreated {
+        account: AccountId,
+        threshold: usize,
+    },
+    Proposed {
+        proposal_id: Id,
+        /// Last block id at which the proposal can be approved
+        expires_at: BlockId,
+    },
+    /// A proposal was approved but still needs more approvals
+    Approval {
+        proposal_id: Id,
+        approvals: usize,
+        threshold: usize,
+    },
+    /// Streamed to clients waiting on a transaction when the canonical chain
+    /// is reorganized; the final response follows later.
+    Reorganized {
+        orphaned_blocks: u64,
+        head_block_id: BlockId,
+    },
+    BlockReceived {
+        block_id: BlockId,
+    },
+    /// Streamed to subscribers, one per event, for as long as they stay connected
+    Event(NodeEvent),
+    Blocks {
+        blocks: Blocks,
+    },
+    /// Latest snapshot of a node, if any, and its canonical chain from block 1
+    Snapshot {
+        snapshot: Option<Snapshot>,
+        blocks: Blocks,
+    },
+    Supply {
+        supply: Supply,
+        supply_cap: Option<f64>,
+    },
+    Escrow {
+        escrow: Escrow,
+        head_block_id: BlockId,
+    },
+    ContractDeployed {
+        contract: AccountId,
+        block_id: BlockId,
+    },
+    ContractCalled {
+        block_id: BlockId,
+        transaction_id: TransactionId,
+        receipt: Receipt,
+    },
+    ContractStorage {
+        contract: AccountId,
+        storage: Storage,
+    },
+    ChainInfo {
+        chain_id: String,
+        genesis_hash: Hash,
+        head_block_id: BlockId,
+        parameters: ChainParameters,
     },
 }
 
-pub fn start_node(port: u16, interval: u64) -> std::io::Result<()> {
-    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
-    let listener = TcpListener::bind(address).unwrap_or_else(|e| {
-        error!("Error creating a TcpListener for port {} -- {}", port, e);
-        panic!("Program will exit due to error.");
-    });
+/// Why the node refused a command. Peers and TCP clients only get the
+/// message; the HTTP gateway picks its status code by the kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CommandError {
+    /// An account, name, escrow, contract or transaction the command names does not exist
+    NotFound(String),
+    /// Anything else that keeps the command from being carried out
+    Refused(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::NotFound(message) | CommandError::Refused(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Refused(message)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Refused(message.to_string())
+    }
+}
+
+impl From<AccountError> for CommandError {
+    fn from(error: AccountError) -> Self {
+        CommandError::NotFound(error.to_string())
+    }
+}
+
+/// Something that happened on a node, as streamed to subscribers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NodeEvent {
+    /// A block joined the canonical chain
+    Block(Block),
+    /// A transaction entered the mempool
+    Pending(Transaction),
+    /// A transaction of the watched account joined the canonical chain
+    Sealed {
+        block_id: BlockId,
+        transaction: Transaction,
+    },
+    /// The canonical chain was reorganized; the adopted blocks follow
+    Reorganized {
+        orphaned_blocks: u64,
+        head_block_id: BlockId,
+    },
+}
+
+/// Events a subscriber may fall behind by before it is dropped.
+pub(crate) const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// Receives the events of the whole node, or only those of one account.
+struct Subscriber {
+    account: Option<AccountId>,
+    sender: SyncSender<NodeEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Consensus {
+    /// Seal a block every interval
+    Interval,
+    /// Seal a block once a nonce meeting the difficulty target is found
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecipientPolicy {
+    /// Refuse transfers to accounts the node never created
+    Existing,
+    /// Let a transfer to an unknown id create the account
+    Implicit,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct NodeOptions {
+    /// Sets how blocks are sealed
+    #[clap(long, value_enum, default_value = "interval")]
+    pub consensus: Consensus,
+
+    /// Sets the initial proof-of-work difficulty (leading zero bits of the block hash)
+    #[clap(long, default_value_t = DEFAULT_DIFFICULTY, value_parser = clap::value_parser!(u32).range(0..=MAX_DIFFICULTY as i64))]
+    pub difficulty: u32,
+
+    /// Sets the account (address) credited with the coinbase reward of mined blocks
+    #[clap(long, value_parser = parse_account_id)]
+    pub miner: Option<AccountId>,
+
+    /// Sets the coinbase reward paid to the miner for each mined block
+    #[clap(long, default_value_t = DEFAULT_REWARD)]
+    pub reward: f64,
+
+    /// Sets the port of a peer node to exchange blocks with (repeatable)
+    #[clap(long = "peer")]
+    pub peers: Vec<u16>,
+
+    /// Sets the number of confirmations after which a block is final and can no longer be reorganized away (0 disables finality)
+    #[clap(long, default_value_t = DEFAULT_FINALITY)]
+    pub finality: u64,
+
+    /// Sets the genesis file defining the chain id, parameters and initial accounts
+    #[clap(long)]
+    pub genesis: Option<PathBuf>,
+
+    /// Sets the most the node mints with create-account per faucet window, for all clients together
+    #[clap(long)]
+    pub faucet_limit: Option<f64>,
+
+    /// Sets the faucet window (in seconds)
+    #[clap(long, default_value = "60")]
+    pub faucet_window: u64,
+
+    /// Sets whether transfers may go to accounts that do not exist yet
+    #[clap(long, value_enum, default_value = "existing")]
+    pub recipient_policy: RecipientPolicy,
+
+    /// Sets the node operator's public key (hex, from `b keygen`) that signs freeze, unfreeze and close commands
+    #[clap(long, value_parser = parse_key)]
+    pub admin_key: Option<PublicKey>,
+
+    /// Sets the directory the chain is kept in across restarts (see `b import`)
+    #[clap(long)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Sets the number of blocks between state snapshots written to the data directory (0 disables snapshots)
+    #[clap(long, default_value_t = DEFAULT_SNAPSHOT_INTERVAL)]
+    pub snapshot_interval: u64,
+
+    /// Discards the transactions of blocks more than N blocks below the head once a snapshot covers them
+    #[clap(long, requires = "data_dir", conflicts_with = "archive")]
+    pub prune_keep: Option<u64>,
+
+    /// Keeps every block whole and refuses a data directory holding pruned blocks
+    #[clap(long)]
+    pub archive: bool,
+
+    /// Sets the port of an HTTP gateway answering JSON requests (off if omitted)
+    #[clap(long)]
+    pub http_port: Option<u16>,
+}
+
+impl Default for NodeOptions {
+    /// The options of `start-node` when none are given on the command line.
+    fn default() -> NodeOptions {
+        #[derive(Parser)]
+        struct Defaults {
+            #[clap(flatten)]
+            options: NodeOptions,
+        }
+        Defaults::parse_from(["start-node"]).options
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ReorgNotice {
+    orphaned_blocks: u64,
+    head_block_id: BlockId,
+}
+
+/// Published through the condvar every time the canonical chain changes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChainEvent {
+    sequence: u64,
+    reorgs: u64,
+    last_reorg: Option<ReorgNotice>,
+}
+
+#[derive(Clone)]
+pub(crate) struct NodeState {
+    port: u16,
+    blocks: Arc<RwLock<BlockTree>>,
+    transactions: Arc<Mutex<Transactions>>,
+    chain_events: Arc<(Mutex<ChainEvent>, Condvar)>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    peers: Arc<Vec<u16>>,
+    finality: u64,
+    chain_id: Arc<String>,
+    genesis_hash: Hash,
+    parameters: Arc<ChainParameters>,
+    rules: ChainRules,
+    faucet: Arc<Mutex<FaucetLimiter>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    multisig: Arc<Mutex<Multisig>>,
+    recipient_policy: RecipientPolicy,
+    data_dir: Option<Arc<PathBuf>>,
+    snapshot_interval: u64,
+    prune_keep: Option<u64>,
+    clock: Arc<dyn Clock>,
+    random: Arc<dyn Random>,
+    network: Arc<dyn Network>,
+}
+
+impl NodeState {
+    /// State of a node serving `blocks` on `port`, before any command or block arrives.
+    pub(crate) fn new(
+        port: u16,
+        genesis: &Genesis,
+        blocks: BlockTree,
+        options: &NodeOptions,
+        clock: Arc<dyn Clock>,
+        random: Arc<dyn Random>,
+        network: Arc<dyn Network>,
+    ) -> NodeState {
+        NodeState {
+            port,
+            genesis_hash: blocks.chain()[0].hash(),
+            blocks: Arc::new(RwLock::new(blocks)),
+            transactions: Arc::new(Mutex::new(Vec::new())),
+            chain_events: Arc::new((Mutex::new(ChainEvent::default()), Condvar::new())),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            peers: Arc::new(options.peers.clone()),
+            finality: options.finality,
+            chain_id: Arc::new(genesis.chain_id.clone()),
+            parameters: Arc::new(genesis.parameters.clone()),
+            rules: chain_rules(genesis, options),
+            faucet: Arc::new(Mutex::new(FaucetLimiter::new(
+                options.faucet_limit,
+                Duration::from_secs(options.faucet_window),
+            ))),
+            scheduler: Arc::new(Mutex::new(Scheduler::default())),
+            multisig: Arc::new(Mutex::new(Multisig::default())),
+            recipient_policy: options.recipient_policy,
+            data_dir: options.data_dir.clone().map(Arc::new),
+            snapshot_interval: options.snapshot_interval,
+            prune_keep: options.prune_keep,
+            clock,
+            random,
+            network,
+        }
+    }
+
+    /// Rules the blocks of peers, imports and this node's own blocks follow.
+    pub(crate) fn rules(&self) -> ChainRules {
+        self.rules
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn blocks(&self) -> RwLockReadGuard<'_, BlockTree> {
+        self.blocks.read().unwrap()
+    }
+
+    pub(crate) fn chain_event(&self) -> ChainEvent {
+        self.chain_events.0.lock().unwrap().clone()
+    }
+
+    /// Events from now on, only those of `account` if given: its pending and
+    /// sealed transactions and reorganizations. A subscriber that falls
+    /// `SUBSCRIBER_BACKLOG` events behind is dropped, which ends its receiver.
+    pub(crate) fn subscribe(&self, account: Option<AccountId>) -> Receiver<NodeEvent> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber { account, sender });
+        receiver
+    }
+
+    /// Transaction whose id is stamped with the node's clock and a random
+    /// nonce.
+    fn transaction(
+        &self,
+        to: AccountId,
+        from: AccountId,
+        amount: f64,
+        asset: AssetId,
+    ) -> Transaction {
+        Transaction::at(to, from, amount, asset, self.clock.now())
+            .with_nonce(self.random.next_u64())
+    }
+}
+
+pub(crate) type Answer = Result<Result<ServerResponse, String>, String>;
+
+/// Transport between nodes; the simulation replaces it with in-process delivery.
+pub(crate) trait Network: Send + Sync {
+    /// Sends a command to a peer and waits for its answer.
+    fn request(&self, peer: u16, command: &ClientCommands) -> Answer;
 
-    let blocks: Arc<RwLock<Blocks>> = Arc::new(RwLock::new(Vec::new()));
-    let transaction_queue: Arc<Mutex<Transactions>> = Arc::new(Mutex::new(Vec::new()));
-    let condvar = Arc::new((Mutex::new(0), Condvar::new()));
+    /// Sends a command to a peer in the background and hands its answer to `on_answer`.
+    fn send(&self, peer: u16, command: ClientCommands, on_answer: Box<dyn FnOnce(Answer) + Send>);
+}
+
+struct TcpNetwork;
+
+impl Network for TcpNetwork {
+    fn request(&self, peer: u16, command: &ClientCommands) -> Answer {
+        let answer = || -> bincode::Result<Result<ServerResponse, String>> {
+            let mut stream = TcpStream::connect(format!("127.0.0.1:{}", peer))?;
+            serialize_into(&mut stream, command)?;
+            deserialize_from(&mut stream)
+        };
+        answer().map_err(|e| e.to_string())
+    }
 
-    let blocks_clone = Arc::clone(&blocks);
-    let transcation_queue_clone = Arc::clone(&transaction_queue);
-    let condvar_clone = Arc::clone(&condvar);
+    fn send(&self, peer: u16, command: ClientCommands, on_answer: Box<dyn FnOnce(Answer) + Send>) {
+        thread::spawn(move || on_answer(TcpNetwork.request(peer, &command)));
+    }
+}
 
+pub fn start_node(port: u16, interval: u64, options: NodeOptions) -> std::io::Result<()> {
+    let (genesis, state) = open_node(
+        port,
+        interval,
+        &options,
+        Arc::new(SystemClock),
+        Arc::new(SystemRandom),
+    )
+    .unwrap_or_else(|e| {
+        error!("{}", e);
+        panic!("Program will exit due to error.");
+    });
     info!(
-        "Starting block processor at time interval: {} seconds",
-        interval
+        "Chain {} with genesis block {}",
+        genesis.chain_id,
+        to_hex(&state.genesis_hash)
     );
-    let interval = Duration::from_secs(interval);
-    thread::spawn(move || {
-        let mut block_id: BlockId = 0;
-        loop {
-            thread::sleep(interval);
-            info!("Publishing block.");
-            {
-                let mut transactions = transcation_queue_clone.lock().unwrap();
-
-                let block = Block {
-                    id: block_id,
-                    transactions: transactions.clone(),
-                };
-                transactions.clear();
+    for account in &genesis.accounts {
+        info!(
+            "Genesis account {} has address {}",
+            account.id,
+            to_address(account.id)
+        );
+    }
 
-                let mut blocks = blocks_clone.write().unwrap();
-                blocks.push(block);
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let listener = TcpListener::bind(address).unwrap_or_else(|e| {
+        error!("Error creating a TcpListener for port {} -- {}", port, e);
+        panic!("Program will exit due to error.");
+    });
 
-                let (lock, cvar) = &*condvar_clone;
-                let mut notified = lock.lock().unwrap();
-                *notified = block_id;
+    if let Some(http_port) = options.http_port {
+        start_http(state.clone(), http_port).unwrap_or_else(|e| {
+            error!("{}", e);
+            panic!("Program will exit due to error.");
+        });
+    }
 
-                cvar.notify_all();
+    let processor_state = state.clone();
 
-                info!("Block published: {:?}", &blocks[block_id as usize]);
-            }
-            block_id += 1;
+    match options.consensus {
+        Consensus::Interval => info!(
+            "Starting block processor at time interval: {} seconds",
+            genesis.parameters.block_interval
+        ),
+        Consensus::Pow => info!(
+            "Starting proof-of-work block processor with target block time: {} seconds",
+            genesis.parameters.block_interval
+        ),
+    }
+    let interval = Duration::from_secs(genesis.parameters.block_interval);
+    thread::spawn(move || loop {
+        if options.consensus == Consensus::Interval {
+            processor_state.clock.sleep(interval);
+            info!("Publishing block.");
+        }
+        if let Err(e) = seal_block(&processor_state, &options) {
+            error!("{}", e);
         }
     });
 
     info!("b server listening on port {}", port);
     for stream in listener.incoming() {
-        let blocks_clone = Arc::clone(&blocks);
-        let transcation_queue_clone = Arc::clone(&transaction_queue);
-        let condvar_clone = Arc::clone(&condvar);
+        let client_state = state.clone();
         match stream {
             Ok(stream) => {
                 thread::spawn(|| {
-                    handle_client(stream, blocks_clone, transcation_queue_clone, condvar_clone);
+                    handle_client(stream, client_state);
                 });
             }
             Err(e) => {
@@ -93,187 +571,2304 @@ pub fn start_node(port: u16, interval: u64) -> std::io::Result<()> {
     Ok(())
 }
 
-fn handle_client(
-    mut stream: TcpStream,
-    shared_blocks: Arc<RwLock<Blocks>>,
-    shared_transactions: Arc<Mutex<Transactions>>,
-    shared_condvar: Arc<(Mutex<BlockId>, Condvar)>,
-) {
-    let mut buffer = [0; 512];
-    loop {
-        let bytes_read = stream
-            .read(&mut buffer)
-            .expect("Failed to read from socket");
+/// Genesis and state of a node on `port` that exchanges blocks with its peers
+/// over TCP, with the chain of its data directory loaded and synced from its
+/// peers.
+pub(crate) fn open_node(
+    port: u16,
+    interval: u64,
+    options: &NodeOptions,
+    clock: Arc<dyn Clock>,
+    random: Arc<dyn Random>,
+) -> Result<(Genesis, NodeState), String> {
+    if let Some(name) = options.miner.and_then(reserved_account_name) {
+        return Err(format!("Miner account id cannot be {}", name));
+    }
+    if options.prune_keep.is_some() && options.finality == 0 {
+        return Err(
+            "Pruning needs --finality so that pruned blocks cannot be reorganized away".to_string(),
+        );
+    }
 
-        if bytes_read == 0 {
-            return;
+    let genesis = match &options.genesis {
+        Some(path) => Genesis::load(path)?,
+        None => Genesis::local(interval),
+    };
+    let mut blocks = BlockTree::new(genesis.block(), options.finality);
+    if let Some(data_dir) = &options.data_dir {
+        load_chain(&mut blocks, data_dir, &chain_rules(&genesis, options))?;
+    }
+    if let (true, Some(pruned)) = (options.archive, blocks.pruned_through()) {
+        return Err(format!(
+            "Blocks up to {} were pruned; an archive node needs a data directory with the whole chain",
+            pruned
+        ));
+    }
+
+    let state = NodeState::new(
+        port,
+        &genesis,
+        blocks,
+        options,
+        clock,
+        random,
+        Arc::new(TcpNetwork),
+    );
+    store_chain(&state, state.blocks.read().unwrap().chain(), None);
+
+    // A node without blocks of its own starts from a peer's snapshot, unless it
+    // has to keep the whole chain
+    for peer in state.peers.iter() {
+        let is_empty = state.blocks.read().unwrap().head().id == 0;
+        let bootstrapped = is_empty && !options.archive && bootstrap_from_peer(&state, *peer);
+        if !bootstrapped {
+            sync_from_peer(&state, *peer);
+        }
+    }
+    Ok((genesis, state))
+}
+
+/// Rules of the chain starting at `genesis` for a node run with `options`.
+fn chain_rules(genesis: &Genesis, options: &NodeOptions) -> ChainRules {
+    let initial_difficulty = (options.consensus == Consensus::Pow).then_some(options.difficulty);
+    ChainRules::new(genesis, options.admin_key, initial_difficulty)
+}
+
+/// Replays the chain kept in `data_dir`, if any, on top of the genesis block.
+fn load_chain(blocks: &mut BlockTree, data_dir: &Path, rules: &ChainRules) -> Result<(), String> {
+    let path = data_dir.join(CHAIN_FILE);
+    if !path.exists() {
+        return fs::create_dir_all(data_dir)
+            .map_err(|e| format!("could not create {}: {}", data_dir.display(), e));
+    }
+
+    let read_chain = |snapshot: Option<&Snapshot>| {
+        let file =
+            File::open(&path).map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+        import_chain(
+            read_blocks(BufReader::new(file), DumpFormat::Jsonl),
+            snapshot,
+            rules,
+        )
+        .map_err(|e| format!("could not load {}: {}", path.display(), e))
+    };
+
+    // Blocks up to a snapshot that matches the chain are not replayed
+    let snapshot_path = data_dir.join(SNAPSHOT_FILE);
+    let (chain, snapshot) = match snapshot_path.exists() {
+        true => {
+            let loaded = Snapshot::load(&snapshot_path)
+                .and_then(|snapshot| read_chain(Some(&snapshot)).map(|chain| (chain, snapshot)));
+            match loaded {
+                Ok((chain, snapshot)) => (chain, Some(snapshot)),
+                Err(e) => {
+                    warn!("Ignoring snapshot {}: {}", snapshot_path.display(), e);
+                    (read_chain(None)?, None)
+                }
+            }
+        }
+        false => (read_chain(None)?, None),
+    };
+    if chain[0].hash() != blocks.head().hash() {
+        return Err(format!(
+            "{} holds a chain with genesis block {}, not the genesis block of this node",
+            path.display(),
+            to_hex(&chain[0].hash())
+        ));
+    }
+    for block in chain.into_iter().skip(1) {
+        let block_id = block.id;
+        blocks
+            .insert(block)
+            .map_err(|e| format!("could not load block {}: {:?}", block_id, e))?;
+    }
+    info!(
+        "Loaded {} block(s) from {}",
+        blocks.head().id,
+        path.display()
+    );
+    if let Some(snapshot) = snapshot {
+        info!(
+            "Replayed blocks after the snapshot of block {}",
+            snapshot.block_id
+        );
+        blocks.set_snapshot(snapshot)?;
+    }
+    Ok(())
+}
+
+/// Keeps the chain file of the data directory in step with the canonical
+/// chain: a new head is appended, anything else rewrites the file.
+fn store_chain(state: &NodeState, chain: &Blocks, new_head: Option<&Block>) {
+    let Some(data_dir) = &state.data_dir else {
+        return;
+    };
+    let path = data_dir.join(CHAIN_FILE);
+    let result = match new_head {
+        Some(block) => OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| write_blocks(&mut BufWriter::new(file), DumpFormat::Jsonl, [block])),
+        None => write_chain_file(&path, chain),
+    };
+    if let Err(e) = result {
+        error!("Could not store the chain in {}: {}", path.display(), e);
+    }
+}
+
+/// Writes the whole chain next to `path` first so a crash never leaves a
+/// partial chain file.
+fn write_chain_file(path: &Path, chain: &Blocks) -> Result<(), String> {
+    let partial = path.with_extension("partial");
+    let file = File::create(&partial)
+        .map_err(|e| format!("could not create {}: {}", partial.display(), e))?;
+    write_blocks(&mut BufWriter::new(file), DumpFormat::Jsonl, chain)?;
+    fs::rename(&partial, path).map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+/// Writes a snapshot of the newest final block (the head without finality)
+/// once it is `snapshot_interval` blocks past the previous snapshot, which it
+/// extends by the blocks in between.
+fn take_snapshot(state: &NodeState) {
+    let Some(data_dir) = &state.data_dir else {
+        return;
+    };
+    let mut blocks = state.blocks.write().unwrap();
+    let block_id = blocks.head().id.saturating_sub(state.finality);
+    let previous_id = blocks.snapshot().map_or(0, |snapshot| snapshot.block_id);
+    if state.snapshot_interval == 0 || block_id < previous_id + state.snapshot_interval {
+        return;
+    }
+
+    let chain = blocks.chain();
+    let snapshot = match blocks.snapshot() {
+        Some(previous) => previous.extend(&chain[previous_id as usize + 1..=block_id as usize]),
+        None => Snapshot::of(&chain[..=block_id as usize]),
+    };
+    let path = data_dir.join(SNAPSHOT_FILE);
+    match snapshot
+        .save(&path)
+        .and_then(|()| blocks.set_snapshot(snapshot))
+    {
+        Ok(()) => info!("Wrote snapshot of block {} to {}", block_id, path.display()),
+        Err(e) => return error!("{}", e),
+    }
+
+    if let Some(keep) = state.prune_keep {
+        let pruned = blocks.prune(keep);
+        if pruned > 0 {
+            info!("Pruned the transactions of {} block(s)", pruned);
+            store_chain(state, blocks.chain(), None);
         }
+    }
+}
+
+/// Loads a dump written by `b export` of the chain starting at `genesis` into
+/// the data directory of a node that has not run yet, checking its blocks
+/// against `rules`, and returns the id of its head block.
+pub fn import_dump(
+    path: &Path,
+    format: DumpFormat,
+    data_dir: &Path,
+    genesis: &Genesis,
+    rules: &ChainRules,
+) -> Result<BlockId, String> {
+    let chain_path = data_dir.join(CHAIN_FILE);
+    if chain_path.exists() {
+        return Err(format!(
+            "{} already holds a chain; import into a fresh data directory",
+            data_dir.display()
+        ));
+    }
+
+    let file = File::open(path).map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+    let chain = import_chain(read_blocks(BufReader::new(file), format), None, rules)
+        .map_err(|e| format!("could not import {}: {}", path.display(), e))?;
+    if chain[0].hash() != genesis.block().hash() {
+        return Err(format!(
+            "{} holds a chain with genesis block {}, not the one of the given genesis; pass the node's --genesis",
+            path.display(),
+            to_hex(&chain[0].hash())
+        ));
+    }
+
+    fs::create_dir_all(data_dir)
+        .map_err(|e| format!("could not create {}: {}", data_dir.display(), e))?;
+    write_chain_file(&chain_path, &chain)?;
+    Ok(chain.len() as BlockId - 1)
+}
 
-        let command: ClientCommands = match deserialize(&buffer[..bytes_read]) {
+fn handle_client(stream: TcpStream, state: NodeState) {
+    let mut reader = BufReader::new(stream.try_clone().expect("Failed to clone socket"));
+    let mut session = TcpSession { stream };
+    loop {
+        let command: ClientCommands = match deserialize_from(&mut reader) {
             Ok(cmd) => cmd,
             Err(e) => {
+                if let ErrorKind::Io(io_error) = e.as_ref() {
+                    if io_error.kind() == io::ErrorKind::UnexpectedEof {
+                        return;
+                    }
+                }
                 let error_message = format!("Failed to deserialize: {}", e);
                 error!("{}", &error_message);
                 let return_value: Result<(), String> = Err(error_message);
-                let serialized_command =
-                    serialize(&return_value).expect("Failed to serialize command");
-
-                stream
-                    .write(&serialized_command)
-                    .expect("failed to send serialized command");
+                serialize_into(&mut session.stream, &return_value)
+                    .expect("Failed to serialize command");
                 return;
             }
         };
 
-        let return_value: Result<ServerResponse, String> = match command {
-            ClientCommands::Balance { account } => {
-                info!("account_id: {} recieved", account);
-                match account == MASTER_ID {
-                    true => Err(format!("could not check balance for account id: MASTER_ID")),
-                    false => shared_blocks
-                        .read()
-                        .unwrap()
-                        .calculate_total(account)
-                        .map(|value| ServerResponse::Balance { balance: value })
-                        .ok_or_else(|| format!("could not find balance for account: {}", account)),
+        let return_value = handle_command(&state, command, &mut session).map_err(|e| e.to_string());
+        if let Err(e) = serialize_into(&mut session.stream, &return_value) {
+            debug!("Client went away: {}", e);
+            return;
+        }
+    }
+}
+
+/// Connection a command arrives on. Commands that wait for confirmations tell
+/// it about reorganizations and leave it to decide how to wait for new blocks.
+pub(crate) trait Session {
+    fn notify_reorg(&mut self, notice: &ReorgNotice);
+
+    /// Passes an event on to a subscriber; an error ends the subscription.
+    fn send_event(&mut self, _event: &NodeEvent) -> Result<(), String> {
+        Err("This connection cannot stream events".to_string())
+    }
+
+    /// Waits until the chain changed after `seen` and returns the new event.
+    fn wait_for_chain(&mut self, state: &NodeState, seen: &ChainEvent) -> ChainEvent {
+        let (lock, cvar) = state.chain_events.as_ref();
+        cvar.wait_while(lock.lock().unwrap(), |event| {
+            event.sequence == seen.sequence
+        })
+        .unwrap()
+        .clone()
+    }
+}
+
+struct TcpSession {
+    stream: TcpStream,
+}
+
+impl Session for TcpSession {
+    fn notify_reorg(&mut self, notice: &ReorgNotice) {
+        let notification: Result<ServerResponse, String> = Ok(ServerResponse::Reorganized {
+            orphaned_blocks: notice.orphaned_blocks,
+            head_block_id: notice.head_block_id,
+        });
+        if let Err(e) = serialize_into(&mut self.stream, &notification) {
+            warn!("Failed to notify client of reorg: {}", e);
+        }
+    }
+
+    fn send_event(&mut self, event: &NodeEvent) -> Result<(), String> {
+        let event: Result<ServerResponse, String> = Ok(ServerResponse::Event(event.clone()));
+        serialize_into(&mut self.stream, &event).map_err(|e| e.to_string())
+    }
+}
+
+/// Runs a command from a client (or a peer, for block exchange) against the node.
+pub(crate) fn handle_command(
+    state: &NodeState,
+    command: ClientCommands,
+    session: &mut dyn Session,
+) -> Result<ServerResponse, CommandError> {
+    match resolve_names(command, state)? {
+        ClientCommands::Balance {
+            account: AccountRef::Id(account),
+            asset,
+        } => {
+            info!("account_id: {} recieved", account);
+            let blocks = state.blocks.read().unwrap();
+            let chain = blocks.chain();
+            let status = account_status(
+                chain.iter().flat_map(|block| block.all_transactions()),
+                account,
+            );
+            match (account == MASTER_ID, status) {
+                (true, _) => Err("could not check balance for account id: MASTER_ID".into()),
+                (false, AccountStatus::Closed) => {
+                    Err(format!("Account {} is closed", to_address(account)).into())
                 }
+                (false, status) => blocks
+                    .balance(account, asset)
+                    .or_else(|| blocks.contains_account(account).then_some(0.0))
+                    .map(|value| ServerResponse::Balance {
+                        balance: value,
+                        locked: chain.calculate_locked(account, asset, blocks.head()),
+                        status,
+                    })
+                    .ok_or_else(|| {
+                        CommandError::NotFound(format!(
+                            "Account not found: {}",
+                            to_address(account)
+                        ))
+                    }),
             }
-            ClientCommands::CreateAccount {
-                account,
-                starting_balance,
-            } => {
-                info!("Received CreateAccount command");
-                let create_account = || {
-                    let transaction = Transaction::new(account, MASTER_ID, starting_balance);
+        }
+        ClientCommands::CreateAccount {
+            starting_balance,
+            confirmations,
+        } => {
+            info!("Received CreateAccount command");
+            let mut create_account = || -> Result<ServerResponse, CommandError> {
+                validate_balance(starting_balance)?;
+                let (account, transaction_id) = {
+                    let mut transactions = state.transactions.lock().unwrap();
+                    let blocks = state.blocks.read().unwrap();
+                    let supply = pending_supply(&blocks, &transactions, NATIVE_ASSET);
+                    state.rules.policy.check_mint(&supply, starting_balance)?;
+                    state
+                        .faucet
+                        .lock()
+                        .unwrap()
+                        .try_grant(starting_balance, state.clock.now())?;
+
+                    let account = new_account_id(
+                        state.random.as_ref(),
+                        &blocks,
+                        &transactions,
+                        &state.multisig.lock().unwrap(),
+                    );
+                    let transaction =
+                        state.transaction(account, MASTER_ID, starting_balance, NATIVE_ASSET);
                     let transaction_id = transaction.id;
+                    queue_transaction(state, &mut transactions, transaction);
+                    (account, transaction_id)
+                };
 
-                    {
-                        let mut transactions = shared_transactions.lock().unwrap();
-                        transactions.push(transaction);
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::AccountCreated {
+                    account,
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            create_account()
+        }
+        ClientCommands::IssueAsset {
+            asset,
+            supply,
+            issuer: AccountRef::Id(issuer),
+            confirmations,
+        } => {
+            info!("Received IssueAsset command");
+            let mut issue_asset = || -> Result<ServerResponse, CommandError> {
+                let transaction = state.transaction(issuer, MASTER_ID, supply, asset);
+                let transaction_id = transaction.id;
+
+                {
+                    let mut transactions = state.transactions.lock().unwrap();
+                    let blocks = state.blocks.read().unwrap();
+                    if pending_supply(&blocks, &transactions, asset).minted > 0.0 {
+                        return Err(format!(
+                            "Asset {} has already been issued",
+                            asset_symbol(asset)
+                        )
+                        .into());
                     }
+                    if !blocks.contains_account(issuer) && !transactions.contains_account(issuer) {
+                        return Err(CommandError::NotFound(format!(
+                            "Account not found: {}",
+                            to_address(issuer)
+                        )));
+                    }
+                    pending_status(issuer, blocks.chain(), &transactions).check_can_send(issuer)?;
+                    queue_transaction(state, &mut transactions, transaction);
+                }
 
-                    let block_id =
-                        wait_on_block_id(transaction_id, &shared_blocks, &shared_condvar);
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
 
-                    Ok(ServerResponse::Transferred {
-                        block_id,
-                        transaction_id,
-                    })
-                };
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
 
-                match (
-                    account == MASTER_ID,
-                    check_account_exists(account, &shared_blocks, &shared_transactions),
-                ) {
-                    (true, _) => Err(format!(
-                        "could not create account for account id: MASTER_ID"
-                    )),
-                    (_, true) => Err(format!("Account {} already exists", account)),
-                    (false, false) => create_account(),
+            match (asset == NATIVE_ASSET, reserved_account_name(issuer)) {
+                (true, _) => Err(format!(
+                    "{} is the native asset and cannot be issued",
+                    asset_symbol(asset)
+                )
+                .into()),
+                (_, Some(name)) => Err(format!(
+                    "could not issue asset because issuer account id was: {}",
+                    name
+                )
+                .into()),
+                (false, None) => {
+                    validate_amount(supply)?;
+                    issue_asset()
                 }
             }
-            ClientCommands::Transfer {
-                from_account,
-                to_account,
-                amount,
-            } => {
-                info!("Received Transfer command");
-                let transfer = || {
-                    let total_balance: Option<f64> = {
-                        let transactions = shared_transactions.lock().unwrap();
-                        let transactions_total = transactions.calculate_total(from_account);
-
-                        let blocks = shared_blocks.read().unwrap();
-                        let blocks_total = blocks.calculate_total(from_account);
-
-                        // total_balance Option type is a proxy for accounts existing
-                        match (transactions_total, blocks_total) {
-                            (Some(val1), Some(val2)) => Some(val1 + val2),
-                            (Some(val), None) | (None, Some(val)) => Some(val),
-                            (None, None) => None,
-                        }
-                    };
+        }
+        ClientCommands::Transfer {
+            from_account: AccountRef::Id(from_account),
+            to_account: AccountRef::Id(to_account),
+            amount,
+            asset,
+            unlock_height,
+            unlock_time,
+            confirmations,
+        } => {
+            info!("Received Transfer command");
+            let lock = match (unlock_height, unlock_time) {
+                (Some(height), _) => Some(TimeLock::Height(height)),
+                (None, Some(seconds)) => Some(TimeLock::Timestamp(seconds.saturating_mul(1000))),
+                (None, None) => None,
+            };
+            let mut transfer = || -> Result<ServerResponse, CommandError> {
+                let mut transaction = state.transaction(to_account, from_account, amount, asset);
+                if let Some(lock) = lock {
+                    transaction = transaction.with_lock(lock);
+                }
+                let transaction_id = submit_transfer(state, transaction)?;
 
-                    match total_balance {
-                        Some(balance) => {
-                            if balance >= amount {
-                                let transaction =
-                                    Transaction::new(to_account, from_account, amount);
-                                let transaction_id = transaction.id;
-                                {
-                                    let mut transactions = shared_transactions.lock().unwrap();
-                                    transactions.push(transaction)
-                                }
-
-                                let block_id = wait_on_block_id(
-                                    transaction_id,
-                                    &shared_blocks,
-                                    &shared_condvar,
-                                );
-
-                                Ok(ServerResponse::Transferred {
-                                    block_id,
-                                    transaction_id,
-                                })
-                            } else {
-                                Err(format!(
-                                    "Not enough in account {} to transfer {}",
-                                    from_account, amount
-                                ))
-                            }
-                        }
-                        None => Err(format!("Account not found: {}", from_account)),
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            match (
+                reserved_account_name(from_account),
+                reserved_account_name(to_account),
+            ) {
+                (Some(name), _) => {
+                    Err(format!("could not transfer because from account id was: {}", name).into())
+                }
+                (_, Some(name)) => {
+                    Err(format!("could not transfer because to account id was: {}", name).into())
+                }
+                (None, None) => {
+                    check_not_multisig(from_account, state)?;
+                    transfer()
+                }
+            }
+        }
+        ClientCommands::ScheduleTransfer {
+            from_account: AccountRef::Id(from_account),
+            to_account: AccountRef::Id(to_account),
+            amount,
+            asset,
+            every,
+            count,
+        } => {
+            info!("Received ScheduleTransfer command");
+            match (
+                reserved_account_name(from_account),
+                reserved_account_name(to_account),
+                check_account_exists(from_account, state),
+            ) {
+                (Some(name), _, _) => Err(format!(
+                    "could not schedule transfer because from account id was: {}",
+                    name
+                )
+                .into()),
+                (_, Some(name), _) => Err(format!(
+                    "could not schedule transfer because to account id was: {}",
+                    name
+                )
+                .into()),
+                (None, None, false) => Err(AccountError::SenderNotFound(from_account).into()),
+                (None, None, true) => {
+                    validate_amount(amount)?;
+                    check_not_multisig(from_account, state)?;
+                    {
+                        let transactions = state.transactions.lock().unwrap();
+                        let blocks = state.blocks.read().unwrap();
+                        check_recipient(to_account, &blocks, &transactions, state)?;
                     }
-                };
-                match (from_account == MASTER_ID, to_account == MASTER_ID) {
-                    (true, _) => Err(format!(
-                        "could not transfer because from account id was: MASTER_ID"
-                    )),
-                    (_, true) => Err(format!(
-                        "could not transfer because to account id was: MASTER_ID"
-                    )),
-                    (false, false) => transfer(),
+                    let first_block_id = state.blocks.read().unwrap().head().id + 1;
+                    let schedule_id = state.scheduler.lock().unwrap().add(Schedule {
+                        id: 0,
+                        from: from_account,
+                        to: to_account,
+                        amount,
+                        asset,
+                        every,
+                        next_height: first_block_id,
+                        remaining: count,
+                    })?;
+                    Ok(ServerResponse::Scheduled {
+                        schedule_id,
+                        first_block_id,
+                    })
                 }
             }
-        };
-
-        let serialized_command = serialize(&return_value).expect("Failed to serialize command");
-        stream
-            .write(&serialized_command)
-            .expect("failed to send serialized command");
-
-        return;
-    }
-}
-
-fn check_account_exists(
-    account: AccountId,
-    shared_blocks: &Arc<RwLock<Blocks>>,
-    shared_transactions: &Arc<Mutex<Transactions>>,
-) -> bool {
-    shared_blocks.read().unwrap().contains_account(account)
-        || shared_transactions
+        }
+        ClientCommands::CancelSchedule { schedule_id } => state
+            .scheduler
             .lock()
             .unwrap()
-            .contains_account(account)
-}
+            .cancel(schedule_id)
+            .map(|schedule| ServerResponse::ScheduleCancelled {
+                schedule_id: schedule.id,
+            })
+            .map_err(CommandError::NotFound),
+        ClientCommands::CreateMultisig { threshold, signers } => {
+            info!("Received CreateMultisig command");
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            let mut multisig = state.multisig.lock().unwrap();
+            let account = new_account_id(state.random.as_ref(), &blocks, &transactions, &multisig);
+            multisig.register(account, MultisigPolicy { threshold, signers })?;
+            Ok(ServerResponse::MultisigCreated { account, threshold })
+        }
+        ClientCommands::ProposeTransfer {
+            from_account: AccountRef::Id(from_account),
+            to_account: AccountRef::Id(to_account),
+            amount,
+            asset,
+            expires_in,
+        } => {
+            info!("Received ProposeTransfer command");
+            match reserved_account_name(to_account) {
+                Some(name) => Err(format!(
+                    "could not propose transfer because to account id was: {}",
+                    name
+                )
+                .into()),
+                None => {
+                    validate_amount(amount)?;
+                    let expires_at = state.blocks.read().unwrap().head().id + expires_in;
+                    let proposal_id = state.multisig.lock().unwrap().propose(
+                        from_account,
+                        to_account,
+                        amount,
+                        asset,
+                        expires_at,
+                        state.clock.now(),
+                    )?;
+                    Ok(ServerResponse::Proposed {
+                        proposal_id,
+                        expires_at,
+                    })
+                }
+            }
+        }
+        ClientCommands::Approve {
+            proposal_id,
+            signer,
+            signature,
+            confirmations,
+            ..
+        } => {
+            info!("Received Approve command");
+            let height = state.blocks.read().unwrap().head().id;
+            let outcome =
+                state
+                    .multisig
+                    .lock()
+                    .unwrap()
+                    .approve(proposal_id, &signer, &signature, height);
+            match outcome {
+                Err(e) => Err(e.into()),
+                Ok(ApprovalOutcome::Pending {
+                    approvals,
+                    threshold,
+                }) => Ok(ServerResponse::Approval {
+                    proposal_id,
+                    approvals,
+                    threshold,
+                }),
+                Ok(ApprovalOutcome::Approved(proposal)) => {
+                    let mut execute = || -> Result<ServerResponse, CommandError> {
+                        let submitted = submit_transfer(
+                            state,
+                            state.transaction(
+                                proposal.to,
+                                proposal.from,
+                                proposal.amount,
+                                proposal.asset,
+                            ),
+                        );
+                        // The proposal is only closed once its transfer is queued
+                        let mut multisig = state.multisig.lock().unwrap();
+                        let transaction_id = match submitted {
+                            Ok(transaction_id) => transaction_id,
+                            Err(e) => {
+                                multisig.withdraw(proposal.id, &signer);
+                                return Err(format!(
+                                    "Proposal {} was approved but could not be executed: {}; it stays open and can be approved again",
+                                    proposal.id, e
+                                ).into());
+                            }
+                        };
+                        multisig.complete(proposal.id);
+                        drop(multisig);
 
-fn wait_on_block_id(
+                        let (block_id, confirmations, is_final) =
+                            wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                        Ok(ServerResponse::Transferred {
+                            block_id,
+                            transaction_id,
+                            confirmations,
+                            finality: state.finality,
+                            is_final,
+                        })
+                    };
+                    execute()
+                }
+            }
+        }
+        ClientCommands::OpenEscrow {
+            payer: AccountRef::Id(payer),
+            payee: AccountRef::Id(payee),
+            amount,
+            asset,
+            arbiter,
+            hashlock,
+            timeout,
+            confirmations,
+        } => {
+            info!("Received OpenEscrow command");
+            let mut open_escrow = || -> Result<ServerResponse, CommandError> {
+                let height = state.blocks.read().unwrap().head().id;
+                let terms = EscrowTerms {
+                    payee,
+                    arbiter,
+                    hashlock,
+                    timeout: height + timeout,
+                };
+                terms.validate(height)?;
+
+                let transaction = state
+                    .transaction(ESCROW_ID, payer, amount, asset)
+                    .with_kind(TransactionKind::EscrowOpen(terms));
+                let transaction_id = submit_transfer(state, transaction)?;
+
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            match (reserved_account_name(payer), reserved_account_name(payee)) {
+                (Some(name), _) => Err(format!(
+                    "could not open escrow because payer account id was: {}",
+                    name
+                )
+                .into()),
+                (_, Some(name)) => Err(format!(
+                    "could not open escrow because payee account id was: {}",
+                    name
+                )
+                .into()),
+                (None, None) => {
+                    check_not_multisig(payer, state)?;
+                    open_escrow()
+                }
+            }
+        }
+        ClientCommands::ReleaseEscrow {
+            escrow_id,
+            preimage,
+            signature,
+            confirmations,
+            ..
+        } => {
+            info!("Received ReleaseEscrow command");
+            let release = || -> Result<ServerResponse, CommandError> {
+                let transaction_id = settle_escrow(state, escrow_id, |escrow, height| {
+                    escrow.release(height, state.clock.now(), preimage, signature.as_deref())
+                })?;
+
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            release()
+        }
+        ClientCommands::RefundEscrow {
+            escrow_id,
+            signature,
+            confirmations,
+            ..
+        } => {
+            info!("Received RefundEscrow command");
+            let mut refund = || -> Result<ServerResponse, CommandError> {
+                let transaction_id = settle_escrow(state, escrow_id, |escrow, height| {
+                    escrow.refund(height, state.clock.now(), signature.as_deref())
+                })?;
+
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            refund()
+        }
+        ClientCommands::EscrowStatus { escrow_id } => {
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            pending_escrow(blocks.chain(), &transactions, escrow_id)
+                .map(|escrow| ServerResponse::Escrow {
+                    escrow,
+                    head_block_id: blocks.head().id,
+                })
+                .ok_or_else(|| CommandError::NotFound(format!("Escrow not found: {}", escrow_id)))
+        }
+        ClientCommands::FreezeAccount {
+            account: AccountRef::Id(account),
+            auth,
+            confirmations,
+        } => {
+            info!("Received FreezeAccount command");
+            update_account(
+                state,
+                account,
+                AdminAction::Freeze,
+                &auth,
+                confirmations,
+                session,
+            )
+        }
+        ClientCommands::UnfreezeAccount {
+            account: AccountRef::Id(account),
+            auth,
+            confirmations,
+        } => {
+            info!("Received UnfreezeAccount command");
+            update_account(
+                state,
+                account,
+                AdminAction::Unfreeze,
+                &auth,
+                confirmations,
+                session,
+            )
+        }
+        ClientCommands::CloseAccount {
+            account: AccountRef::Id(account),
+            sweep_to: AccountRef::Id(sweep_to),
+            auth,
+            confirmations,
+        } => {
+            info!("Received CloseAccount command");
+            update_account(
+                state,
+                account,
+                AdminAction::Close { sweep_to },
+                &auth,
+                confirmations,
+                session,
+            )
+        }
+        ClientCommands::RegisterName {
+            account: AccountRef::Id(account),
+            name,
+            confirmations,
+        } => {
+            info!("Received RegisterName command");
+            let register = || -> Result<ServerResponse, CommandError> {
+                let transaction = state
+                    .transaction(account, account, 0.0, NATIVE_ASSET)
+                    .with_kind(TransactionKind::RegisterName { name });
+                let transaction_id = submit_transfer(state, transaction)?;
+
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            match reserved_account_name(account) {
+                Some(name) => Err(format!("could not register a name for: {}", name).into()),
+                None => {
+                    check_not_multisig(account, state)?;
+                    register()
+                }
+            }
+        }
+        ClientCommands::TransferName {
+            name,
+            to_account: AccountRef::Id(to_account),
+            confirmations,
+        } => {
+            info!("Received TransferName command");
+            let transfer_name = || -> Result<ServerResponse, CommandError> {
+                let owner = {
+                    let transactions = state.transactions.lock().unwrap();
+                    let blocks = state.blocks.read().unwrap();
+                    pending_names(blocks.chain(), &transactions)
+                        .get(&name)
+                        .copied()
+                        .ok_or_else(|| {
+                            CommandError::NotFound(format!("Name not registered: {}", name))
+                        })?
+                };
+                check_not_multisig(owner, state)?;
+
+                let transaction = state
+                    .transaction(to_account, owner, 0.0, NATIVE_ASSET)
+                    .with_kind(TransactionKind::TransferName { name });
+                let transaction_id = submit_transfer(state, transaction)?;
+
+                let (block_id, confirmations, is_final) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+                Ok(ServerResponse::Transferred {
+                    block_id,
+                    transaction_id,
+                    confirmations,
+                    finality: state.finality,
+                    is_final,
+                })
+            };
+            match reserved_account_name(to_account) {
+                Some(name) => Err(format!("could not transfer a name to: {}", name).into()),
+                None => transfer_name(),
+            }
+        }
+        ClientCommands::Resolve { account } => {
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            let registry = pending_names(blocks.chain(), &transactions);
+            let account = match account {
+                AccountRef::Id(id) => match registry.values().any(|owner| *owner == id)
+                    || blocks.contains_account(id)
+                    || transactions.contains_account(id)
+                {
+                    true => Ok(id),
+                    false => Err(CommandError::NotFound(format!(
+                        "Account not found: {}",
+                        to_address(id)
+                    ))),
+                },
+                AccountRef::Name(name) => registry.get(&name).copied().ok_or_else(|| {
+                    CommandError::NotFound(format!("Name not registered: {}", name))
+                }),
+            };
+            account.map(|account| ServerResponse::Resolved {
+                account,
+                names: registry
+                    .into_iter()
+                    .filter(|(_, owner)| *owner == account)
+                    .map(|(name, _)| name)
+                    .collect(),
+            })
+        }
+        ClientCommands::AccountInfo {
+            account: AccountRef::Id(account),
+        } => {
+            let multisig_threshold = state
+                .multisig
+                .lock()
+                .unwrap()
+                .policy(account)
+                .map(|policy| policy.threshold);
+            let exists = check_account_exists(account, state) || multisig_threshold.is_some();
+
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            // An account the snapshot knows may first appear in a pruned block
+            let created_block_id = blocks.chain().find_account(account);
+            let created_pruned = blocks.pruned_through().is_some()
+                && blocks
+                    .snapshot()
+                    .is_some_and(|snapshot| snapshot.contains_account(account))
+                && created_block_id != Some(0);
+            Ok(ServerResponse::AccountInfo {
+                account,
+                exists,
+                created_block_id: created_block_id.filter(|_| !created_pruned),
+                created_pruned,
+                pending_transactions: transactions
+                    .iter()
+                    .filter(|t| t.to() == account || t.from() == account)
+                    .count(),
+                status: pending_status(account, blocks.chain(), &transactions),
+                names: pending_names(blocks.chain(), &transactions)
+                    .into_iter()
+                    .filter(|(_, owner)| *owner == account)
+                    .map(|(name, _)| name)
+                    .collect(),
+                multisig_threshold,
+            })
+        }
+        ClientCommands::DeployContract {
+            from_account: AccountRef::Id(from_account),
+            code,
+            confirmations,
+            ..
+        } => {
+            info!("Received DeployContract command");
+            let deploy = || -> Result<ServerResponse, CommandError> {
+                vm::validate(&code)?;
+                check_not_multisig(from_account, state)?;
+                let transaction = state
+                    .transaction(from_account, from_account, 0.0, NATIVE_ASSET)
+                    .with_kind(TransactionKind::Deploy { code });
+                let contract = submit_transfer(state, transaction)?;
+
+                let (block_id, _, _) =
+                    wait_on_confirmations(contract, confirmations, state, session)?;
+                Ok(ServerResponse::ContractDeployed { contract, block_id })
+            };
+            match reserved_account_name(from_account) {
+                Some(name) => Err(format!(
+                    "could not deploy contract because from account id was: {}",
+                    name
+                )
+                .into()),
+                None => deploy(),
+            }
+        }
+        ClientCommands::CallContract {
+            from_account: AccountRef::Id(from_account),
+            contract: AccountRef::Id(contract),
+            args,
+            gas_limit,
+            confirmations,
+        } => {
+            info!("Received CallContract command");
+            let call = || -> Result<ServerResponse, CommandError> {
+                if gas_limit > MAX_GAS_LIMIT {
+                    return Err(format!("Gas limit is capped at {}", MAX_GAS_LIMIT).into());
+                }
+                check_not_multisig(from_account, state)?;
+                {
+                    let transactions = state.transactions.lock().unwrap();
+                    let blocks = state.blocks.read().unwrap();
+                    let deployed = blocks
+                        .chain()
+                        .iter()
+                        .flat_map(|block| &block.transactions)
+                        .chain(transactions.iter());
+                    if find_contract(deployed, contract).is_none() {
+                        return Err(CommandError::NotFound(format!(
+                            "Contract not found: {}",
+                            to_address(contract)
+                        )));
+                    }
+                }
+
+                let transaction = state
+                    .transaction(contract, from_account, 0.0, NATIVE_ASSET)
+                    .with_kind(TransactionKind::Call { args, gas_limit });
+                let transaction_id = submit_transfer(state, transaction)?;
+
+                let (block_id, _, _) =
+                    wait_on_confirmations(transaction_id, confirmations, state, session)?;
+                // The lock was released while waiting, so a reorganization
+                // may have replaced the block or removed it altogether
+                let blocks = state.blocks.read().unwrap();
+                blocks
+                    .chain()
+                    .get(block_id as usize)
+                    .and_then(|block| {
+                        block
+                            .receipts
+                            .iter()
+                            .find(|receipt| receipt.transaction_id == transaction_id)
+                    })
+                    .map(|receipt| ServerResponse::ContractCalled {
+                        block_id,
+                        transaction_id,
+                        receipt: receipt.clone(),
+                    })
+                    .ok_or_else(|| {
+                        CommandError::from(format!(
+                            "Call {} was reorganized out of block {}; check it with get-tx",
+                            transaction_id, block_id
+                        ))
+                    })
+            };
+            match reserved_account_name(from_account) {
+                Some(name) => Err(format!(
+                    "could not call contract because from account id was: {}",
+                    name
+                )
+                .into()),
+                None => call(),
+            }
+        }
+        ClientCommands::ContractStorage {
+            contract: AccountRef::Id(contract),
+        } => {
+            let blocks = state.blocks.read().unwrap();
+            match find_contract(
+                blocks.chain().iter().flat_map(|block| &block.transactions),
+                contract,
+            ) {
+                Some(_) => Ok(ServerResponse::ContractStorage {
+                    contract,
+                    storage: contract_storage(blocks.chain(), contract),
+                }),
+                None => Err(CommandError::NotFound(format!(
+                    "Contract not found: {}",
+                    to_address(contract)
+                ))),
+            }
+        }
+        ClientCommands::TransactionStatus { transaction_id } => {
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            match blocks
+                .chain()
+                .find_transaction(transaction_id)
+                .or_else(|| blocks.find_pruned_transaction(transaction_id))
+            {
+                Some(block_id) => Ok(ServerResponse::TransactionStatus {
+                    transaction_id,
+                    block_id: Some(block_id),
+                    confirmations: blocks.confirmations(block_id),
+                    finality: state.finality,
+                    is_final: blocks.is_final(block_id),
+                }),
+                None if transactions.iter().any(|t| t.id == transaction_id) => {
+                    Ok(ServerResponse::TransactionStatus {
+                        transaction_id,
+                        block_id: None,
+                        confirmations: 0,
+                        finality: state.finality,
+                        is_final: false,
+                    })
+                }
+                None => Err(CommandError::NotFound(format!(
+                    "Transaction not found: {}",
+                    transaction_id
+                ))),
+            }
+        }
+        ClientCommands::History {
+            account: AccountRef::Id(account),
+            from,
+        } => {
+            let blocks = state.blocks.read().unwrap();
+            match blocks.pruned_through() {
+                Some(pruned) if from <= pruned => Err(format!(
+                    "Blocks up to {} were pruned on this node; list from block {} or ask an archive node",
+                    pruned,
+                    pruned + 1
+                ).into()),
+                _ => Ok(ServerResponse::History {
+                    account,
+                    transactions: blocks
+                        .chain()
+                        .iter()
+                        .skip(from as usize)
+                        .flat_map(|block| {
+                            block
+                                .all_transactions()
+                                .filter(|t| t.to() == account || t.from() == account)
+                                .map(|t| (block.id, t.clone()))
+                        })
+                        .collect(),
+                }),
+            }
+        }
+        ClientCommands::GetTx { transaction_id } => {
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            let sealed = blocks.chain().iter().find_map(|block| {
+                block
+                    .all_transactions()
+                    .find(|t| t.id == transaction_id)
+                    .map(|t| (Some(block.id), t))
+            });
+            let pending = || {
+                transactions
+                    .iter()
+                    .find(|t| t.id == transaction_id)
+                    .map(|t| (None, t))
+            };
+            match sealed.or_else(pending) {
+                Some((block_id, transaction)) => Ok(ServerResponse::Transaction {
+                    block_id,
+                    transaction: transaction.clone(),
+                }),
+                None => match blocks.find_pruned_transaction(transaction_id) {
+                    Some(block_id) => Err(format!(
+                        "Transaction {} is in block {}, whose transactions were pruned on this node; ask an archive node",
+                        transaction_id, block_id
+                    ).into()),
+                    None => Err(CommandError::NotFound(format!("Transaction not found: {}", transaction_id))),
+                },
+            }
+        }
+        ClientCommands::Supply { asset } => {
+            let blocks = state.blocks.read().unwrap();
+            let supply = check_supply_invariant(blocks.snapshot(), blocks.chain(), asset)?;
+            Ok(ServerResponse::Supply {
+                supply,
+                supply_cap: match asset == NATIVE_ASSET {
+                    true => state.rules.policy.supply_cap,
+                    false => None,
+                },
+            })
+        }
+        ClientCommands::Subscribe { account: None } => stream_events(state, None, session),
+        ClientCommands::Subscribe {
+            account: Some(AccountRef::Id(account)),
+        } => stream_events(state, Some(account), session),
+        ClientCommands::ChainInfo => {
+            let blocks = state.blocks.read().unwrap();
+            Ok(ServerResponse::ChainInfo {
+                chain_id: state.chain_id.to_string(),
+                genesis_hash: state.genesis_hash,
+                head_block_id: blocks.head().id,
+                parameters: state.parameters.as_ref().clone(),
+            })
+        }
+        ClientCommands::SubmitBlock { genesis, .. }
+        | ClientCommands::GetBlocks { genesis, .. }
+        | ClientCommands::GetSnapshot { genesis }
+            if genesis != state.genesis_hash =>
+        {
+            Err(format!(
+                "Genesis mismatch: this node runs chain {} with genesis {}",
+                state.chain_id,
+                to_hex(&state.genesis_hash)
+            )
+            .into())
+        }
+        ClientCommands::SubmitBlock { block, origin, .. } => {
+            debug!("Received block {} from peer {}", block.id, origin);
+            let block_id = block.id;
+            match accept_block(state, block.clone()) {
+                Ok(true) => {
+                    broadcast_block(&block, state);
+                    take_snapshot(state);
+                    Ok(ServerResponse::BlockReceived { block_id })
+                }
+                Ok(false) => Ok(ServerResponse::BlockReceived { block_id }),
+                Err(e) => {
+                    warn!("{}", e);
+                    if origin != state.port {
+                        let sync_state = state.clone();
+                        state.network.send(
+                            origin,
+                            sync_command(state),
+                            Box::new(move |answer| accept_synced(&sync_state, origin, answer)),
+                        );
+                    }
+                    Err(e.into())
+                }
+            }
+        }
+        ClientCommands::Export { from, to, .. } => {
+            let blocks = state.blocks.read().unwrap();
+            let head_block_id = blocks.head().id;
+            let to = to.map_or(head_block_id, |to| to.min(head_block_id));
+            match from <= to {
+                true => Ok(ServerResponse::Blocks {
+                    blocks: blocks.chain()[from as usize..=to as usize].to_vec(),
+                }),
+                false => Err(format!(
+                    "No blocks from {} to {} (head block is {})",
+                    from, to, head_block_id
+                )
+                .into()),
+            }
+        }
+        ClientCommands::GetBlocks { from, .. } => {
+            let blocks = state.blocks.read().unwrap();
+            Ok(ServerResponse::Blocks {
+                blocks: blocks.chain().iter().skip(from as usize).cloned().collect(),
+            })
+        }
+        ClientCommands::GetSnapshot { .. } => {
+            let blocks = state.blocks.read().unwrap();
+            Ok(ServerResponse::Snapshot {
+                snapshot: blocks.snapshot().cloned(),
+                blocks: blocks.chain()[1..].to_vec(),
+            })
+        }
+        command => Err(format!("Unresolved account name in {:?}", command).into()),
+    }
+}
+
+/// Assembles the next block from the mempool, mining it under proof of work,
+/// adds it to the chain and announces it to the peers.
+pub(crate) fn seal_block(state: &NodeState, options: &NodeOptions) -> Result<Block, String> {
+    let block = match options.consensus {
+        Consensus::Interval => {
+            run_schedules(state);
+            assemble_block(state, None)
+        }
+        Consensus::Pow => {
+            run_schedules(state);
+            let coinbase = options.miner.map(|miner| (miner, options.reward));
+            let mut block = assemble_block(state, coinbase);
+            // Retargeted for the chain the block was assembled on, which a
+            // block of a peer may have extended since
+            let difficulty = state
+                .blocks
+                .read()
+                .unwrap()
+                .branch(&block.previous_hash)
+                .map_or(options.difficulty, |branch| state.rules.difficulty(&branch));
+            block.difficulty = difficulty;
+
+            debug!("Mining block {} at difficulty {}", block.id, difficulty);
+            let hash = mine(&mut block);
+            info!(
+                "Mined block {} with nonce {} (hash {})",
+                block.id,
+                block.nonce,
+                to_hex(&hash)
+            );
+            block
+        }
+    };
+
+    if accept_block(state, block.clone())? {
+        broadcast_block(&block, state);
+        take_snapshot(state);
+    }
+    Ok(block)
+}
+
+/// Builds an unsealed block on top of the current head from the mempool,
+/// paying the miner as much of `coinbase` as the supply cap allows.
+fn assemble_block(state: &NodeState, coinbase: Option<(AccountId, f64)>) -> Block {
+    let mut transactions = state.transactions.lock().unwrap();
+    let blocks = state.blocks.read().unwrap();
+    let head = blocks.head();
+
+    let coinbase = coinbase.and_then(|(miner, reward)| {
+        let supply = pending_supply(&blocks, &transactions, NATIVE_ASSET);
+        match state.rules.policy.coinbase_reward(&supply, reward) {
+            Some(reward) => Some(state.transaction(miner, MASTER_ID, reward, NATIVE_ASSET)),
+            None => {
+                debug!("Supply cap reached, mining block without coinbase");
+                None
+            }
+        }
+    });
+
+    // Transactions that belong together are sealed whole; a bundle larger
+    // than the limit goes into a block of its own. Transactions checked
+    // against a chain that was reorganized away since may no longer pass the
+    // checks peers run on the block, and are dropped.
+    let timestamp = state.clock.now_millis();
+    let rules = state.rules();
+    let mut check = BlockCheck::new(
+        &rules,
+        blocks.chain(),
+        blocks.balances_at(blocks.chain()),
+        timestamp,
+    );
+    let coinbase = coinbase.filter(|coinbase| check.apply(std::slice::from_ref(coinbase)).is_ok());
+    let mut sealed = Vec::new();
+    let mut dropped = Vec::new();
+    for bundle in transactions.chunk_by(|previous, next| next.is_bundled_with(previous)) {
+        if !sealed.is_empty()
+            && sealed.len() + bundle.len() > state.parameters.max_block_transactions
+        {
+            break;
+        }
+        match check.apply(bundle) {
+            Ok(()) => sealed.extend_from_slice(bundle),
+            Err(e) => {
+                warn!("Dropping transaction {}: {}", bundle[0].id, e);
+                dropped.extend(bundle.iter().map(|t| t.id));
+            }
+        }
+    }
+    transactions.retain(|t| !dropped.contains(&t.id));
+    let mut block = Block::new(head.id + 1, head.hash(), sealed);
+    block.timestamp = timestamp;
+    block.coinbase = coinbase;
+    block.receipts = execute_calls(blocks.chain(), &block.transactions);
+    block
+}
+
+/// Inserts a block into the block tree, keeping the mempool consistent with
+/// the canonical chain. Returns `false` if the block was already known.
+fn accept_block(state: &NodeState, block: Block) -> Result<bool, String> {
+    let block_id = block.id;
+    if block.is_pruned() {
+        return Err(format!(
+            "Rejected block {}: its transactions were pruned; sync from an archive node",
+            block_id
+        ));
+    }
+    // Retargeting goes by timestamps, so they may not run ahead of the clock
+    let now = state.clock.now_millis();
+    if block.timestamp > now + MAX_BLOCK_DRIFT {
+        return Err(format!(
+            "Rejected block {}: it is stamped {} ms ahead of this node's clock",
+            block_id,
+            block.timestamp - now
+        ));
+    }
+    let mut transactions = state.transactions.lock().unwrap();
+    let mut blocks = state.blocks.write().unwrap();
+
+    // Checked against the branch the block extends, which need not be the
+    // canonical chain; blocks with an unknown parent are refused by insert
+    if let Some(branch) = blocks.branch(&block.previous_hash) {
+        check_block(
+            &state.rules(),
+            &branch,
+            &mut blocks.balances_at(&branch),
+            &block,
+        )
+        .map_err(|e| format!("Rejected block {}: {}", block_id, e))?;
+    }
+
+    let outcome = blocks
+        .insert(block)
+        .map_err(|e| format!("Rejected block {}: {:?}", block_id, e))?;
+
+    match outcome {
+        InsertOutcome::Duplicate => return Ok(false),
+        InsertOutcome::SideChain => info!("Stored side chain block {}", block_id),
+        InsertOutcome::Extended => {
+            let head = blocks.head();
+            remove_sealed(&mut transactions, head);
+            publish_chain_event(state, None);
+            publish_event(state, &NodeEvent::Block(head.clone()));
+            store_chain(state, blocks.chain(), Some(head));
+            info!("Block published: {:?}", head);
+            if let Err(e) = check_supply_invariant(blocks.snapshot(), blocks.chain(), NATIVE_ASSET)
+            {
+                error!("{}", e);
+            }
+        }
+        InsertOutcome::Reorganized(reorg) => {
+            let requeued = requeue_orphaned(&reorg, &blocks, &mut transactions);
+            let notice = ReorgNotice {
+                orphaned_blocks: reorg.orphaned.len() as u64,
+                head_block_id: blocks.head().id,
+            };
+            warn!(
+                "Chain reorganized at block {}: {} block(s) orphaned, {} adopted",
+                reorg.adopted[0].id,
+                reorg.orphaned.len(),
+                reorg.adopted.len()
+            );
+            publish_event(
+                state,
+                &NodeEvent::Reorganized {
+                    orphaned_blocks: notice.orphaned_blocks,
+                    head_block_id: notice.head_block_id,
+                },
+            );
+            for block in &reorg.adopted {
+                publish_event(state, &NodeEvent::Block(block.clone()));
+            }
+            for transaction in requeued {
+                publish_event(state, &NodeEvent::Pending(transaction));
+            }
+            publish_chain_event(state, Some(notice));
+            store_chain(state, blocks.chain(), None);
+            if let Err(e) = check_supply_invariant(blocks.snapshot(), blocks.chain(), NATIVE_ASSET)
+            {
+                error!("{}", e);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Drops the transactions a block sealed from the mempool, along with the
+/// fees of sealed transactions should a peer have sealed them without.
+fn remove_sealed(transactions: &mut Transactions, block: &Block) {
+    let is_sealed = |id| block.transactions.iter().any(|t| t.id == id);
+    transactions.retain(|pending| match pending.kind() {
+        TransactionKind::Fee { transaction } if is_sealed(*transaction) => false,
+        _ => !is_sealed(pending.id),
+    });
+}
+
+/// Returns transactions of orphaned blocks that did not make it into the new
+/// canonical chain to the mempool, dropping those that are no longer valid,
+/// and gives back the requeued ones.
+fn requeue_orphaned(
+    reorg: &Reorg,
+    blocks: &BlockTree,
+    transactions: &mut Transactions,
+) -> Vec<Transaction> {
+    let chain = blocks.chain();
+    for block in &reorg.adopted {
+        remove_sealed(transactions, block);
+    }
+
+    // Transactions that belong together, such as a transfer and its fee, are
+    // requeued together or not at all
+    let bundles = reorg.orphaned.iter().flat_map(|block| {
+        block
+            .transactions
+            .chunk_by(|previous, next| next.is_bundled_with(previous))
+    });
+    let mut requeued = Vec::new();
+    for bundle in bundles {
+        if bundle.iter().any(|transaction| {
+            chain.find_transaction(transaction.id).is_some()
+                || transactions.iter().any(|t| t.id == transaction.id)
+        }) {
+            continue;
+        }
+
+        let queued = transactions.len();
+        for transaction in bundle {
+            match is_still_valid(transaction, blocks, transactions) {
+                true => transactions.push(transaction.clone()),
+                false => {
+                    warn!(
+                        "Dropping orphaned transaction {} which is no longer valid",
+                        transaction.id
+                    );
+                    transactions.truncate(queued);
+                    break;
+                }
+            }
+        }
+        if transactions.len() > queued {
+            requeued.extend_from_slice(bundle);
+        }
+    }
+    requeued
+}
+
+/// Whether an orphaned transaction can still be made on top of the canonical
+/// chain and the mempool.
+fn is_still_valid(
+    transaction: &Transaction,
+    blocks: &BlockTree,
+    transactions: &Transactions,
+) -> bool {
+    let chain = blocks.chain();
+    let asset = transaction.asset();
+    match (transaction.from() == MASTER_ID, asset == NATIVE_ASSET) {
+        // Pending transfers of the new account depend on its mint, so
+        // only another mint means the account was created elsewhere
+        (true, true) => {
+            let mints = |t: &&Transaction| t.from() == MASTER_ID && t.to() == transaction.to();
+            !chain
+                .iter()
+                .flat_map(Block::all_transactions)
+                .chain(transactions.iter())
+                .any(|t| mints(&t))
+        }
+        (true, false) => pending_supply(blocks, transactions, asset).minted <= 0.0,
+        (false, _) => match transaction.kind() {
+            TransactionKind::EscrowRelease { escrow, .. }
+            | TransactionKind::EscrowRefund { escrow, .. } => {
+                pending_escrow(chain, transactions, *escrow)
+                    .is_some_and(|escrow| escrow.state == EscrowState::Open)
+            }
+            TransactionKind::RegisterName { name } => {
+                !pending_names(chain, transactions).contains_key(name)
+            }
+            TransactionKind::TransferName { name } => {
+                pending_names(chain, transactions).get(name) == Some(&transaction.from())
+            }
+            _ => pending_spendable(transaction.from(), asset, blocks, transactions)
+                .is_some_and(|balance| balance >= transaction.amount()),
+        },
+    }
+}
+
+fn publish_chain_event(state: &NodeState, reorg: Option<ReorgNotice>) {
+    let (lock, cvar) = &*state.chain_events;
+    let mut event = lock.lock().unwrap();
+    event.sequence += 1;
+    if reorg.is_some() {
+        event.reorgs += 1;
+        event.last_reorg = reorg;
+    }
+    cvar.notify_all();
+}
+
+/// Hands an event to every subscriber it concerns and forgets subscribers
+/// that went away or fell too far behind.
+fn publish_event(state: &NodeState, event: &NodeEvent) {
+    let involves = |transaction: &Transaction, account| {
+        transaction.from() == account
+            || transaction.to() == account
+            || matches!(transaction.kind(), TransactionKind::EscrowOpen(terms) if terms.payee == account)
+    };
+    state.subscribers.lock().unwrap().retain(|subscriber| {
+        let events = match (subscriber.account, event) {
+            (None, event) => vec![event.clone()],
+            (Some(account), NodeEvent::Block(block)) => block
+                .all_transactions()
+                .filter(|transaction| involves(transaction, account))
+                .map(|transaction| NodeEvent::Sealed {
+                    block_id: block.id,
+                    transaction: transaction.clone(),
+                })
+                .collect(),
+            (Some(account), NodeEvent::Pending(transaction)) => {
+                match involves(transaction, account) {
+                    true => vec![event.clone()],
+                    false => Vec::new(),
+                }
+            }
+            (Some(_), event) => vec![event.clone()],
+        };
+        events
+            .into_iter()
+            .all(|event| match subscriber.sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "Dropping a subscriber that fell {} events behind",
+                        SUBSCRIBER_BACKLOG
+                    );
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            })
+    });
+}
+
+/// Streams events to the session until the subscriber goes away.
+fn stream_events(
+    state: &NodeState,
+    account: Option<AccountId>,
+    session: &mut dyn Session,
+) -> Result<ServerResponse, CommandError> {
+    if let Some(account) = account.filter(|account| !check_account_exists(*account, state)) {
+        return Err(CommandError::NotFound(format!(
+            "Account not found: {}",
+            to_address(account)
+        )));
+    }
+    info!("Client subscribed to events");
+    for event in state.subscribe(account) {
+        session.send_event(&event)?;
+    }
+    Err("The node stopped publishing events, or this subscriber fell too far behind".into())
+}
+
+/// Adds a transaction to the mempool and tells subscribers about it.
+fn queue_transaction(state: &NodeState, transactions: &mut Transactions, transaction: Transaction) {
+    publish_event(state, &NodeEvent::Pending(transaction.clone()));
+    transactions.push(transaction);
+}
+
+fn broadcast_block(block: &Block, state: &NodeState) {
+    for peer in state.peers.iter() {
+        let peer = *peer;
+        let command = ClientCommands::SubmitBlock {
+            block: block.clone(),
+            origin: state.port,
+            genesis: state.genesis_hash,
+        };
+        state.network.send(
+            peer,
+            command,
+            Box::new(move |answer| match answer {
+                Ok(Ok(response)) => debug!("Peer {} answered: {:?}", peer, response),
+                Ok(Err(e)) => debug!("Peer {} refused block: {}", peer, e),
+                Err(e) => warn!("Could not reach peer {}: {}", peer, e),
+            }),
+        );
+    }
+}
+
+/// Fetches the canonical chain of a peer and inserts every block we do not know yet.
+fn sync_from_peer(state: &NodeState, peer: u16) {
+    let answer = state.network.request(peer, &sync_command(state));
+    accept_synced(state, peer, answer);
+}
+
+fn sync_command(state: &NodeState) -> ClientCommands {
+    ClientCommands::GetBlocks {
+        from: 1,
+        genesis: state.genesis_hash,
+    }
+}
+
+fn accept_synced(state: &NodeState, peer: u16, answer: Answer) {
+    let blocks = match answer {
+        Ok(Ok(ServerResponse::Blocks { blocks })) => blocks,
+        Ok(Ok(response)) => {
+            warn!("Unexpected response from peer {}: {:?}", peer, response);
+            return;
+        }
+        Ok(Err(e)) => {
+            warn!("Peer {} refused to sync: {}", peer, e);
+            return;
+        }
+        Err(e) => {
+            warn!("Could not sync from peer {}: {}", peer, e);
+            return;
+        }
+    };
+
+    info!("Syncing {} block(s) from peer {}", blocks.len(), peer);
+    for block in blocks {
+        if let Err(e) = accept_block(state, block) {
+            warn!("{}", e);
+            break;
+        }
+    }
+    take_snapshot(state);
+}
+
+/// Adopts the latest snapshot of a peer and its chain, replaying only the
+/// blocks after the snapshot. Returns `false` if the peer has no snapshot or
+/// its chain does not check out, leaving the node as it was.
+fn bootstrap_from_peer(state: &NodeState, peer: u16) -> bool {
+    let command = ClientCommands::GetSnapshot {
+        genesis: state.genesis_hash,
+    };
+    let (snapshot, blocks) = match state.network.request(peer, &command) {
+        Ok(Ok(ServerResponse::Snapshot {
+            snapshot: Some(snapshot),
+            blocks,
+        })) => (snapshot, blocks),
+        Ok(Ok(ServerResponse::Snapshot { snapshot: None, .. })) => return false,
+        Ok(Ok(response)) => {
+            warn!("Unexpected response from peer {}: {:?}", peer, response);
+            return false;
+        }
+        Ok(Err(e)) => {
+            warn!("Peer {} refused to share its snapshot: {}", peer, e);
+            return false;
+        }
+        Err(e) => {
+            warn!("Could not bootstrap from peer {}: {}", peer, e);
+            return false;
+        }
+    };
+
+    let mut tree = state.blocks.write().unwrap();
+    let genesis = tree.chain()[0].clone();
+    let adopt = || {
+        let chain = import_chain(
+            std::iter::once(genesis.clone()).chain(blocks).map(Ok),
+            Some(&snapshot),
+            &state.rules(),
+        )?;
+        let mut adopted = BlockTree::new(genesis, state.finality);
+        for block in chain.into_iter().skip(1) {
+            let block_id = block.id;
+            adopted
+                .insert(block)
+                .map_err(|e| format!("block {}: {:?}", block_id, e))?;
+        }
+        adopted.set_snapshot(snapshot.clone())?;
+        Ok::<_, String>(adopted)
+    };
+    match adopt() {
+        Ok(adopted) => *tree = adopted,
+        Err(e) => {
+            warn!("Refusing the snapshot of peer {}: {}", peer, e);
+            return false;
+        }
+    }
+    info!(
+        "Bootstrapped {} block(s) from the snapshot of block {} of peer {}",
+        tree.head().id,
+        snapshot.block_id,
+        peer
+    );
+    if let Some(data_dir) = &state.data_dir {
+        if let Err(e) = snapshot.save(&data_dir.join(SNAPSHOT_FILE)) {
+            error!("{}", e);
+        }
+    }
+    store_chain(state, tree.chain(), None);
+    true
+}
+
+/// Validates a transfer against the canonical chain plus the mempool and
+/// queues it, together with its fee, for the next block.
+fn submit_transfer(
+    state: &NodeState,
+    transaction: Transaction,
+) -> Result<TransactionId, CommandError> {
+    let (from_account, amount, asset) = (
+        transaction.from(),
+        transaction.amount(),
+        transaction.asset(),
+    );
+    validate_transaction(&transaction)?;
+    if let Some(max) = state.parameters.max_transfer_amount {
+        if amount > max {
+            return Err(
+                format!("Transfer of {} exceeds the chain limit of {}", amount, max).into(),
+            );
+        }
+    }
+
+    let fee = state.parameters.transfer_fee;
+    let mut transactions = state.transactions.lock().unwrap();
+    let blocks = state.blocks.read().unwrap();
+
+    pending_status(from_account, blocks.chain(), &transactions).check_can_send(from_account)?;
+    pending_status(transaction.to(), blocks.chain(), &transactions)
+        .check_can_receive(transaction.to())?;
+    // Calls are checked against the contract instead
+    let recipient = match transaction.kind() {
+        TransactionKind::EscrowOpen(terms) => Some(terms.payee),
+        TransactionKind::Call { .. } => None,
+        _ => Some(transaction.to()),
+    };
+    if let Some(recipient) = recipient {
+        check_recipient(recipient, &blocks, &transactions, state)?;
+    }
+    match transaction.kind() {
+        TransactionKind::RegisterName { name } => {
+            validate_name(name)?;
+            if pending_names(blocks.chain(), &transactions).contains_key(name) {
+                return Err(format!("Name {} is already registered", name).into());
+            }
+        }
+        TransactionKind::TransferName { name }
+            if pending_names(blocks.chain(), &transactions).get(name) != Some(&from_account) =>
+        {
+            return Err(
+                format!("Name {} is not owned by {}", name, to_address(from_account)).into(),
+            );
+        }
+        _ => {}
+    }
+
+    if asset != NATIVE_ASSET && pending_supply(&blocks, &transactions, asset).minted <= 0.0 {
+        return Err(format!("Unknown asset: {}", asset_symbol(asset)).into());
+    }
+
+    if !blocks.contains_account(from_account) && !transactions.contains_account(from_account) {
+        return Err(AccountError::SenderNotFound(from_account).into());
+    }
+
+    // The fee is always paid in the native asset
+    let spendable =
+        |asset| pending_spendable(from_account, asset, &blocks, &transactions).unwrap_or(0.0);
+    let is_covered = match asset == NATIVE_ASSET {
+        true => spendable(NATIVE_ASSET) - fee >= amount,
+        false => spendable(NATIVE_ASSET) >= fee && spendable(asset) >= amount,
+    };
+
+    match is_covered {
+        true => {
+            let transaction_id = transaction.id;
+            queue_transaction(state, &mut transactions, transaction);
+            if fee > 0.0 {
+                let fee = state
+                    .transaction(BURN_ID, from_account, fee, NATIVE_ASSET)
+                    .with_kind(TransactionKind::Fee {
+                        transaction: transaction_id,
+                    });
+                queue_transaction(state, &mut transactions, fee);
+            }
+            Ok(transaction_id)
+        }
+        false => Err(format!(
+            "Not enough spendable in account {} to transfer {} {} (fee {} {})",
+            to_address(from_account),
+            amount,
+            asset_symbol(asset),
+            fee,
+            asset_symbol(NATIVE_ASSET)
+        )
+        .into()),
+    }
+}
+
+/// Submits the scheduled transfers due for the next block. A transfer that
+/// cannot be made is skipped; the schedule keeps running.
+fn run_schedules(state: &NodeState) {
+    let height = state.blocks.read().unwrap().head().id + 1;
+    let due = state.scheduler.lock().unwrap().take_due(height);
+    for schedule in due {
+        let transaction =
+            state.transaction(schedule.to, schedule.from, schedule.amount, schedule.asset);
+        match submit_transfer(state, transaction) {
+            Ok(transaction_id) => info!(
+                "Schedule {} submitted transaction {} for block {}",
+                schedule.id, transaction_id, height
+            ),
+            Err(e) => warn!("Schedule {} skipped block {}: {}", schedule.id, height, e),
+        }
+    }
+}
+
+/// Escrow over the canonical chain plus the mempool.
+fn pending_escrow(
+    chain: &Blocks,
+    transactions: &Transactions,
+    escrow_id: TransactionId,
+) -> Option<Escrow> {
+    find_escrow(
+        chain
+            .iter()
+            .flat_map(|block| block.all_transactions())
+            .chain(transactions),
+        escrow_id,
+    )
+}
+
+/// Queues the transaction built by `settle` for an open escrow. Settlements
+/// are paid by `ESCROW_ID` and carry no fee.
+fn settle_escrow(
+    state: &NodeState,
+    escrow_id: TransactionId,
+    settle: impl FnOnce(&Escrow, BlockId) -> Result<Transaction, String>,
+) -> Result<TransactionId, CommandError> {
+    let mut transactions = state.transactions.lock().unwrap();
+    let blocks = state.blocks.read().unwrap();
+
+    let escrow = pending_escrow(blocks.chain(), &transactions, escrow_id)
+        .ok_or_else(|| CommandError::NotFound(format!("Escrow not found: {}", escrow_id)))?;
+    let transaction = settle(&escrow, blocks.head().id)?.with_nonce(state.random.next_u64());
+    pending_status(transaction.to(), blocks.chain(), &transactions)
+        .check_can_receive(transaction.to())?;
+    let transaction_id = transaction.id;
+    queue_transaction(state, &mut transactions, transaction);
+    Ok(transaction_id)
+}
+
+/// Verifies an admin command against the operator key and queues its
+/// transactions: a marker for freezes, one sweep per held asset for closes.
+/// Admin transactions carry no fee.
+fn submit_admin_action(
+    state: &NodeState,
+    account: AccountId,
+    action: AdminAction,
+    auth: &AdminAuth,
+) -> Result<TransactionId, CommandError> {
+    let admin_key = state
+        .rules
+        .admin_key
+        .ok_or("This node has no admin key; start it with --admin-key")?;
+    let now = state.clock.now_millis();
+    verify_admin(
+        &admin_key,
+        action,
+        account,
+        auth.timestamp,
+        &auth.signature,
+        now,
+    )?;
+
+    let mut transactions = state.transactions.lock().unwrap();
+    let blocks = state.blocks.read().unwrap();
+    let chain = blocks.chain();
+    if !blocks.contains_account(account) && !transactions.contains_account(account) {
+        return Err(CommandError::NotFound(format!(
+            "Account not found: {}",
+            to_address(account)
+        )));
+    }
+    let recorded = chain
+        .iter()
+        .flat_map(|block| &block.transactions)
+        .chain(transactions.iter());
+    if is_signature_used(recorded, &auth.signature) {
+        return Err("Admin signature was already used; sign the command again".into());
+    }
+
+    let kind = TransactionKind::Admin {
+        action,
+        timestamp: auth.timestamp,
+        signature: auth.signature.clone(),
+    };
+    let queued = match (action, pending_status(account, chain, &transactions)) {
+        (_, AccountStatus::Closed) => {
+            return Err(format!("Account {} is closed", to_address(account)).into())
+        }
+        (AdminAction::Freeze, AccountStatus::Frozen) => {
+            return Err(format!("Account {} is already frozen", to_address(account)).into())
+        }
+        (AdminAction::Unfreeze, AccountStatus::Active) => {
+            return Err(format!("Account {} is not frozen", to_address(account)).into())
+        }
+        (AdminAction::Close { sweep_to }, _) => {
+            if let Some(name) = reserved_account_name(sweep_to) {
+                return Err(format!("could not sweep to account id: {}", name).into());
+            }
+            if sweep_to == account {
+                return Err("An account cannot be swept to itself".into());
+            }
+            check_recipient(sweep_to, &blocks, &transactions, state)?;
+            pending_status(sweep_to, chain, &transactions).check_can_receive(sweep_to)?;
+
+            // Blocks the snapshot covers may have been pruned, so assets held
+            // since before it come from the snapshot
+            let snapshot = blocks.snapshot();
+            let unsnapshotted = snapshot.map_or(0, |snapshot| snapshot.block_id as usize + 1);
+            let held = held_assets(
+                snapshot,
+                chain[unsnapshotted..]
+                    .iter()
+                    .flat_map(|block| block.all_transactions())
+                    .chain(transactions.iter()),
+                account,
+            );
+            let mut sweeps = Vec::new();
+            for asset in held {
+                let balance =
+                    pending_balance(account, asset, &blocks, &transactions).unwrap_or(0.0);
+                let spendable =
+                    pending_spendable(account, asset, &blocks, &transactions).unwrap_or(0.0);
+                if spendable < balance {
+                    return Err(format!(
+                        "Account {} holds time-locked {}; close it once they unlock",
+                        to_address(account),
+                        asset_symbol(asset)
+                    )
+                    .into());
+                }
+                if balance > 0.0 {
+                    sweeps.push(
+                        state
+                            .transaction(sweep_to, account, balance, asset)
+                            .with_kind(kind.clone()),
+                    );
+                }
+            }
+            if sweeps.is_empty() {
+                sweeps.push(
+                    state
+                        .transaction(sweep_to, account, 0.0, NATIVE_ASSET)
+                        .with_kind(kind),
+                );
+            }
+            sweeps
+        }
+        _ => vec![state
+            .transaction(account, account, 0.0, NATIVE_ASSET)
+            .with_kind(kind)],
+    };
+
+    let transaction_id = queued.last().expect("admin actions queue a transaction").id;
+    for transaction in queued {
+        queue_transaction(state, &mut transactions, transaction);
+    }
+    Ok(transaction_id)
+}
+
+fn update_account(
+    state: &NodeState,
+    account: AccountId,
+    action: AdminAction,
+    auth: &AdminAuth,
+    confirmations: u64,
+    session: &mut dyn Session,
+) -> Result<ServerResponse, CommandError> {
+    let transaction_id = submit_admin_action(state, account, action, auth)?;
+    let (block_id, _, _) = wait_on_confirmations(transaction_id, confirmations, state, session)?;
+
+    Ok(ServerResponse::AccountUpdated {
+        account,
+        status: match action {
+            AdminAction::Freeze => AccountStatus::Frozen,
+            AdminAction::Unfreeze => AccountStatus::Active,
+            AdminAction::Close { .. } => AccountStatus::Closed,
+        },
+        block_id,
+        transaction_id,
+    })
+}
+
+/// Owners of registered names over the canonical chain plus the mempool.
+fn pending_names(chain: &Blocks, transactions: &Transactions) -> BTreeMap<String, AccountId> {
+    name_registry(
+        chain
+            .iter()
+            .flat_map(|block| block.all_transactions())
+            .chain(transactions),
+    )
+}
+
+/// Replaces every account name in a command by the account it is registered to.
+fn resolve_names(
+    mut command: ClientCommands,
+    state: &NodeState,
+) -> Result<ClientCommands, CommandError> {
+    let resolve = |account: &mut AccountRef| -> Result<(), CommandError> {
+        if let AccountRef::Name(name) = account {
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            let owner = pending_names(blocks.chain(), &transactions)
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| CommandError::NotFound(format!("Name not registered: {}", name)))?;
+            *account = AccountRef::Id(owner);
+        }
+        Ok(())
+    };
+
+    match &mut command {
+        ClientCommands::Transfer {
+            from_account,
+            to_account,
+            ..
+        }
+        | ClientCommands::ScheduleTransfer {
+            from_account,
+            to_account,
+            ..
+        }
+        | ClientCommands::ProposeTransfer {
+            from_account,
+            to_account,
+            ..
+        }
+        | ClientCommands::CallContract {
+            from_account,
+            contract: to_account,
+            ..
+        }
+        | ClientCommands::OpenEscrow {
+            payer: from_account,
+            payee: to_account,
+            ..
+        }
+        | ClientCommands::CloseAccount {
+            account: from_account,
+            sweep_to: to_account,
+            ..
+        } => {
+            resolve(from_account)?;
+            resolve(to_account)?;
+        }
+        ClientCommands::Balance { account, .. }
+        | ClientCommands::IssueAsset {
+            issuer: account, ..
+        }
+        | ClientCommands::FreezeAccount { account, .. }
+        | ClientCommands::UnfreezeAccount { account, .. }
+        | ClientCommands::RegisterName { account, .. }
+        | ClientCommands::TransferName {
+            to_account: account,
+            ..
+        }
+        | ClientCommands::DeployContract {
+            from_account: account,
+            ..
+        }
+        | ClientCommands::Approve {
+            to_account: account,
+            ..
+        }
+        | ClientCommands::ContractStorage { contract: account }
+        | ClientCommands::History { account, .. }
+        | ClientCommands::AccountInfo { account }
+        | ClientCommands::Subscribe {
+            account: Some(account),
+        } => resolve(account)?,
+        _ => {}
+    }
+    Ok(command)
+}
+
+/// Status of an account over the canonical chain plus the mempool.
+fn pending_status(
+    account: AccountId,
+    chain: &Blocks,
+    transactions: &Transactions,
+) -> AccountStatus {
+    account_status(
+        chain
+            .iter()
+            .flat_map(|block| block.all_transactions())
+            .chain(transactions),
+        account,
+    )
+}
+
+/// Supply of an asset over the canonical chain plus the mempool.
+fn pending_supply(blocks: &BlockTree, transactions: &Transactions, asset: AssetId) -> Supply {
+    let (sealed, pending) = (blocks.supply(asset), Supply::of(transactions, asset));
+    Supply {
+        minted: sealed.minted + pending.minted,
+        burned: sealed.burned + pending.burned,
+        circulating: sealed.circulating + pending.circulating,
+    }
+}
+
+/// Balance of an account in an asset over the canonical chain plus the
+/// mempool, `None` if the account holds none of it in either.
+fn pending_balance(
+    account: AccountId,
+    asset: AssetId,
+    blocks: &BlockTree,
+    transactions: &Transactions,
+) -> Option<f64> {
+    match (
+        transactions.calculate_total(account, asset),
+        blocks.balance(account, asset),
+    ) {
+        (Some(val1), Some(val2)) => Some(val1 + val2),
+        (Some(val), None) | (None, Some(val)) => Some(val),
+        (None, None) => None,
+    }
+}
+
+/// Balance of an account in an asset that is not time-locked at the current
+/// head, over the canonical chain plus the mempool.
+fn pending_spendable(
+    account: AccountId,
+    asset: AssetId,
+    blocks: &BlockTree,
+    transactions: &Transactions,
+) -> Option<f64> {
+    let head = blocks.head();
+    pending_balance(account, asset, blocks, transactions).map(|balance| {
+        balance
+            - blocks.chain().calculate_locked(account, asset, head)
+            - transactions.calculate_locked(account, asset, head)
+    })
+}
+
+/// Name of an account id that is reserved for the node's own bookkeeping.
+fn reserved_account_name(account: AccountId) -> Option<&'static str> {
+    match account {
+        MASTER_ID => Some("MASTER_ID"),
+        BURN_ID => Some("BURN_ID"),
+        ESCROW_ID => Some("ESCROW_ID"),
+        _ => None,
+    }
+}
+
+/// Transfers out of multisig accounts have to go through proposals.
+fn check_not_multisig(account: AccountId, state: &NodeState) -> Result<(), String> {
+    match state.multisig.lock().unwrap().policy(account) {
+        Some(policy) => Err(format!(
+            "Account {} needs {} approvals per transfer; use propose-transfer",
+            to_address(account),
+            policy.threshold
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Generates an account id that no transaction or multisig policy uses yet.
+fn new_account_id(
+    random: &dyn Random,
+    blocks: &BlockTree,
+    transactions: &Transactions,
+    multisig: &Multisig,
+) -> AccountId {
+    loop {
+        let account = generate_account_id(random);
+        if !blocks.contains_account(account)
+            && !transactions.contains_account(account)
+            && multisig.policy(account).is_none()
+        {
+            return account;
+        }
+    }
+}
+
+/// Whether an account was created, over the canonical chain plus the mempool.
+fn account_known(
+    account: AccountId,
+    blocks: &BlockTree,
+    transactions: &Transactions,
+    state: &NodeState,
+) -> bool {
+    blocks.contains_account(account)
+        || transactions.contains_account(account)
+        || state.multisig.lock().unwrap().policy(account).is_some()
+}
+
+/// Applies the node's recipient policy to an account about to receive funds.
+fn check_recipient(
+    account: AccountId,
+    blocks: &BlockTree,
+    transactions: &Transactions,
+    state: &NodeState,
+) -> Result<(), AccountError> {
+    match state.recipient_policy {
+        RecipientPolicy::Existing if !account_known(account, blocks, transactions, state) => {
+            Err(AccountError::RecipientNotFound(account))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_account_exists(account: AccountId, state: &NodeState) -> bool {
+    let in_blocks = state.blocks.read().unwrap().contains_account(account);
+    in_blocks || state.transactions.lock().unwrap().contains_account(account)
+}
+
+/// Blocks until the transaction is part of the canonical chain with at least
+/// `confirmations` confirmations, telling `session` about every
+/// reorganization observed while waiting. Returns the block id, confirmation count and
+/// whether the block is final.
+fn wait_on_confirmations(
     transaction_id: TransactionId,
-    shared_blocks: &Arc<RwLock<Blocks>>,
-    shared_condvar: &Arc<(Mutex<BlockId>, Condvar)>,
-) -> BlockId {
-    let mut block_id: BlockId = 0;
-    let mut block_contains_transaction = false;
-    while !block_contains_transaction {
-        let (lock, cvar) = shared_condvar.as_ref();
-        let cond_block_id = lock.lock().unwrap();
-        let cond_block_id = cvar.wait(cond_block_id).unwrap();
-        block_id = *cond_block_id;
-        block_contains_transaction = shared_blocks
-            .read()
-            .unwrap()
-            .contains_transaction(block_id, transaction_id);
+    confirmations: u64,
+    state: &NodeState,
+    session: &mut dyn Session,
+) -> Result<(BlockId, u64, bool), String> {
+    let mut seen = state.chain_events.0.lock().unwrap().clone();
+    loop {
+        {
+            let transactions = state.transactions.lock().unwrap();
+            let blocks = state.blocks.read().unwrap();
+            match blocks
+                .chain()
+                .find_transaction(transaction_id)
+                .or_else(|| blocks.find_pruned_transaction(transaction_id))
+            {
+                Some(block_id) if blocks.confirmations(block_id) >= confirmations => {
+                    return Ok((
+                        block_id,
+                        blocks.confirmations(block_id),
+                        blocks.is_final(block_id),
+                    ));
+                }
+                Some(_) => {}
+                None if transactions.iter().any(|t| t.id == transaction_id) => {}
+                None => {
+                    return Err(format!(
+                        "Transaction {} was dropped after a chain reorganization",
+                        transaction_id
+                    ))
+                }
+            }
+        }
+
+        let event = session.wait_for_chain(state, &seen);
+        if event.reorgs != seen.reorgs {
+            if let Some(notice) = &event.last_reorg {
+                session.notify_reorg(notice);
+            }
+        }
+        seen = event;
     }
-    block_id
 }
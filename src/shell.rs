@@ -0,0 +1,344 @@
+use crate::client::{prepare_command, print_reorg, print_response, Client, ClientCommands};
+use crate::core::address::to_address;
+use crate::server::ServerResponse;
+use clap::{CommandFactory, Parser};
+use log::{error, info, warn};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::PathBuf;
+
+/// Commands of the shell besides the client commands.
+const BUILTINS: [&str; 5] = ["help", "set", "vars", "exit", "quit"];
+
+/// Client commands the shell refuses, as they take over the connection.
+const REFUSED: [&str; 1] = ["watch"];
+
+/// A line typed into the shell, parsed like the arguments of `b`.
+#[derive(Parser)]
+#[clap(no_binary_name = true, disable_help_flag = true)]
+pub(crate) struct ShellLine {
+    #[clap(subcommand)]
+    pub(crate) command: ClientCommands,
+}
+
+/// What the shell knows besides its connection: session variables and the
+/// accounts seen so far, which tab completion offers.
+#[derive(Default)]
+pub(crate) struct ShellState {
+    variables: BTreeMap<String, String>,
+    accounts: BTreeSet<String>,
+}
+
+impl ShellState {
+    /// Replaces every word of the form `$name` with the value of the variable.
+    pub(crate) fn expand(&self, line: &str) -> Result<Vec<String>, String> {
+        line.split_whitespace()
+            .map(|word| self.substitute(word))
+            .collect()
+    }
+
+    /// Value of the variable if `word` is `$name`, otherwise the word itself.
+    pub(crate) fn substitute(&self, word: &str) -> Result<String, String> {
+        match word.strip_prefix('$') {
+            Some(name) => self
+                .variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown variable ${}", name)),
+            None => Ok(word.to_string()),
+        }
+    }
+
+    /// Keeps the ids a response names: `last_tx`, `last_block` and
+    /// `last_account` are set from it and its accounts become known.
+    pub(crate) fn remember(&mut self, response: &ServerResponse) {
+        let (account, block_id, transaction_id) = match response {
+            ServerResponse::Transferred {
+                block_id,
+                transaction_id,
+                ..
+            }
+            | ServerResponse::ContractCalled {
+                block_id,
+                transaction_id,
+                ..
+            } => (None, Some(*block_id), Some(*transaction_id)),
+            ServerResponse::AccountCreated {
+                account,
+                block_id,
+                transaction_id,
+                ..
+            } => (Some(*account), Some(*block_id), Some(*transaction_id)),
+            ServerResponse::ContractDeployed { contract, block_id } => {
+                (Some(*contract), Some(*block_id), None)
+            }
+            ServerResponse::MultisigCreated { account, .. } => (Some(*account), None, None),
+            ServerResponse::Transaction {
+                block_id,
+                transaction,
+            } => {
+                self.accounts.insert(to_address(transaction.from()));
+                self.accounts.insert(to_address(transaction.to()));
+                (None, *block_id, Some(transaction.id))
+            }
+            ServerResponse::Resolved { account, .. }
+            | ServerResponse::AccountInfo { account, .. }
+            | ServerResponse::AccountUpdated { account, .. } => {
+                self.accounts.insert(to_address(*account));
+                (None, None, None)
+            }
+            ServerResponse::History { transactions, .. } => {
+                for (_, transaction) in transactions {
+                    self.accounts.insert(to_address(transaction.from()));
+                    self.accounts.insert(to_address(transaction.to()));
+                }
+                (None, None, None)
+            }
+            _ => (None, None, None),
+        };
+        if let Some(account) = account {
+            self.accounts.insert(to_address(account));
+            self.set("last_account", to_address(account));
+        }
+        if let Some(block_id) = block_id {
+            self.set("last_block", block_id.to_string());
+        }
+        if let Some(transaction_id) = transaction_id {
+            self.set("last_tx", transaction_id.to_string());
+        }
+    }
+
+    pub(crate) fn set(&mut self, name: &str, value: String) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Words that may complete `word`: commands at the start of the line,
+    /// known accounts and variables after it.
+    fn candidates(&self, word: &str, first: bool) -> Vec<String> {
+        let words: Vec<String> = match first {
+            true => ShellLine::command()
+                .get_subcommands()
+                .map(|command| command.get_name().to_string())
+                .filter(|name| !REFUSED.contains(&name.as_str()))
+                .chain(BUILTINS.iter().map(|builtin| builtin.to_string()))
+                .collect(),
+            false => self
+                .accounts
+                .iter()
+                .cloned()
+                .chain(self.variables.keys().map(|name| format!("${}", name)))
+                .collect(),
+        };
+        words
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect()
+    }
+}
+
+impl Completer for ShellState {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |space| space + 1);
+        Ok((start, self.candidates(&line[start..pos], start == 0)))
+    }
+}
+
+impl Hinter for ShellState {
+    type Hint = String;
+}
+
+impl Highlighter for ShellState {}
+
+impl Validator for ShellState {}
+
+impl Helper for ShellState {}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".b_history"))
+}
+
+/// Reads commands from the terminal and sends them to the node on `port`
+/// over one connection, reconnecting when it is lost.
+pub fn run_shell(port: u16) {
+    let mut editor: Editor<ShellState, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            error!("could not start the shell: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(ShellState::default()));
+    if let Some(path) = history_path() {
+        let _ = editor.load_history(&path);
+    }
+
+    let mut client = None;
+    info!(
+        "b shell for the node on port {}; type help for commands",
+        port
+    );
+    loop {
+        let line = match editor.readline("b> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                error!("{}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+        let state = editor.helper_mut().expect("the shell sets its helper");
+        if !run_line(&line, port, &mut client, state) {
+            break;
+        }
+    }
+
+    if let Some(path) = history_path() {
+        if let Err(e) = editor.save_history(&path) {
+            warn!("could not save the shell history: {}", e);
+        }
+    }
+}
+
+/// Runs one line of input; returns `false` once the shell should exit.
+fn run_line(line: &str, port: u16, client: &mut Option<Client>, state: &mut ShellState) -> bool {
+    let words = match state.expand(line) {
+        Ok(words) => words,
+        Err(e) => {
+            error!("{}", e);
+            return true;
+        }
+    };
+    match words
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["exit" | "quit"] => return false,
+        ["help"] => {
+            info!("{}", ShellLine::command().render_help());
+            info!("Shell commands:\n  set <name> <value>  Sets a variable; $name is replaced by its value\n  vars                Lists the variables (last_tx, last_block and last_account are set for you)\n  exit                Leaves the shell");
+        }
+        ["vars"] => {
+            for (name, value) in &state.variables {
+                info!("${} = {}", name, value);
+            }
+        }
+        ["set", name, value] => state.set(name, value.to_string()),
+        ["set", ..] => error!("usage: set <name> <value>"),
+        _ => match ShellLine::try_parse_from(&words) {
+            Ok(ShellLine {
+                command: ClientCommands::Subscribe { .. },
+            }) => error!("watch takes over the connection; run `b watch` in another terminal"),
+            Ok(ShellLine { command }) => send(command, port, client, state),
+            Err(e) => info!("{}", e.render()),
+        },
+    }
+    true
+}
+
+fn send(command: ClientCommands, port: u16, client: &mut Option<Client>, state: &mut ShellState) {
+    let connection = match client {
+        Some(connection) => connection,
+        None => match Client::connect(port) {
+            Ok(connection) => client.insert(connection),
+            Err(e) => return error!("{}", e),
+        },
+    };
+    let command = match prepare_command(command, connection) {
+        Ok(command) => command,
+        Err(e) => return error!("{}", e),
+    };
+    match connection.exchange(&command, print_reorg) {
+        Ok(Ok(response)) => {
+            state.remember(&response);
+            print_response(response, &command);
+        }
+        Ok(Err(e)) => error!("recieved error: {}", e),
+        Err(e) => {
+            error!("lost the connection to the node: {}", e);
+            *client = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variables() {
+        let mut state = ShellState::default();
+        state.remember(&ServerResponse::AccountCreated {
+            account: 12345,
+            block_id: 2,
+            transaction_id: 99,
+            confirmations: 1,
+            finality: 6,
+            is_final: false,
+        });
+        state.set("bob", "67890".to_string());
+
+        assert_eq!(
+            state.expand("transfer $last_account $bob 5").unwrap(),
+            vec!["transfer", &to_address(12345), "67890", "5"]
+        );
+        assert_eq!(
+            state.expand("get-tx $last_tx").unwrap(),
+            vec!["get-tx", "99"]
+        );
+        assert!(state.expand("balance $nobody").is_err());
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut state = ShellState::default();
+        assert!(state
+            .candidates("tra", true)
+            .contains(&"transfer".to_string()));
+        assert!(state.candidates("wa", true).is_empty());
+        assert!(state.candidates("va", true).contains(&"vars".to_string()));
+
+        state.remember(&ServerResponse::MultisigCreated {
+            account: 12345,
+            threshold: 2,
+        });
+        let address = to_address(12345);
+        assert_eq!(state.candidates(&address[..3], false), vec![address]);
+        assert_eq!(state.candidates("$last_a", false), vec!["$last_account"]);
+    }
+
+    #[test]
+    fn test_lines_parse_as_commands() {
+        let parse = |line: &str| ShellLine::try_parse_from(line.split_whitespace());
+        assert!(matches!(
+            parse("create-account 100"),
+            Ok(ShellLine {
+                command: ClientCommands::CreateAccount { .. }
+            })
+        ));
+        assert!(parse("transfer 1 2 -5").is_err());
+        assert!(parse("no-such-command").is_err());
+    }
+}
@@ -0,0 +1,510 @@
+use crate::client::ClientCommands;
+use crate::core::block_tree::BlockTree;
+use crate::core::constants::NATIVE_ASSET;
+use crate::core::env::{ManualClock, Random, SeededRandom};
+use crate::core::genesis::Genesis;
+use crate::core::monetary::check_supply_invariant;
+use crate::core::names::AccountRef;
+use crate::core::types::{AccountId, Block, BlockId};
+use crate::core::utilities::to_hex;
+use crate::server::{
+    handle_command, seal_block, Answer, ChainEvent, Consensus, Network, NodeOptions, NodeState,
+    ReorgNotice, ServerResponse, Session,
+};
+use clap::Parser;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Port of the first simulated node; nodes are never bound to it.
+const FIRST_PORT: u16 = 20000;
+
+/// Start of simulated time, so that runs do not depend on when they happen.
+pub(crate) const EPOCH: Duration = Duration::from_secs(1_700_000_000);
+
+#[derive(Debug, Clone, Parser)]
+pub struct SimulationOptions {
+    /// Seed every choice of the run is drawn from; the same seed replays the same run
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Number of steps (client commands or block ticks) to run
+    #[clap(long, default_value_t = 500)]
+    pub steps: u64,
+
+    /// Number of nodes, all peers of each other
+    #[clap(long, default_value_t = 3)]
+    pub nodes: u16,
+
+    /// Number of clients sending commands, each to its own node
+    #[clap(long, default_value_t = 4)]
+    pub clients: u8,
+
+    /// Chance in percent that a message between nodes is lost
+    #[clap(long, default_value_t = 10)]
+    pub drop_rate: u64,
+
+    #[clap(flatten)]
+    pub node: NodeOptions,
+}
+
+/// Outcome of a run that kept every invariant.
+#[derive(Debug)]
+pub struct Report {
+    pub seed: u64,
+    pub head_block_id: u64,
+    pub accounts: usize,
+    pub commands: usize,
+    pub refused: usize,
+    pub delivered: usize,
+    pub dropped: usize,
+    /// Nodes left on another chain because their fork is final
+    pub splits: usize,
+    /// Digest of the trace of every step; equal for runs of the same seed
+    pub digest: String,
+}
+
+struct Message {
+    peer: u16,
+    command: ClientCommands,
+    on_answer: Box<dyn FnOnce(Answer) + Send>,
+}
+
+/// Network that holds messages until the simulation delivers (or drops) them.
+struct SimNetwork {
+    options: NodeOptions,
+    clock: Arc<ManualClock>,
+    block_interval: Duration,
+    nodes: Mutex<BTreeMap<u16, NodeState>>,
+    messages: Mutex<Vec<Message>>,
+}
+
+impl SimNetwork {
+    fn node(&self, peer: u16) -> Result<NodeState, String> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .cloned()
+            .ok_or_else(|| format!("no simulated node on port {}", peer))
+    }
+
+    /// Seals a block on `node`. Mining takes no simulated time by itself, so
+    /// the clock moves on by a block interval first; otherwise retargeting
+    /// would raise the difficulty until mining never ends.
+    fn seal(&self, node: &NodeState) -> Result<Block, String> {
+        if self.options.consensus == Consensus::Pow {
+            self.clock.advance(self.block_interval);
+        }
+        seal_block(node, &self.options)
+    }
+}
+
+impl Network for SimNetwork {
+    fn request(&self, peer: u16, command: &ClientCommands) -> Answer {
+        let node = self.node(peer)?;
+        let mut session = SimSession { network: self };
+        Ok(handle_command(&node, command.clone(), &mut session).map_err(|e| e.to_string()))
+    }
+
+    fn send(&self, peer: u16, command: ClientCommands, on_answer: Box<dyn FnOnce(Answer) + Send>) {
+        self.messages.lock().unwrap().push(Message {
+            peer,
+            command,
+            on_answer,
+        });
+    }
+}
+
+/// Session of a simulated client: instead of waiting for the block
+/// processor, waiting for confirmations seals a block on the spot.
+struct SimSession<'a> {
+    network: &'a SimNetwork,
+}
+
+impl Session for SimSession<'_> {
+    fn notify_reorg(&mut self, notice: &ReorgNotice) {
+        debug!("client notified of {:?}", notice);
+    }
+
+    fn wait_for_chain(&mut self, state: &NodeState, _seen: &ChainEvent) -> ChainEvent {
+        if let Err(e) = self.network.seal(state) {
+            debug!("{}", e);
+        }
+        state.chain_event()
+    }
+}
+
+struct Simulation {
+    options: SimulationOptions,
+    random: SeededRandom,
+    clock: Arc<ManualClock>,
+    network: Arc<SimNetwork>,
+    nodes: Vec<NodeState>,
+    /// Accounts created by each client
+    accounts: Vec<Vec<AccountId>>,
+    trace: Sha256,
+    commands: usize,
+    refused: usize,
+    delivered: usize,
+    dropped: usize,
+    splits: usize,
+}
+
+impl Drop for Simulation {
+    /// Nodes and pending messages refer back to the network.
+    fn drop(&mut self) {
+        self.network.nodes.lock().unwrap().clear();
+        self.network.messages.lock().unwrap().clear();
+    }
+}
+
+/// Runs a seeded simulation of `options.nodes` nodes in-process, checking the
+/// supply invariant and balances after every step and that the nodes agree
+/// on the chain at the end. Errors name the seed and step that failed.
+pub fn run_simulation(interval: u64, options: SimulationOptions) -> Result<Report, String> {
+    let seed = options.seed;
+    let mut simulation = Simulation::new(interval, options)?;
+    simulation
+        .run()
+        .map_err(|e| format!("seed {}: {}", seed, e))?;
+    Ok(simulation.report())
+}
+
+impl Simulation {
+    fn new(interval: u64, options: SimulationOptions) -> Result<Simulation, String> {
+        if options.node.data_dir.is_some() {
+            return Err("Simulated nodes keep their chain in memory; drop --data-dir".to_string());
+        }
+        if options.nodes == 0 || options.clients == 0 {
+            return Err("A simulation needs at least one node and one client".to_string());
+        }
+
+        let random = SeededRandom::new(options.seed);
+        let clock = Arc::new(ManualClock::new(EPOCH));
+        let network = Arc::new(SimNetwork {
+            options: options.node.clone(),
+            clock: clock.clone(),
+            block_interval: Duration::from_secs(interval),
+            nodes: Mutex::new(BTreeMap::new()),
+            messages: Mutex::new(Vec::new()),
+        });
+
+        let genesis = Genesis::local(interval);
+        let ports: Vec<u16> = (0..options.nodes).map(|i| FIRST_PORT + i).collect();
+        let nodes: Vec<NodeState> = ports
+            .iter()
+            .map(|&port| {
+                let mut node_options = options.node.clone();
+                node_options.peers = ports.iter().copied().filter(|p| *p != port).collect();
+                NodeState::new(
+                    port,
+                    &genesis,
+                    BlockTree::new(genesis.block(), node_options.finality),
+                    &node_options,
+                    clock.clone(),
+                    Arc::new(SeededRandom::new(random.next_u64())),
+                    network.clone(),
+                )
+            })
+            .collect();
+        network
+            .nodes
+            .lock()
+            .unwrap()
+            .extend(ports.iter().copied().zip(nodes.iter().cloned()));
+
+        Ok(Simulation {
+            accounts: vec![Vec::new(); options.clients as usize],
+            options,
+            random,
+            clock,
+            network,
+            nodes,
+            trace: Sha256::new(),
+            commands: 0,
+            refused: 0,
+            delivered: 0,
+            dropped: 0,
+            splits: 0,
+        })
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        for step in 0..self.options.steps {
+            self.clock
+                .advance(Duration::from_millis(1 + self.random.below(500)));
+            match self.random.below(100) {
+                0..=14 => self.create_account(),
+                15..=59 => self.transfer(),
+                60..=74 => self.query_balance(),
+                _ => self.tick(),
+            }
+            // Each message in flight arrives with even odds, so most take a step or two
+            let in_flight = self.network.messages.lock().unwrap().len();
+            for _ in 0..in_flight {
+                if self.random.below(2) == 0 {
+                    self.deliver(true);
+                }
+            }
+            self.check().map_err(|e| format!("step {}: {}", step, e))?;
+        }
+
+        // Let every node see every block, then outgrow any tie between forks
+        while self.deliver(false) {}
+        self.tick_node(0);
+        while self.deliver(false) {}
+        self.check()
+            .and_then(|()| self.check_agreement())
+            .map(|splits| self.splits = splits)
+            .map_err(|e| format!("after the last step: {}", e))
+    }
+
+    fn record(&mut self, line: String) {
+        debug!("{}", line);
+        self.trace.update(line.as_bytes());
+        self.trace.update(b"\n");
+    }
+
+    fn pick<T: Copy>(&self, items: &[T]) -> Option<T> {
+        match items.is_empty() {
+            true => None,
+            false => Some(items[self.random.below(items.len() as u64) as usize]),
+        }
+    }
+
+    /// Sends a command from a client to its node, as if over the wire.
+    fn command(
+        &mut self,
+        client: usize,
+        command: ClientCommands,
+    ) -> Result<ServerResponse, String> {
+        let node = &self.nodes[client % self.nodes.len()];
+        let mut session = SimSession {
+            network: &self.network,
+        };
+        let line = format!("client {} to node {}: {:?}", client, node.port(), command);
+        let response = handle_command(node, command, &mut session).map_err(|e| e.to_string());
+        self.commands += 1;
+        if response.is_err() {
+            self.refused += 1;
+        }
+        self.record(format!("{} -> {:?}", line, response));
+        response
+    }
+
+    fn create_account(&mut self) {
+        let client = self.random.below(self.accounts.len() as u64) as usize;
+        let command = ClientCommands::CreateAccount {
+            starting_balance: self.random.below(1000) as f64,
+            confirmations: 1,
+        };
+        if let Ok(ServerResponse::AccountCreated { account, .. }) = self.command(client, command) {
+            self.accounts[client].push(account);
+        }
+    }
+
+    fn transfer(&mut self) {
+        let client = self.random.below(self.accounts.len() as u64) as usize;
+        let everyone: Vec<AccountId> = self.accounts.iter().flatten().copied().collect();
+        let (Some(from), Some(to)) = (self.pick(&self.accounts[client]), self.pick(&everyone))
+        else {
+            return;
+        };
+        let command = ClientCommands::Transfer {
+            from_account: AccountRef::Id(from),
+            to_account: AccountRef::Id(to),
+            amount: 1.0 + self.random.below(300) as f64,
+            asset: NATIVE_ASSET,
+            unlock_height: None,
+            unlock_time: None,
+            confirmations: 1,
+        };
+        let _ = self.command(client, command);
+    }
+
+    fn query_balance(&mut self) {
+        let client = self.random.below(self.accounts.len() as u64) as usize;
+        let everyone: Vec<AccountId> = self.accounts.iter().flatten().copied().collect();
+        if let Some(account) = self.pick(&everyone) {
+            let command = ClientCommands::Balance {
+                account: AccountRef::Id(account),
+                asset: NATIVE_ASSET,
+            };
+            let _ = self.command(client, command);
+        }
+    }
+
+    fn tick(&mut self) {
+        let node = self.random.below(self.nodes.len() as u64) as usize;
+        self.tick_node(node);
+    }
+
+    fn tick_node(&mut self, node: usize) {
+        let sealed = self.network.seal(&self.nodes[node]);
+        let line = format!(
+            "node {} sealed {:?}",
+            self.nodes[node].port(),
+            sealed.map(|block| (block.id, to_hex(&block.hash())))
+        );
+        self.record(line);
+    }
+
+    /// Delivers a pending message between nodes, picked at random so that
+    /// messages overtake each other, and possibly drops it. Returns `false`
+    /// when there was nothing to deliver.
+    fn deliver(&mut self, may_drop: bool) -> bool {
+        let message = {
+            let mut messages = self.network.messages.lock().unwrap();
+            if messages.is_empty() {
+                return false;
+            }
+            let index = self.random.below(messages.len() as u64) as usize;
+            messages.remove(index)
+        };
+
+        let line = format!("message to node {}: {:?}", message.peer, message.command);
+        if may_drop && self.random.below(100) < self.options.drop_rate {
+            self.dropped += 1;
+            self.record(format!("{} -> dropped", line));
+            (message.on_answer)(Err("dropped by the simulation".to_string()));
+            return true;
+        }
+
+        self.delivered += 1;
+        let answer = self.network.request(message.peer, &message.command);
+        self.record(format!("{} -> {:?}", line, answer));
+        (message.on_answer)(answer);
+        true
+    }
+
+    /// Supply and balances of every node must add up after every step: the
+    /// chain must replay without overdrafts or unbacked supply, and every
+    /// balance the node reports must match that replay.
+    fn check(&self) -> Result<(), String> {
+        for node in &self.nodes {
+            let blocks = node.blocks();
+            check_supply_invariant(blocks.snapshot(), blocks.chain(), NATIVE_ASSET)
+                .map_err(|e| format!("node {}: {}", node.port(), e))?;
+            let replay = blocks.balances_at(blocks.chain());
+            for (&account, &balance) in replay.balances.get(&NATIVE_ASSET).into_iter().flatten() {
+                let reported = blocks.balance(account, NATIVE_ASSET).unwrap_or(0.0);
+                if (reported - balance).abs() > 1e-6 {
+                    return Err(format!(
+                        "node {}: account {} reports a balance of {} but its blocks add up to {}",
+                        node.port(),
+                        account,
+                        reported,
+                        balance
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Nodes must end on the chain of the node that sealed last, unless
+    /// their chains fork below a block that one of the two treats as final.
+    /// Returns the number of nodes split off that way.
+    fn check_agreement(&self) -> Result<usize, String> {
+        let reference = self.nodes[0].blocks();
+        let mut splits = 0;
+        for node in &self.nodes[1..] {
+            let blocks = node.blocks();
+            let fork_height = blocks
+                .chain()
+                .iter()
+                .zip(reference.chain())
+                .position(|(block, other)| block.hash() != other.hash())
+                .unwrap_or(blocks.chain().len().min(reference.chain().len()))
+                as BlockId;
+            if blocks.head().hash() == reference.head().hash() {
+                continue;
+            }
+            match blocks.is_final(fork_height) || reference.is_final(fork_height) {
+                true => splits += 1,
+                false => {
+                    return Err(format!(
+                        "node {} ends at block {} but node {} at block {}, forking at block {} which neither treats as final",
+                        node.port(),
+                        blocks.head().id,
+                        self.nodes[0].port(),
+                        reference.head().id,
+                        fork_height
+                    ))
+                }
+            }
+        }
+        Ok(splits)
+    }
+
+    fn report(&self) -> Report {
+        Report {
+            seed: self.options.seed,
+            head_block_id: self.nodes[0].blocks().head().id,
+            accounts: self.accounts.iter().flatten().count(),
+            commands: self.commands,
+            refused: self.refused,
+            delivered: self.delivered,
+            dropped: self.dropped,
+            splits: self.splits,
+            digest: to_hex(&self.trace.clone().finalize()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::to_address;
+
+    fn options(args: &[&str]) -> SimulationOptions {
+        SimulationOptions::parse_from(["simulate"].iter().chain(args))
+    }
+
+    #[test]
+    fn test_seeds_keep_invariants() {
+        for seed in 0..10 {
+            let seed = seed.to_string();
+            if let Err(e) = run_simulation(10, options(&["--seed", &seed, "--steps", "200"])) {
+                panic!("{}; replay it with `b simulate --seed {}`", e, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_seed_replays_exactly() {
+        let run = |seed: &str| {
+            run_simulation(10, options(&["--seed", seed, "--steps", "150"]))
+                .unwrap()
+                .digest
+        };
+        assert_eq!(run("42"), run("42"));
+        assert_ne!(run("42"), run("43"));
+    }
+
+    #[test]
+    fn test_finality_and_pow() {
+        let report = run_simulation(
+            1,
+            options(&[
+                "--seed",
+                "7",
+                "--steps",
+                "100",
+                "--finality",
+                "2",
+                "--consensus",
+                "pow",
+                "--difficulty",
+                "4",
+                "--miner",
+                &to_address(1),
+            ]),
+        )
+        .unwrap();
+        assert!(report.head_block_id > 0);
+    }
+}